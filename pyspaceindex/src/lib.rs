@@ -1,58 +1,210 @@
-use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use parking_lot::RwLock;
+use pyo3::exceptions::{PyKeyError, PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyList, PySet, PyTuple};
+use pyo3::types::{PyDict, PyInt, PyList, PySet, PyTuple};
+
+use spaceindex::rtree::metric::Euclidean;
+use spaceindex::rtree::{ItemId, RTreeMap};
 
-use spaceindex::rtree::{Index, RTree as Tree};
+type Map = RTreeMap<u64, PyObject, f64>;
 
+/// Looks up the ids of the items in a map matching a shape.
+type Lookup<S> = fn(&Map, S) -> Vec<&u64>;
+
+/// An r-tree which can be shared between Python threads.  Queries run concurrently with each
+/// other, while changes wait for running queries to finish and block new ones until they're
+/// done.
 #[pyclass]
 struct RTree {
-    tree: Tree<PyObject, f64>,
+    /// Every item in the tree, keyed by the id returned when it was inserted.
+    ///
+    /// The lock is only ever held either without the GIL, or with the GIL but without running
+    /// any Python code.  So a thread holding the lock never waits for the GIL, and threads
+    /// can't deadlock between the two.
+    map: RwLock<Map>,
+
+    /// The id given to the next item inserted, which is only changed under the write lock.
+    next_id: AtomicU64,
 }
 
 impl RTree {
-    pub fn _query<S, IT: IntoIterator<Item = Index>>(
+    pub fn _query<S: Send>(
         &self,
         py: Python,
         shape: S,
-        lookup: impl Fn(S) -> IT,
+        lookup: Lookup<S>,
         hit_test: Option<PyObject>,
     ) -> PyResult<Vec<PyObject>> {
+        // Walk the tree without the GIL, so that other Python threads can run in the meantime
+        let found = py.allow_threads(|| {
+            let map = self.map.read();
+            lookup(&map, shape).into_iter().copied().collect::<Vec<_>>()
+        });
+
         let mut hits = Vec::new();
+        for item in self._items(py, found) {
+            if Self::_hit_test(py, &item, &hit_test)? {
+                hits.push(item);
+            }
+        }
 
-        // Iterate over all points in our tree containing the point `(x, y)`.
-        for hit in lookup(shape) {
-            // for hit in self.tree.point_lookup((x, y)) {
-            // Retrieve a ref to the item in the tree
-            let item = self.tree.get_node(hit).get_data().ok_or_else(|| {
-                PyErr::new::<PyRuntimeError, _>(format!(
-                    "failed to retrieve item with index {:?}",
-                    hit
-                ))
-            })?;
-
-            // whether this item should be included in the result
-            let include_in_result: bool = match &hit_test {
-                Some(hit_test) => hit_test.call1(py, (item,))?.extract(py)?,
-                None => true,
-            };
+        Ok(hits)
+    }
+
+    /// Returns the items with each of `ids`, along with `extra` for each.  Items removed since
+    /// their ids were found are skipped.
+    fn _items_with<E>(&self, py: Python, ids: Vec<(u64, E)>) -> Vec<(PyObject, E)> {
+        // Clone our internally held references (increasing their reference counts) under the
+        // lock, but run any Python code, such as `hit_test`, only once it is released
+        let map = self.map.read();
+
+        ids.into_iter()
+            .filter_map(|(id, extra)| Some((map.get(&id)?.1.clone_ref(py), extra)))
+            .collect()
+    }
+
+    fn _items(&self, py: Python, ids: Vec<u64>) -> Vec<PyObject> {
+        let ids = ids.into_iter().map(|id| (id, ())).collect();
+
+        self._items_with(py, ids)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
 
-            if include_in_result {
-                // Clone our internally held reference (increases the reference count)
-                hits.push(item.clone_ref(py));
+    /// Whether `item` should be included in the result of a query.
+    fn _hit_test(py: Python, item: &PyObject, hit_test: &Option<PyObject>) -> PyResult<bool> {
+        match hit_test {
+            Some(hit_test) => hit_test.call1(py, (item,))?.extract(py),
+            None => Ok(true),
+        }
+    }
+
+    /// Converts the neighbors found in the tree of `map` to the ids of their items.
+    fn _keys(map: &Map, neighbors: Vec<(ItemId, f64)>) -> Vec<(u64, f64)> {
+        neighbors
+            .into_iter()
+            .filter_map(|(hit, distance)| Some((map.tree().get(hit)?.1 .0, distance)))
+            .collect()
+    }
+
+    /// Appends the items with the ids in `neighbors`, which are ordered by increasing
+    /// distance, to `hits`, as `(item, distance)` tuples if `with_distances` is set.  Only the
+    /// items passing `hit_test` are appended, until `hits` holds `limit` items.
+    fn _neighbors(
+        &self,
+        py: Python,
+        neighbors: Vec<(u64, f64)>,
+        hit_test: &Option<PyObject>,
+        with_distances: bool,
+        limit: usize,
+        hits: &PyList,
+    ) -> PyResult<()> {
+        for (item, distance) in self._items_with(py, neighbors) {
+            if hits.len() == limit {
+                break;
+            }
+
+            if Self::_hit_test(py, &item, hit_test)? {
+                if with_distances {
+                    hits.append((item, distance))?;
+                } else {
+                    hits.append(item)?;
+                }
             }
         }
 
-        Ok(hits)
+        Ok(())
+    }
+
+    /// Runs `lookup` on each of `shapes`, collecting the ids found into the CSR-style arrays
+    /// `(offsets, ids)`.  The ids found for each shape are sorted.
+    ///
+    /// The shapes are taken as an owned `Vec` as they are read without the GIL, when other
+    /// Python threads are free to modify any NumPy array they came from.
+    fn _csr<'py, S: Send>(
+        &self,
+        py: Python<'py>,
+        shapes: Vec<S>,
+        lookup: Lookup<S>,
+    ) -> (&'py PyArray1<i64>, &'py PyArray1<i64>) {
+        // None of the lookups touch Python objects, so they all run without the GIL
+        let (offsets, ids) = py.allow_threads(|| {
+            let map = self.map.read();
+            let mut offsets = vec![0];
+            let mut ids = Vec::new();
+
+            for shape in shapes {
+                let start = ids.len();
+                ids.extend(lookup(&map, shape).into_iter().map(|&id| id as i64));
+                ids[start..].sort_unstable();
+
+                offsets.push(ids.len() as i64);
+            }
+
+            (offsets, ids)
+        });
+
+        (offsets.into_pyarray(py), ids.into_pyarray(py))
+    }
+
+    /// Converts an `(N, 4)` array of bounds into regions.
+    fn _to_rects(bounds: PyReadonlyArray2<f64>) -> PyResult<Vec<spaceindex::Rect<f64>>> {
+        let bounds = bounds.as_array();
+
+        if bounds.ncols() != 4 {
+            return Err(PyErr::new::<PyValueError, _>(format!(
+                "expected `bounds` to have 4 columns, instead it had {}",
+                bounds.ncols()
+            )));
+        }
+
+        bounds
+            .rows()
+            .into_iter()
+            .enumerate()
+            .map(|(index, row)| {
+                Self::_finite_rect(row[0], row[1], row[2], row[3]).ok_or_else(|| {
+                    PyErr::new::<PyValueError, _>(format!(
+                        "row {} of `bounds` has a coordinate or area which isn't finite: {}",
+                        index, row
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the region from `(minx, miny)` to `(maxx, maxy)`, if its area is finite.  The
+    /// tree compares the areas of regions as it grows, which would fail for infinite areas.
+    fn _finite_rect(minx: f64, miny: f64, maxx: f64, maxy: f64) -> Option<spaceindex::Rect<f64>> {
+        ((maxx - minx) * (maxy - miny))
+            .is_finite()
+            .then(|| spaceindex::Rect::new((minx, miny), (maxx, maxy)))
     }
 
     fn _to_rect(&self, bounds: &PyTuple) -> PyResult<spaceindex::Rect<f64>> {
+        if bounds.len() != 4 {
+            return Err(PyErr::new::<PyValueError, _>(format!(
+                "expected `bounds` to be a 4-tuple, instead it was a {}-tuple",
+                bounds.len()
+            )));
+        }
+
         // Extract the bounding box
         let minx: f64 = bounds.get_item(0)?.extract()?;
         let miny: f64 = bounds.get_item(1)?.extract()?;
         let maxx: f64 = bounds.get_item(2)?.extract()?;
         let maxy: f64 = bounds.get_item(3)?.extract()?;
 
-        Ok(spaceindex::Rect::new((minx, miny), (maxx, maxy)))
+        Self::_finite_rect(minx, miny, maxx, maxy).ok_or_else(|| {
+            PyErr::new::<PyValueError, _>(format!(
+                "`bounds` has a coordinate or area which isn't finite: ({}, {}, {}, {})",
+                minx, miny, maxx, maxy
+            ))
+        })
     }
 }
 
@@ -60,23 +212,152 @@ impl RTree {
 impl RTree {
     #[new]
     fn new() -> Self {
-        Self { tree: Tree::new() }
+        Self {
+            map: RwLock::new(RTreeMap::new()),
+            next_id: AtomicU64::new(0),
+        }
     }
 
-    pub fn insert(&mut self, bounds: &PyTuple, item: PyObject) -> PyResult<()> {
-        if bounds.len() != 4 {
+    /// Builds a tree from an `(N, 4)` array of bounds, each row holding
+    /// `(min_x, min_y, max_x, max_y)`.  The tree is packed in one go, which is much faster than
+    /// inserting the rows one at a time.
+    ///
+    /// The item of each row is taken from the sequence `items` if it is given, and is otherwise
+    /// the index of the row.  Each row's index is also its id.  A `ValueError` is raised if
+    /// any row has a coordinate or area which isn't finite.
+    #[staticmethod]
+    #[pyo3(signature = (bounds, items = None))]
+    pub fn from_arrays(
+        py: Python,
+        bounds: PyReadonlyArray2<f64>,
+        items: Option<&PyAny>,
+    ) -> PyResult<Self> {
+        let regions = Self::_to_rects(bounds)?;
+
+        let items = match items {
+            Some(items) => items
+                .iter()?
+                .map(|item| item.map(PyObject::from))
+                .collect::<PyResult<Vec<_>>>()?,
+            None => (0..regions.len()).map(|row| row.into_py(py)).collect(),
+        };
+
+        if items.len() != regions.len() {
             return Err(PyErr::new::<PyValueError, _>(format!(
-                "expected `bounds` to be a 4-tuple, instead it was a {}-tuple",
-                bounds.len()
+                "expected {} items, one for each row of `bounds`, instead there were {}",
+                regions.len(),
+                items.len()
             )));
         }
 
-        // Insert it into our tree
-        self.tree
-            .insert(self._to_rect(bounds)?, item)
-            .map_err(|_| PyErr::new::<PyRuntimeError, _>("failed to insert into tree"))?;
+        // Packing the tree only moves the items around, so it doesn't need the GIL
+        let next_id = regions.len() as u64;
+        let map = py.allow_threads(|| {
+            RTreeMap::bulk_load(
+                regions
+                    .into_iter()
+                    .zip(items)
+                    .enumerate()
+                    .map(|(row, (region, item))| (row as u64, region, item)),
+            )
+        });
 
-        Ok(())
+        Ok(Self {
+            map: RwLock::new(map),
+            next_id: AtomicU64::new(next_id),
+        })
+    }
+
+    fn __len__(&self) -> usize {
+        self.map.read().len()
+    }
+
+    /// Inserts an item into the tree, returning an id which identifies it until it is removed.
+    pub fn insert(&self, py: Python, bounds: &PyTuple, item: PyObject) -> PyResult<u64> {
+        let region = self._to_rect(bounds)?;
+
+        // Insert it into our tree, waiting for any running queries without the GIL
+        py.allow_threads(|| {
+            let mut map = self.map.write();
+            let id = self.next_id.load(Ordering::Relaxed);
+
+            map.insert(id, region, item).ok()?;
+            self.next_id.store(id + 1, Ordering::Relaxed);
+
+            Some(id)
+        })
+        .ok_or_else(|| PyErr::new::<PyRuntimeError, _>("failed to insert into tree"))
+    }
+
+    /// Removes an item from the tree.  An `int` is taken to be the id returned by `insert`;
+    /// any other object removes every item equal to it.
+    pub fn remove(&self, py: Python, item_or_id: &PyAny) -> PyResult<()> {
+        let ids = if item_or_id.is_instance_of::<PyInt>()? {
+            vec![item_or_id.extract::<u64>()?]
+        } else {
+            // Compare against a snapshot of the items, as comparisons run Python code
+            let items = {
+                let map = self.map.read();
+                map.iter()
+                    .map(|(&id, _, item)| (id, item.clone_ref(py)))
+                    .collect::<Vec<_>>()
+            };
+
+            let mut ids = Vec::new();
+            for (id, item) in items {
+                if item_or_id.eq(item.as_ref(py))? {
+                    ids.push(id);
+                }
+            }
+
+            ids
+        };
+
+        let removed = py.allow_threads(|| {
+            let mut map = self.map.write();
+            if !ids.first().is_some_and(|id| map.contains_key(id)) {
+                return None;
+            }
+
+            Some(
+                ids.iter()
+                    .filter_map(|id| map.remove(id))
+                    .collect::<Vec<_>>(),
+            )
+        });
+
+        // The removed items are only released once we hold the GIL again
+        match removed {
+            Some(_) => Ok(()),
+            None => Err(PyErr::new::<PyKeyError, _>(item_or_id.to_object(py))),
+        }
+    }
+
+    /// Moves the item with the given id to a new region.
+    pub fn update(&self, py: Python, id: u64, bounds: &PyTuple) -> PyResult<()> {
+        let region = self._to_rect(bounds)?;
+
+        let updated = py.allow_threads(|| {
+            let mut map = self.map.write();
+            if !map.contains_key(&id) {
+                return Ok(false);
+            }
+
+            map.update_region(&id, region).map(|_| true)
+        });
+
+        match updated {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(PyErr::new::<PyKeyError, _>(id)),
+            Err(_) => Err(PyErr::new::<PyRuntimeError, _>("failed to update tree")),
+        }
+    }
+
+    /// Removes every item from the tree.
+    pub fn clear(&self, py: Python) {
+        // The removed items are only released once we hold the GIL again
+        let _removed =
+            py.allow_threads(|| std::mem::replace(&mut *self.map.write(), RTreeMap::new()));
     }
 
     /// Finds all items in the tree that intersect with the given point.
@@ -91,7 +372,7 @@ impl RTree {
         let hits = self._query(
             py,
             spaceindex::Point::new(x, y),
-            |point| self.tree.point_lookup(point),
+            |map, point| map.point_lookup(point),
             hit_test,
         )?;
 
@@ -124,13 +405,121 @@ impl RTree {
         let hits = self._query(
             py,
             region,
-            |region| self.tree.region_intersection_lookup(region),
+            |map, region| map.region_intersection_lookup(region),
             hit_test,
         )?;
 
         // Make a set
         Ok(PySet::new(py, &hits)?.to_object(py))
     }
+
+    /// Finds the items containing each of the points `(xs[i], ys[i])`, returned as CSR-style
+    /// arrays `(offsets, ids)`: the ids of the items containing point `i` are
+    /// `ids[offsets[i]:offsets[i + 1]]`.
+    pub fn query_points<'py>(
+        &self,
+        py: Python<'py>,
+        xs: PyReadonlyArray1<f64>,
+        ys: PyReadonlyArray1<f64>,
+    ) -> PyResult<(&'py PyArray1<i64>, &'py PyArray1<i64>)> {
+        let (xs, ys) = (xs.as_array(), ys.as_array());
+
+        if xs.len() != ys.len() {
+            return Err(PyErr::new::<PyValueError, _>(format!(
+                "expected `xs` and `ys` to have the same length, instead they had lengths {} and {}",
+                xs.len(),
+                ys.len()
+            )));
+        }
+
+        // Copy the points out of the arrays before releasing the GIL
+        let points = xs
+            .iter()
+            .zip(ys.iter())
+            .map(|(&x, &y)| (x, y))
+            .collect::<Vec<_>>();
+
+        Ok(self._csr(py, points, |map, point| map.point_lookup(point)))
+    }
+
+    /// Finds the items intersecting each row of an `(N, 4)` array of bounds, returned as
+    /// CSR-style arrays `(offsets, ids)`: the ids of the items intersecting row `i` are
+    /// `ids[offsets[i]:offsets[i + 1]]`.
+    pub fn query_boxes<'py>(
+        &self,
+        py: Python<'py>,
+        bounds: PyReadonlyArray2<f64>,
+    ) -> PyResult<(&'py PyArray1<i64>, &'py PyArray1<i64>)> {
+        let regions = Self::_to_rects(bounds)?;
+
+        Ok(self._csr(py, regions, |map, region| {
+            map.region_intersection_lookup(region)
+        }))
+    }
+
+    /// Finds the `k` items in the tree closest to the given point, ordered by increasing
+    /// distance.  If `with_distances` is set, `(item, distance)` tuples are returned instead.
+    #[pyo3(signature = (x, y, k = 1, hit_test = None, with_distances = false))]
+    pub fn nearest(
+        &self,
+        py: Python,
+        x: f64,
+        y: f64,
+        k: usize,
+        hit_test: Option<PyObject>,
+        with_distances: bool,
+    ) -> PyResult<PyObject> {
+        let point = spaceindex::Point::new(x, y);
+        let hits = PyList::empty(py);
+
+        // The nearest `n` items are always a prefix of the nearest `2n` items, so when items fail
+        // `hit_test` we search again for twice as many, only testing those we haven't already.
+        let mut tested = 0;
+        let mut fetch = k;
+        while hits.len() < k {
+            let mut neighbors = py.allow_threads(|| {
+                let map = self.map.read();
+                Self::_keys(&map, map.tree().nearest_neighbors(point, fetch, Euclidean))
+            });
+            let found = neighbors.len();
+            neighbors.drain(..tested.min(found));
+            self._neighbors(py, neighbors, &hit_test, with_distances, k, hits)?;
+
+            // Every item in the tree has been tested
+            if found < fetch {
+                break;
+            }
+
+            tested = found;
+            fetch = fetch.saturating_mul(2);
+        }
+
+        Ok(hits.to_object(py))
+    }
+
+    /// Finds all items in the tree within `radius` of the given point, ordered by increasing
+    /// distance.  If `with_distances` is set, `(item, distance)` tuples are returned instead.
+    #[pyo3(signature = (x, y, radius, hit_test = None, with_distances = false))]
+    pub fn within(
+        &self,
+        py: Python,
+        x: f64,
+        y: f64,
+        radius: f64,
+        hit_test: Option<PyObject>,
+        with_distances: bool,
+    ) -> PyResult<PyObject> {
+        let point = spaceindex::Point::new(x, y);
+        let neighbors = py.allow_threads(|| {
+            let map = self.map.read();
+            Self::_keys(&map, map.tree().within_distance(point, radius, Euclidean))
+        });
+
+        let hits = PyList::empty(py);
+        self._neighbors(py, neighbors, &hit_test, with_distances, usize::MAX, hits)?;
+
+        Ok(hits.to_object(py))
+    }
 }
 
 #[pymodule]
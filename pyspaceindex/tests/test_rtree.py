@@ -1,3 +1,6 @@
+from concurrent.futures import ThreadPoolExecutor
+
+import numpy as np
 import pyspaceindex as psi
 
 
@@ -12,4 +15,187 @@ def test_basic_rtree():
     tree.insert((-1, -1, 2, 2), 99)
 
     # Query the tree for whether it contains a point
-    assert sorted(tree.query(0.5, 1.0)) == [12, 99]
\ No newline at end of file
+    assert sorted(tree.query(0.5, 1.0)) == [12, 99]
+
+def test_remove_and_update():
+    tree = psi.RTree()
+
+    # `insert` returns an id identifying each item
+    first = tree.insert((0, 0, 3, 3), "shed")
+    second = tree.insert((-1, -1, 2, 2), "house")
+    third = tree.insert((5, 5, 6, 6), "house")
+    assert len({first, second, third}) == 3
+    assert len(tree) == 3
+
+    # Items can be removed by id...
+    tree.remove(first)
+    assert tree.query(0.5, 1.0) == {"house"}
+
+    # ...or by value, which removes every equal item
+    tree.remove("house")
+    assert len(tree) == 0
+
+    try:
+        tree.remove(first)
+        assert False, "removed an item twice"
+    except KeyError:
+        pass
+
+    # Items can be moved to a new region
+    moving = tree.insert((0, 0, 1, 1), 12)
+    tree.update(moving, (10, 10, 11, 11))
+    assert tree.query(0.5, 0.5) == set()
+    assert tree.query(10.5, 10.5) == {12}
+
+    tree.clear()
+    assert len(tree) == 0
+    assert tree.query(10.5, 10.5) == set()
+
+    # Ids aren't reused after clearing
+    assert tree.insert((0, 0, 1, 1), 12) not in (first, second, third, moving)
+
+
+def test_nearest_and_within():
+    tree = psi.RTree()
+    tree.insert((0, 0, 1, 1), "a")
+    tree.insert((3, 0, 4, 1), "b")
+    tree.insert((0, 5, 1, 6), "c")
+    tree.insert((10, 10, 11, 11), "d")
+
+    assert tree.nearest(2, 0.5) == ["a"]
+    assert tree.nearest(2, 0.5, k=3) == ["a", "b", "c"]
+    assert tree.nearest(2, 0.5, k=10) == ["a", "b", "c", "d"]
+    assert sorted(tree.nearest(2, 0.5, k=2, with_distances=True)) == [("a", 1.0), ("b", 1.0)]
+
+    # Items failing `hit_test` are skipped, and further items found in their place
+    assert tree.nearest(0, 0, k=2, hit_test=lambda item: item in "cd") == ["c", "d"]
+
+    line = psi.RTree()
+    for x in range(100):
+        line.insert((x, 0, x, 0), x)
+    assert line.nearest(0, 0, k=3, hit_test=lambda item: item % 30 == 29) == [29, 59, 89]
+
+    assert tree.within(0.5, 0.5, 2.5) == ["a", "b"]
+    assert tree.within(0.5, 0.5, 5.0, with_distances=True) == [("a", 0.0), ("b", 2.5), ("c", 4.5)]
+    assert tree.within(0.5, 0.5, 5.0, hit_test=lambda item: item != "b") == ["a", "c"]
+    assert tree.within(50, 50, 1.0) == []
+
+
+def test_arrays():
+    bounds = np.array([[0, 0, 2, 2], [1, 1, 3, 3], [5, 5, 6, 6]], dtype=np.float64)
+
+    # Without items, each row's item is its index
+    tree = psi.RTree.from_arrays(bounds)
+    assert len(tree) == 3
+    assert tree.query(1.5, 1.5) == {0, 1}
+
+    # Batch queries return the ids found for each query in CSR form
+    xs = np.array([1.5, 5.5, 10.0], dtype=np.float64)
+    ys = np.array([1.5, 5.5, 10.0], dtype=np.float64)
+    offsets, ids = tree.query_points(xs, ys)
+    assert list(offsets) == [0, 2, 3, 3]
+    assert list(ids) == [0, 1, 2]
+
+    boxes = np.array([[20, 20, 30, 30], [2.5, 2.5, 5.5, 5.5]], dtype=np.float64)
+    offsets, ids = tree.query_boxes(boxes)
+    assert list(offsets) == [0, 0, 2]
+    assert list(ids) == [1, 2]
+
+    # Items can be given too, and each row's index is its id
+    tree = psi.RTree.from_arrays(bounds, items=["a", "b", "c"])
+    assert tree.query(5.5, 5.5) == {"c"}
+    tree.remove(2)
+    assert tree.query(5.5, 5.5) == set()
+    assert tree.insert((0, 0, 1, 1), "d") == 3
+
+    try:
+        psi.RTree.from_arrays(bounds, items=["a"])
+        assert False, "built a tree with too few items"
+    except ValueError:
+        pass
+
+    # Rows which aren't finite are rejected, naming the first of them
+    bad = np.array(
+        [[0, 0, 1, 1], [0, float("nan"), 1, 1], [float("inf"), 0, 1, 1]], dtype=np.float64
+    )
+    for build in (psi.RTree.from_arrays, tree.query_boxes):
+        try:
+            build(bad)
+            assert False, "accepted bounds which aren't finite"
+        except ValueError as error:
+            assert "row 1 " in str(error)
+
+
+def test_non_finite_bounds():
+    tree = psi.RTree()
+    id = tree.insert((0, 0, 1, 1), "a")
+
+    # Coordinates which aren't finite, or which span an infinite area, are rejected
+    for bounds in [
+        (0, float("nan"), 1, 1),
+        (0, 0, float("inf"), 1),
+        (-1e308, -1e308, 1e308, 1e308),
+    ]:
+        for change in (lambda: tree.insert(bounds, "b"), lambda: tree.update(id, bounds)):
+            try:
+                change()
+                assert False, "accepted bounds which aren't finite"
+            except ValueError:
+                pass
+
+    assert len(tree) == 1
+    assert tree.query(0.5, 0.5) == {"a"}
+
+
+def test_threaded_queries():
+    tree = psi.RTree()
+    for i in range(1000):
+        tree.insert((i, i, i + 1.5, i + 1.5), i)
+
+    def work(i):
+        return (
+            tree.query(i + 1.25, i + 1.25),
+            tree.query_intersecting((i, i, i + 0.5, i + 0.5)),
+            tree.nearest(i + 0.6, i + 0.6, hit_test=lambda item: item != i),
+        )
+
+    # Queries from many threads at once see the same results as queries from one
+    with ThreadPoolExecutor(max_workers=8) as pool:
+        for i, (point, region, nearest) in enumerate(pool.map(work, range(999))):
+            assert point == {i, i + 1}
+            assert region == {i - 1, i} - {-1}
+            assert nearest == [i - 1 if i > 0 else 1]
+
+
+def test_concurrent_readers_and_writers():
+    tree = psi.RTree()
+    for i in range(100):
+        tree.insert((i * 10, 0, i * 10 + 1, 1), ("fixed", i))
+
+    def write(worker):
+        # Each writer churns through its own items, away from the fixed ones
+        for i in range(200):
+            id = tree.insert((i, 100 + worker, i + 1, 101 + worker), ("moving", worker, i))
+            tree.update(id, (i, 200 + worker, i + 1, 201 + worker))
+            if i % 2 == 0:
+                tree.remove(id)
+        return worker
+
+    def read(i):
+        # Readers always see every fixed item, whatever the writers are doing
+        x = (i % 100) * 10 + 0.5
+        assert tree.query(x, 0.5) == {("fixed", i % 100)}
+        assert tree.query_intersecting((x, 0, x, 1)) == {("fixed", i % 100)}
+        assert tree.nearest(x, -5.0, hit_test=lambda item: item[0] == "fixed") == [
+            ("fixed", i % 100)
+        ]
+        return len(tree.within(x, 0.5, 1.0))
+
+    # Writers block while queries run and vice versa, instead of failing to borrow the tree
+    with ThreadPoolExecutor(max_workers=8) as pool:
+        writers = [pool.submit(write, worker) for worker in range(4)]
+        readers = [pool.submit(read, i) for i in range(2000)]
+        assert [writer.result() for writer in writers] == [0, 1, 2, 3]
+        assert all(reader.result() == 1 for reader in readers)
+
+    assert len(tree) == 100 + 4 * 100
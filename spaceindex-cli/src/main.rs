@@ -0,0 +1,382 @@
+//! `spaceindex`: builds, queries and inspects r-tree index files from the command line.
+//!
+//! Index files are written with [`RTree::write_to`], and hold the payload of every row as a
+//! line of CSV.
+mod stats;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use spaceindex::io::csv::Columns;
+use spaceindex::rtree::metric::Euclidean;
+use spaceindex::{ItemId, RTree, Rect};
+
+use crate::stats::Stats;
+
+#[derive(Parser)]
+#[command(
+    name = "spaceindex",
+    version,
+    about = "Build, query and inspect r-tree index files"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build an index file from a CSV or GeoJSON file
+    Build {
+        /// The CSV or GeoJSON file to index
+        input: PathBuf,
+
+        /// Where to write the index
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// The format of the input, if it can't be told from its extension
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+
+        /// The CSV columns holding each region: either `x,y` for points or
+        /// `minx,miny,maxx,maxy` for bounding boxes
+        #[arg(long, value_delimiter = ',', default_value = "minx,miny,maxx,maxy")]
+        columns: Vec<String>,
+    },
+
+    /// Print the rows of an index matching a query, one per line
+    Query {
+        /// The index to query
+        index: PathBuf,
+
+        #[command(subcommand)]
+        query: Query,
+    },
+
+    /// Print the height, node counts, fill factors and overlap of an index
+    Stats {
+        /// The index to inspect
+        index: PathBuf,
+    },
+
+    /// Check that an index file is intact and describes a valid tree
+    Validate {
+        /// The index to check
+        index: PathBuf,
+    },
+
+    /// Render the nodes of an index as a PNG image or a graphviz dotfile
+    Render {
+        /// The index to render
+        index: PathBuf,
+
+        /// Where to write the rendering; its extension (`png` or `dot`) picks the format
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// The width of a PNG image, which defaults to fit the index
+        #[arg(long)]
+        width: Option<u32>,
+
+        /// The height of a PNG image, which defaults to fit the index
+        #[arg(long)]
+        height: Option<u32>,
+
+        /// Only draw the nodes at this depth of a PNG image, where the root is at depth 0
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Query {
+    /// Rows whose region contains a point
+    #[command(allow_negative_numbers = true)]
+    Point { x: f64, y: f64 },
+
+    /// Rows whose region intersects a bounding box
+    #[command(allow_negative_numbers = true)]
+    Bbox {
+        min_x: f64,
+        min_y: f64,
+        max_x: f64,
+        max_y: f64,
+    },
+
+    /// The rows whose region is closest to a point, each preceded by its distance
+    #[command(allow_negative_numbers = true)]
+    Nearest {
+        x: f64,
+        y: f64,
+
+        /// The number of rows to print
+        #[arg(short, default_value_t = 1)]
+        k: usize,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Geojson,
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Build {
+            input,
+            output,
+            format,
+            columns,
+        } => build(&input, &output, format, &columns),
+        Command::Query {
+            index,
+            query: request,
+        } => query(&index, request),
+        Command::Stats { index } => {
+            print!("{}", Stats::new(&read_index(&index)?));
+            Ok(())
+        }
+        Command::Validate { index } => {
+            let tree = read_index(&index)?;
+            println!(
+                "ok: {} entries, height {}",
+                tree.len(),
+                Stats::new(&tree).height()
+            );
+            Ok(())
+        }
+        Command::Render {
+            index,
+            output,
+            width,
+            height,
+            depth,
+        } => render(&index, &output, width, height, depth),
+    }
+}
+
+fn build(input: &Path, output: &Path, format: Option<Format>, columns: &[String]) -> Result<()> {
+    let format = match format {
+        Some(format) => format,
+        None => match input.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Format::Csv,
+            Some("geojson" | "json") => Format::Geojson,
+            _ => bail!(
+                "can't tell the format of `{}` from its extension; pass --format",
+                input.display()
+            ),
+        },
+    };
+
+    let file = BufReader::new(
+        File::open(input).with_context(|| format!("failed to open `{}`", input.display()))?,
+    );
+
+    // The payload of every row is stored as a single line of CSV, so that it can be printed
+    // as-is by `query`.
+    let tree: RTree<String, f64> = match format {
+        Format::Csv => {
+            let columns = match columns {
+                [x, y] => Columns::Point {
+                    x: x.clone(),
+                    y: y.clone(),
+                },
+                [min_x, min_y, max_x, max_y] => Columns::Bounds {
+                    min_x: min_x.clone(),
+                    min_y: min_y.clone(),
+                    max_x: max_x.clone(),
+                    max_y: max_y.clone(),
+                },
+                _ => bail!("--columns takes either 2 or 4 column names"),
+            };
+
+            spaceindex::io::csv::load(file, &columns)?
+                .into_iter()
+                .map(|(region, fields)| Ok((region, encode_fields(&fields)?)))
+                .collect::<Result<_>>()?
+        }
+        Format::Geojson => RTree::from_geojson(file)?
+            .into_iter()
+            .map(|(region, properties)| {
+                let fields = properties
+                    .map(|properties| serde_json::to_string(&properties))
+                    .transpose()?;
+
+                Ok((region, encode_fields(fields.as_slice())?))
+            })
+            .collect::<Result<_>>()?,
+    };
+
+    let mut writer = BufWriter::new(
+        File::create(output).with_context(|| format!("failed to create `{}`", output.display()))?,
+    );
+    tree.write_to(&mut writer)?;
+    writer.flush()?;
+
+    println!("indexed {} rows into `{}`", tree.len(), output.display());
+    Ok(())
+}
+
+fn query(index: &Path, request: Query) -> Result<()> {
+    let tree = read_index(index)?;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    match request {
+        Query::Point { x, y } => {
+            for id in tree.point_lookup((x, y)) {
+                print_row(&mut stdout, &tree, id, None)?;
+            }
+        }
+        Query::Bbox {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        } => {
+            let region = Rect::new((min_x, min_y), (max_x, max_y));
+            for id in tree.region_intersection_lookup(region) {
+                print_row(&mut stdout, &tree, id, None)?;
+            }
+        }
+        Query::Nearest { x, y, k } => {
+            for (id, distance) in tree.nearest_neighbors((x, y), k, Euclidean) {
+                print_row(&mut stdout, &tree, id, Some(distance))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn render(
+    index: &Path,
+    output: &Path,
+    width: Option<u32>,
+    height: Option<u32>,
+    depth: Option<usize>,
+) -> Result<()> {
+    let tree = read_index(index)?;
+
+    match output.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => {
+            // Regions are drawn one unit to a pixel, with the minimum corner of the root region
+            // at the top left of the image.
+            let region = tree.root_node().get_region();
+            let fit = |length: f64| (length.ceil() + 1.0).clamp(1.0, 8192.0) as u32;
+            let width = width.unwrap_or_else(|| fit(region.width()));
+            let height = height.unwrap_or_else(|| fit(region.height()));
+
+            render_png(&tree, output, region.min().x_y(), width, height, depth)
+        }
+        Some("dot") => render_dot(&tree, output),
+        _ => bail!(
+            "can't tell how to render to `{}`; use a `.png` or `.dot` extension",
+            output.display()
+        ),
+    }
+}
+
+#[cfg(feature = "png")]
+fn render_png(
+    tree: &RTree<String, f64>,
+    output: &Path,
+    origin: (f64, f64),
+    width: u32,
+    height: u32,
+    depth: Option<usize>,
+) -> Result<()> {
+    use spaceindex::rtree::rendering::image::TreeRenderOptions;
+
+    let mut options = TreeRenderOptions::new(width, height);
+    options.with_origin(origin.0, origin.1);
+    match depth {
+        Some(depth) => options.with_threshold(depth),
+        None => options.without_threshold(),
+    };
+    options.draw_tree(output, tree, tree.root_index());
+
+    Ok(())
+}
+
+#[cfg(not(feature = "png"))]
+fn render_png(
+    _tree: &RTree<String, f64>,
+    _output: &Path,
+    _origin: (f64, f64),
+    _width: u32,
+    _height: u32,
+    _depth: Option<usize>,
+) -> Result<()> {
+    bail!("this build of spaceindex can't render PNG images; enable the `png` feature")
+}
+
+#[cfg(feature = "dot")]
+fn render_dot(tree: &RTree<String, f64>, output: &Path) -> Result<()> {
+    spaceindex::rtree::rendering::graphviz::render_gviz(tree, output);
+    Ok(())
+}
+
+#[cfg(not(feature = "dot"))]
+fn render_dot(_tree: &RTree<String, f64>, _output: &Path) -> Result<()> {
+    bail!("this build of spaceindex can't render dotfiles; enable the `dot` feature")
+}
+
+/// Reads the index file at `path`, checking that it describes a valid tree.
+fn read_index(path: &Path) -> Result<RTree<String, f64>> {
+    let file = File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
+
+    RTree::read_from(BufReader::new(file))
+        .with_context(|| format!("`{}` isn't a valid index", path.display()))
+}
+
+/// Encodes `fields` as a single line of CSV, without a trailing newline.
+fn encode_fields<S: AsRef<[u8]>>(fields: &[S]) -> Result<String> {
+    if fields.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    writer.write_record(fields)?;
+
+    let mut line = String::from_utf8(writer.into_inner().map_err(|error| anyhow!("{}", error))?)?;
+    line.pop();
+
+    Ok(line)
+}
+
+/// Prints the region and payload of the element `id` as a line of CSV, preceded by
+/// `distance` if there is one.
+fn print_row<W: Write>(
+    writer: &mut W,
+    tree: &RTree<String, f64>,
+    id: ItemId,
+    distance: Option<f64>,
+) -> Result<()> {
+    let (region, payload) = tree.get(id).expect("query returned a missing element");
+
+    if let Some(distance) = distance {
+        write!(writer, "{},", distance)?;
+    }
+    write!(
+        writer,
+        "{},{},{},{}",
+        region.min().x,
+        region.min().y,
+        region.max().x,
+        region.max().y
+    )?;
+    if !payload.is_empty() {
+        write!(writer, ",{}", payload)?;
+    }
+    writeln!(writer)?;
+
+    Ok(())
+}
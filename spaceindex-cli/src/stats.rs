@@ -0,0 +1,203 @@
+//! Statistics describing the shape of a tree.
+use std::fmt;
+
+use spaceindex::rtree::Index;
+use spaceindex::{RTree, Rect};
+
+/// Statistics for a single level of a tree, where level `0` holds the root.
+#[derive(Default)]
+struct Level {
+    /// The number of nodes on this level.
+    nodes: usize,
+
+    /// The number of children of nodes on this level.
+    children: usize,
+
+    /// The summed area of the children of nodes on this level.
+    area: f64,
+
+    /// The summed area of every intersection between two children of the same node.
+    overlap: f64,
+}
+
+/// Statistics describing the shape of a tree.
+pub struct Stats {
+    len: usize,
+    max_children: usize,
+    leaf_nodes: usize,
+    levels: Vec<Level>,
+}
+
+impl Stats {
+    /// Walks `tree`, collecting statistics for every level of internal nodes.
+    pub fn new<ND>(tree: &RTree<ND, f64>) -> Self {
+        let mut stats = Stats {
+            len: tree.len(),
+            max_children: tree.max_children(),
+            leaf_nodes: 0,
+            levels: Vec::new(),
+        };
+
+        if !tree.is_empty() {
+            stats.visit(tree, tree.root_index(), 0);
+        }
+
+        stats
+    }
+
+    /// Collects statistics for the subtree of `index`, which is on level `depth`.
+    fn visit<ND>(&mut self, tree: &RTree<ND, f64>, index: Index, depth: usize) {
+        let node = tree.get_node(index);
+        if node.get_data().is_some() {
+            self.leaf_nodes += 1;
+            return;
+        }
+
+        if self.levels.len() <= depth {
+            self.levels.resize_with(depth + 1, Level::default);
+        }
+
+        let regions = node
+            .child_index_iter()
+            .map(|child_index| tree.get_node(child_index).get_region())
+            .collect::<Vec<_>>();
+
+        let level = &mut self.levels[depth];
+        level.nodes += 1;
+        level.children += regions.len();
+        level.area += regions.iter().map(area).sum::<f64>();
+        for (position, a) in regions.iter().enumerate() {
+            for b in &regions[position + 1..] {
+                level.overlap += intersection_area(a, b);
+            }
+        }
+
+        for child_index in node.child_index_iter() {
+            self.visit(tree, child_index, depth + 1);
+        }
+    }
+
+    /// Returns the height of the tree, which is the number of levels of internal nodes.
+    pub fn height(&self) -> usize {
+        self.levels.len()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "entries:        {}", self.len)?;
+        writeln!(f, "height:         {}", self.height())?;
+        writeln!(
+            f,
+            "internal nodes: {}",
+            self.levels.iter().map(|level| level.nodes).sum::<usize>()
+        )?;
+        writeln!(f, "leaf nodes:     {}", self.leaf_nodes)?;
+
+        if self.levels.is_empty() {
+            return Ok(());
+        }
+
+        // A node holds at most `max_children - 1` children, as it is split on reaching
+        // `max_children`.
+        let capacity = (self.max_children - 1) as f64;
+
+        writeln!(f)?;
+        writeln!(f, "level  nodes  avg children  fill    overlap")?;
+        for (depth, level) in self.levels.iter().enumerate() {
+            let average = level.children as f64 / level.nodes as f64;
+            let overlap = if level.area > 0.0 {
+                level.overlap / level.area
+            } else {
+                0.0
+            };
+
+            writeln!(
+                f,
+                "{:<5}  {:<5}  {:<12.2}  {:<6}  {:.4}",
+                depth,
+                level.nodes,
+                average,
+                format!("{:.1}%", 100.0 * average / capacity),
+                overlap
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn area(region: &Rect<f64>) -> f64 {
+    region.width() * region.height()
+}
+
+fn intersection_area(a: &Rect<f64>, b: &Rect<f64>) -> f64 {
+    let width = a.max().x.min(b.max().x) - a.min().x.max(b.min().x);
+    let height = a.max().y.min(b.max().y) - a.min().y.max(b.min().y);
+
+    width.max(0.0) * height.max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use spaceindex::{RTree, Rect};
+
+    use super::Stats;
+
+    #[test]
+    fn test_stats_of_small_tree() {
+        let mut tree = RTree::new();
+        tree.insert(Rect::new((0.0, 0.0), (2.0, 2.0)), ()).unwrap();
+        tree.insert(Rect::new((1.0, 1.0), (3.0, 3.0)), ()).unwrap();
+        tree.insert(Rect::new((5.0, 5.0), (6.0, 6.0)), ()).unwrap();
+
+        // A root holding three entries, the first two of which overlap in a unit square.
+        let stats = Stats::new(&tree);
+        assert_eq!(stats.height(), 1);
+        assert_eq!(stats.leaf_nodes, 3);
+        assert_eq!(stats.levels[0].nodes, 1);
+        assert_eq!(stats.levels[0].children, 3);
+        assert_eq!(stats.levels[0].area, 9.0);
+        assert_eq!(stats.levels[0].overlap, 1.0);
+
+        let output = stats.to_string();
+        assert!(output.contains("entries:        3\n"));
+        assert!(output.contains("internal nodes: 1\n"));
+        assert!(output.contains("0.1111\n"));
+    }
+
+    #[test]
+    fn test_stats_of_larger_tree() {
+        let mut tree = RTree::new();
+        for i in 0..500 {
+            let x = (i % 25) as f64 * 4.0;
+            let y = (i / 25) as f64 * 4.0;
+            tree.insert(Rect::new((x, y), (x + 1.0, y + 1.0)), i)
+                .unwrap();
+        }
+
+        let stats = Stats::new(&tree);
+        assert!(stats.height() >= 3);
+        assert_eq!(stats.leaf_nodes, 500);
+        assert_eq!(stats.levels[0].nodes, 1);
+
+        // Every child on one level is a node on the next, and the last level holds the entries.
+        for (parent, child) in stats.levels.iter().zip(&stats.levels[1..]) {
+            assert_eq!(parent.children, child.nodes);
+        }
+        assert_eq!(stats.levels.last().unwrap().children, 500);
+
+        // The entries are disjoint, so the bottom level has no overlap.
+        assert_eq!(stats.levels.last().unwrap().overlap, 0.0);
+    }
+
+    #[test]
+    fn test_stats_of_empty_tree() {
+        let tree = RTree::<(), f64>::new();
+
+        let stats = Stats::new(&tree);
+        assert_eq!(stats.height(), 0);
+        assert_eq!(stats.leaf_nodes, 0);
+        assert!(!stats.to_string().contains("level"));
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Creates an empty directory for the files used by the test `name`.
+fn scratch_directory(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("spaceindex-cli-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+
+    directory
+}
+
+fn spaceindex(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_spaceindex"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs `spaceindex` with `args`, returning what it printed.
+fn run(args: &[&str]) -> String {
+    let output = spaceindex(args);
+    assert!(
+        output.status.success(),
+        "`spaceindex {}` failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Returns the lines of `output` in sorted order.
+fn sorted_lines(output: &str) -> Vec<&str> {
+    let mut lines = output.lines().collect::<Vec<_>>();
+    lines.sort_unstable();
+    lines
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn test_csv_index() {
+    let directory = scratch_directory("csv");
+    let input = directory.join("buildings.csv");
+    let index = directory.join("buildings.idx");
+    std::fs::write(
+        &input,
+        "name,minx,miny,maxx,maxy\nhouse,0,0,2,2\nshed,5,0,6,1\n\"barn, old\",-4,-3,-1,-1\n",
+    )
+    .unwrap();
+
+    let output = run(&["build", path(&input), "-o", path(&index)]);
+    assert!(output.starts_with("indexed 3 rows"));
+
+    let query = |args: &[&str]| run(&[&["query", path(&index)], args].concat());
+    assert_eq!(query(&["point", "1", "1"]), "0,0,2,2,house\n");
+    assert_eq!(query(&["point", "-2", "-2"]), "-4,-3,-1,-1,\"barn, old\"\n");
+    assert_eq!(query(&["point", "10", "10"]), "");
+    assert_eq!(
+        sorted_lines(&query(&["bbox", "-5", "-5", "0.5", "0.5"])),
+        vec!["-4,-3,-1,-1,\"barn, old\"", "0,0,2,2,house"]
+    );
+    assert_eq!(
+        query(&["nearest", "4", "0.5", "-k", "2"]),
+        "1,5,0,6,1,shed\n2,0,0,2,2,house\n"
+    );
+
+    let stats = run(&["stats", path(&index)]);
+    assert!(stats.contains("entries:        3\n"));
+    assert!(stats.contains("height:         1\n"));
+    assert!(stats.contains("leaf nodes:     3\n"));
+
+    assert_eq!(
+        run(&["validate", path(&index)]),
+        "ok: 3 entries, height 1\n"
+    );
+
+    // Damaged indexes fail validation.
+    let mut bytes = std::fs::read(&index).unwrap();
+    bytes.truncate(bytes.len() - 3);
+    std::fs::write(&index, bytes).unwrap();
+    let output = spaceindex(&["validate", path(&index)]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("isn't a valid index"));
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_geojson_index() {
+    let directory = scratch_directory("geojson");
+    let input = directory.join("features.geojson");
+    let index = directory.join("features.idx");
+    std::fs::write(
+        &input,
+        r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [3.0, 4.0] },
+                    "properties": { "name": "well" }
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[0.0, 0.0], [5.0, 1.0], [2.0, 6.0], [0.0, 0.0]]]
+                    },
+                    "properties": null
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let output = run(&["build", path(&input), "-o", path(&index)]);
+    assert!(output.starts_with("indexed 2 rows"));
+
+    let query = |args: &[&str]| run(&[&["query", path(&index)], args].concat());
+
+    // Properties are printed as JSON, in a single CSV field.
+    let hits = query(&["point", "3", "4"]);
+    let hits = sorted_lines(&hits);
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0], "0,0,5,6");
+    assert!(hits[1].starts_with("3,4,3,4,") && hits[1].contains("well"));
+
+    assert_eq!(query(&["bbox", "4", "4", "10", "10"]), "0,0,5,6\n");
+    assert_eq!(query(&["nearest", "8", "6", "-k", "1"]), "3,0,0,5,6\n");
+
+    let stats = run(&["stats", path(&index)]);
+    assert!(stats.contains("entries:        2\n"));
+    assert!(stats.contains("height:         1\n"));
+
+    assert_eq!(
+        run(&["validate", path(&index)]),
+        "ok: 2 entries, height 1\n"
+    );
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn test_render_negative_coordinates() {
+    let directory = scratch_directory("render");
+    let input = directory.join("points.csv");
+    let index = directory.join("points.idx");
+    let image = directory.join("points.png");
+    std::fs::write(&input, "x,y\n-40,-30\n-10,-5\n-25,-20\n").unwrap();
+
+    run(&[
+        "build",
+        path(&input),
+        "-o",
+        path(&index),
+        "--columns",
+        "x,y",
+    ]);
+    run(&["render", path(&index), "-o", path(&image)]);
+
+    // The image is sized to the root region, which lies entirely at negative coordinates.
+    let bytes = std::fs::read(&image).unwrap();
+    assert_eq!(&bytes[1..4], b"PNG");
+    let dimension =
+        |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    assert_eq!((dimension(16), dimension(20)), (31, 26));
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
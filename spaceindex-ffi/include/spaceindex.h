@@ -0,0 +1,186 @@
+#ifndef SPACEINDEX_H
+#define SPACEINDEX_H
+
+/* Generated by cbindgen from src/lib.rs; do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// The outcome of an operation which can fail.
+typedef enum SpaceIndexStatus {
+  // The operation succeeded.
+  SPACE_INDEX_STATUS_OK = 0,
+  // A pointer which must not be null was null.
+  SPACE_INDEX_STATUS_NULL_ARGUMENT = 1,
+  // A coordinate or the area of a region wasn't a finite number.
+  SPACE_INDEX_STATUS_INVALID_REGION = 2,
+  // No element has the given id.
+  SPACE_INDEX_STATUS_NOT_FOUND = 3,
+  // The serialized bytes don't describe a valid tree.
+  SPACE_INDEX_STATUS_INVALID_DATA = 4,
+  // The operation failed because of a bug in `spaceindex`.  The tree may no longer be
+  // usable, but it can still be released.
+  SPACE_INDEX_STATUS_INTERNAL = 5,
+} SpaceIndexStatus;
+
+// An r-tree of regions, each identified by a `uint64_t` id.
+typedef struct SpaceIndexTree SpaceIndexTree;
+
+// Called with the id of every element matching a query, along with the `user_data` passed to
+// the query.  Returns `true` to continue the query, or `false` to stop it.
+typedef bool (*SpaceIndexVisitor)(uint64_t id, void *user_data);
+
+// Called with the id and distance of every element found by a nearest neighbour query, in
+// order of increasing distance, along with the `user_data` passed to the query.  Returns
+// `true` to continue the query, or `false` to stop it.
+typedef bool (*SpaceIndexNeighborVisitor)(uint64_t id, double distance, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates a new, empty tree, which must be released with [`spaceindex_tree_free`].
+struct SpaceIndexTree *spaceindex_tree_new(void);
+
+// Releases `tree`.  Does nothing if `tree` is null.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't already been released.
+void spaceindex_tree_free(struct SpaceIndexTree *tree);
+
+// Returns the number of elements in `tree`, or `0` if `tree` is null.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released.
+size_t spaceindex_tree_len(const struct SpaceIndexTree *tree);
+
+// Inserts the region from `(min_x, min_y)` to `(max_x, max_y)` into `tree` with id `id`,
+// replacing any element with the same id.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released.
+enum SpaceIndexStatus spaceindex_tree_insert(struct SpaceIndexTree *tree,
+                                             uint64_t id,
+                                             double min_x,
+                                             double min_y,
+                                             double max_x,
+                                             double max_y);
+
+// Removes the element with id `id` from `tree`.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released.
+enum SpaceIndexStatus spaceindex_tree_remove(struct SpaceIndexTree *tree, uint64_t id);
+
+// Finds the elements of `tree` whose region contains the point `(x, y)`, writing the ids of
+// up to `capacity` of them to `ids`.  Returns the total number of matching elements.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released, and `ids` must be null or valid
+// for writing `capacity` ids.
+size_t spaceindex_tree_query_point(const struct SpaceIndexTree *tree,
+                                   double x,
+                                   double y,
+                                   uint64_t *ids,
+                                   size_t capacity);
+
+// Finds the elements of `tree` whose region intersects the region from `(min_x, min_y)` to
+// `(max_x, max_y)`, writing the ids of up to `capacity` of them to `ids`.  Returns the total
+// number of matching elements.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released, and `ids` must be null or valid
+// for writing `capacity` ids.
+size_t spaceindex_tree_query_bbox(const struct SpaceIndexTree *tree,
+                                  double min_x,
+                                  double min_y,
+                                  double max_x,
+                                  double max_y,
+                                  uint64_t *ids,
+                                  size_t capacity);
+
+// Finds the `k` elements of `tree` closest to the point `(x, y)`, writing the ids of up to
+// `capacity` of them to `ids` and their Euclidean distances to `distances`, in order of
+// increasing distance.  Returns the number of elements found, which is `k` unless the tree
+// has fewer elements.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released, and each of `ids` and
+// `distances` must be null or valid for writing `capacity` values.
+size_t spaceindex_tree_nearest(const struct SpaceIndexTree *tree,
+                               double x,
+                               double y,
+                               size_t k,
+                               uint64_t *ids,
+                               double *distances,
+                               size_t capacity);
+
+// Calls `visitor` with the id of every element of `tree` whose region contains the point
+// `(x, y)`.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released.
+enum SpaceIndexStatus spaceindex_tree_query_point_each(const struct SpaceIndexTree *tree,
+                                                       double x,
+                                                       double y,
+                                                       SpaceIndexVisitor visitor,
+                                                       void *user_data);
+
+// Calls `visitor` with the id of every element of `tree` whose region intersects the region
+// from `(min_x, min_y)` to `(max_x, max_y)`.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released.
+enum SpaceIndexStatus spaceindex_tree_query_bbox_each(const struct SpaceIndexTree *tree,
+                                                      double min_x,
+                                                      double min_y,
+                                                      double max_x,
+                                                      double max_y,
+                                                      SpaceIndexVisitor visitor,
+                                                      void *user_data);
+
+// Calls `visitor` with the id and Euclidean distance of each of the `k` elements of `tree`
+// closest to the point `(x, y)`, in order of increasing distance.
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released.
+enum SpaceIndexStatus spaceindex_tree_nearest_each(const struct SpaceIndexTree *tree,
+                                                   double x,
+                                                   double y,
+                                                   size_t k,
+                                                   SpaceIndexNeighborVisitor visitor,
+                                                   void *user_data);
+
+// Serializes `tree`, storing a pointer to the bytes in `bytes` and their number in `length`.
+// The bytes must be released with [`spaceindex_bytes_free`].
+//
+// # Safety
+// `tree` must be null or a tree which hasn't been released, and `bytes` and `length` must be
+// null or valid for writing.
+enum SpaceIndexStatus spaceindex_tree_serialize(const struct SpaceIndexTree *tree,
+                                                uint8_t **bytes,
+                                                size_t *length);
+
+// Releases bytes returned by [`spaceindex_tree_serialize`].  Does nothing if `bytes` is null.
+//
+// # Safety
+// `bytes` must be null or bytes returned by [`spaceindex_tree_serialize`] which haven't
+// already been released, and `length` must be the length returned with them.
+void spaceindex_bytes_free(uint8_t *bytes, size_t length);
+
+// Reads a tree from the `length` bytes at `bytes`, storing it in `tree`.  The tree must be
+// released with [`spaceindex_tree_free`].
+//
+// # Safety
+// `bytes` must be valid for reading `length` bytes, and `tree` must be null or valid for
+// writing.
+enum SpaceIndexStatus spaceindex_tree_deserialize(const uint8_t *bytes,
+                                                  size_t length,
+                                                  struct SpaceIndexTree **tree);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* SPACEINDEX_H */
@@ -0,0 +1,444 @@
+//! C bindings for `spaceindex`, for embedding its r-tree in C and C++ applications.
+//!
+//! A tree is created with [`spaceindex_tree_new`] or [`spaceindex_tree_deserialize`], and must
+//! be released with [`spaceindex_tree_free`].  Every element is identified by a `uint64_t` id
+//! chosen by the caller, and inserting an element with an id already in the tree replaces it.
+//!
+//! Each query comes in two forms.  The first writes the ids of matching elements into a
+//! buffer provided by the caller and returns the total number of matches, so a caller whose
+//! buffer was too small can retry with a larger one.  The second, suffixed `_each`, calls a
+//! callback for every match until the callback returns `false`.
+//!
+//! Trees are serialized in the format of [`RTree::write_to`](spaceindex::RTree::write_to),
+//! holding each id as the data of its element, so they can be exchanged with Rust code using
+//! an `RTree<u64, f64>`.
+//!
+//! Panics never unwind into C.  A function which panics returns
+//! [`SpaceIndexStatus::Internal`] instead, or `0` or null if it doesn't return a status.
+//!
+//! The header `include/spaceindex.h` is generated from this file by running
+//! `cbindgen --config cbindgen.toml --output include/spaceindex.h` in this crate.
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use spaceindex::rtree::metric::Euclidean;
+use spaceindex::{RTree, RTreeMap, Rect};
+
+/// The outcome of an operation which can fail.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpaceIndexStatus {
+    /// The operation succeeded.
+    Ok = 0,
+
+    /// A pointer which must not be null was null.
+    NullArgument = 1,
+
+    /// A coordinate or the area of a region wasn't a finite number.
+    InvalidRegion = 2,
+
+    /// No element has the given id.
+    NotFound = 3,
+
+    /// The serialized bytes don't describe a valid tree.
+    InvalidData = 4,
+
+    /// The operation failed because of a bug in `spaceindex`.  The tree may no longer be
+    /// usable, but it can still be released.
+    Internal = 5,
+}
+
+/// An r-tree of regions, each identified by a `uint64_t` id.
+pub struct SpaceIndexTree {
+    map: RTreeMap<u64, (), f64>,
+}
+
+/// Called with the id of every element matching a query, along with the `user_data` passed to
+/// the query.  Returns `true` to continue the query, or `false` to stop it.
+pub type SpaceIndexVisitor = Option<extern "C" fn(id: u64, user_data: *mut c_void) -> bool>;
+
+/// Called with the id and distance of every element found by a nearest neighbour query, in
+/// order of increasing distance, along with the `user_data` passed to the query.  Returns
+/// `true` to continue the query, or `false` to stop it.
+pub type SpaceIndexNeighborVisitor =
+    Option<extern "C" fn(id: u64, distance: f64, user_data: *mut c_void) -> bool>;
+
+/// Creates a new, empty tree, which must be released with [`spaceindex_tree_free`].
+#[no_mangle]
+pub extern "C" fn spaceindex_tree_new() -> *mut SpaceIndexTree {
+    catch_panic(ptr::null_mut(), || {
+        Box::into_raw(Box::new(SpaceIndexTree {
+            map: RTreeMap::new(),
+        }))
+    })
+}
+
+/// Releases `tree`.  Does nothing if `tree` is null.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't already been released.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_free(tree: *mut SpaceIndexTree) {
+    catch_panic((), || {
+        if !tree.is_null() {
+            drop(Box::from_raw(tree));
+        }
+    })
+}
+
+/// Returns the number of elements in `tree`, or `0` if `tree` is null.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_len(tree: *const SpaceIndexTree) -> usize {
+    catch_panic(0, || tree.as_ref().map_or(0, |tree| tree.map.len()))
+}
+
+/// Inserts the region from `(min_x, min_y)` to `(max_x, max_y)` into `tree` with id `id`,
+/// replacing any element with the same id.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_insert(
+    tree: *mut SpaceIndexTree,
+    id: u64,
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+) -> SpaceIndexStatus {
+    catch_panic(SpaceIndexStatus::Internal, || {
+        let Some(tree) = tree.as_mut() else {
+            return SpaceIndexStatus::NullArgument;
+        };
+        let Some(region) = region(min_x, min_y, max_x, max_y) else {
+            return SpaceIndexStatus::InvalidRegion;
+        };
+
+        match tree.map.insert(id, region, ()) {
+            Ok(_) => SpaceIndexStatus::Ok,
+            Err(_) => SpaceIndexStatus::InvalidRegion,
+        }
+    })
+}
+
+/// Removes the element with id `id` from `tree`.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_remove(
+    tree: *mut SpaceIndexTree,
+    id: u64,
+) -> SpaceIndexStatus {
+    catch_panic(SpaceIndexStatus::Internal, || {
+        let Some(tree) = tree.as_mut() else {
+            return SpaceIndexStatus::NullArgument;
+        };
+
+        match tree.map.remove(&id) {
+            Some(_) => SpaceIndexStatus::Ok,
+            None => SpaceIndexStatus::NotFound,
+        }
+    })
+}
+
+/// Finds the elements of `tree` whose region contains the point `(x, y)`, writing the ids of
+/// up to `capacity` of them to `ids`.  Returns the total number of matching elements.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released, and `ids` must be null or valid
+/// for writing `capacity` ids.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_query_point(
+    tree: *const SpaceIndexTree,
+    x: f64,
+    y: f64,
+    ids: *mut u64,
+    capacity: usize,
+) -> usize {
+    catch_panic(0, || {
+        let Some(tree) = tree.as_ref() else {
+            return 0;
+        };
+
+        write_ids(tree.map.point_lookup((x, y)), ids, capacity)
+    })
+}
+
+/// Finds the elements of `tree` whose region intersects the region from `(min_x, min_y)` to
+/// `(max_x, max_y)`, writing the ids of up to `capacity` of them to `ids`.  Returns the total
+/// number of matching elements.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released, and `ids` must be null or valid
+/// for writing `capacity` ids.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_query_bbox(
+    tree: *const SpaceIndexTree,
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+    ids: *mut u64,
+    capacity: usize,
+) -> usize {
+    catch_panic(0, || {
+        let (Some(tree), Some(region)) = (tree.as_ref(), region(min_x, min_y, max_x, max_y)) else {
+            return 0;
+        };
+
+        write_ids(tree.map.region_intersection_lookup(region), ids, capacity)
+    })
+}
+
+/// Finds the `k` elements of `tree` closest to the point `(x, y)`, writing the ids of up to
+/// `capacity` of them to `ids` and their Euclidean distances to `distances`, in order of
+/// increasing distance.  Returns the number of elements found, which is `k` unless the tree
+/// has fewer elements.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released, and each of `ids` and
+/// `distances` must be null or valid for writing `capacity` values.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_nearest(
+    tree: *const SpaceIndexTree,
+    x: f64,
+    y: f64,
+    k: usize,
+    ids: *mut u64,
+    distances: *mut f64,
+    capacity: usize,
+) -> usize {
+    catch_panic(0, || {
+        let Some(tree) = tree.as_ref() else {
+            return 0;
+        };
+
+        let neighbors = tree.map.nearest_neighbors((x, y), k, Euclidean);
+        for (position, (&id, distance)) in neighbors.iter().take(capacity).enumerate() {
+            if !ids.is_null() {
+                ids.add(position).write(id);
+            }
+            if !distances.is_null() {
+                distances.add(position).write(*distance);
+            }
+        }
+
+        neighbors.len()
+    })
+}
+
+/// Calls `visitor` with the id of every element of `tree` whose region contains the point
+/// `(x, y)`.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_query_point_each(
+    tree: *const SpaceIndexTree,
+    x: f64,
+    y: f64,
+    visitor: SpaceIndexVisitor,
+    user_data: *mut c_void,
+) -> SpaceIndexStatus {
+    catch_panic(SpaceIndexStatus::Internal, || {
+        let (Some(tree), Some(visitor)) = (tree.as_ref(), visitor) else {
+            return SpaceIndexStatus::NullArgument;
+        };
+
+        visit_ids(tree.map.point_lookup((x, y)), visitor, user_data);
+        SpaceIndexStatus::Ok
+    })
+}
+
+/// Calls `visitor` with the id of every element of `tree` whose region intersects the region
+/// from `(min_x, min_y)` to `(max_x, max_y)`.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_query_bbox_each(
+    tree: *const SpaceIndexTree,
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+    visitor: SpaceIndexVisitor,
+    user_data: *mut c_void,
+) -> SpaceIndexStatus {
+    catch_panic(SpaceIndexStatus::Internal, || {
+        let (Some(tree), Some(visitor)) = (tree.as_ref(), visitor) else {
+            return SpaceIndexStatus::NullArgument;
+        };
+        let Some(region) = region(min_x, min_y, max_x, max_y) else {
+            return SpaceIndexStatus::InvalidRegion;
+        };
+
+        visit_ids(
+            tree.map.region_intersection_lookup(region),
+            visitor,
+            user_data,
+        );
+        SpaceIndexStatus::Ok
+    })
+}
+
+/// Calls `visitor` with the id and Euclidean distance of each of the `k` elements of `tree`
+/// closest to the point `(x, y)`, in order of increasing distance.
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_nearest_each(
+    tree: *const SpaceIndexTree,
+    x: f64,
+    y: f64,
+    k: usize,
+    visitor: SpaceIndexNeighborVisitor,
+    user_data: *mut c_void,
+) -> SpaceIndexStatus {
+    catch_panic(SpaceIndexStatus::Internal, || {
+        let (Some(tree), Some(visitor)) = (tree.as_ref(), visitor) else {
+            return SpaceIndexStatus::NullArgument;
+        };
+
+        for (&id, distance) in tree.map.nearest_neighbors((x, y), k, Euclidean) {
+            if !visitor(id, distance, user_data) {
+                break;
+            }
+        }
+
+        SpaceIndexStatus::Ok
+    })
+}
+
+/// Serializes `tree`, storing a pointer to the bytes in `bytes` and their number in `length`.
+/// The bytes must be released with [`spaceindex_bytes_free`].
+///
+/// # Safety
+/// `tree` must be null or a tree which hasn't been released, and `bytes` and `length` must be
+/// null or valid for writing.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_serialize(
+    tree: *const SpaceIndexTree,
+    bytes: *mut *mut u8,
+    length: *mut usize,
+) -> SpaceIndexStatus {
+    catch_panic(SpaceIndexStatus::Internal, || {
+        let Some(tree) = tree.as_ref() else {
+            return SpaceIndexStatus::NullArgument;
+        };
+        if bytes.is_null() || length.is_null() {
+            return SpaceIndexStatus::NullArgument;
+        }
+
+        let tree = tree
+            .map
+            .iter()
+            .map(|(&id, region, _)| (*region, id))
+            .collect::<RTree<u64, f64>>();
+
+        let mut buffer = Vec::new();
+        tree.write_to(&mut buffer)
+            .expect("writing to a buffer can't fail");
+
+        let buffer = buffer.into_boxed_slice();
+        length.write(buffer.len());
+        bytes.write(Box::into_raw(buffer).cast());
+
+        SpaceIndexStatus::Ok
+    })
+}
+
+/// Releases bytes returned by [`spaceindex_tree_serialize`].  Does nothing if `bytes` is null.
+///
+/// # Safety
+/// `bytes` must be null or bytes returned by [`spaceindex_tree_serialize`] which haven't
+/// already been released, and `length` must be the length returned with them.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_bytes_free(bytes: *mut u8, length: usize) {
+    catch_panic((), || {
+        if !bytes.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(bytes, length)));
+        }
+    })
+}
+
+/// Reads a tree from the `length` bytes at `bytes`, storing it in `tree`.  The tree must be
+/// released with [`spaceindex_tree_free`].
+///
+/// # Safety
+/// `bytes` must be valid for reading `length` bytes, and `tree` must be null or valid for
+/// writing.
+#[no_mangle]
+pub unsafe extern "C" fn spaceindex_tree_deserialize(
+    bytes: *const u8,
+    length: usize,
+    tree: *mut *mut SpaceIndexTree,
+) -> SpaceIndexStatus {
+    catch_panic(SpaceIndexStatus::Internal, || {
+        if bytes.is_null() || tree.is_null() {
+            return SpaceIndexStatus::NullArgument;
+        }
+
+        let Ok(read) = RTree::<u64, f64>::read_from(slice::from_raw_parts(bytes, length)) else {
+            return SpaceIndexStatus::InvalidData;
+        };
+
+        let mut map = RTreeMap::new();
+        for (read_region, id) in read {
+            let (min, max) = (read_region.min(), read_region.max());
+            let Some(region) = region(min.x, min.y, max.x, max.y) else {
+                return SpaceIndexStatus::InvalidData;
+            };
+            if map.insert(id, region, ()).is_err() {
+                return SpaceIndexStatus::InvalidData;
+            }
+        }
+
+        tree.write(Box::into_raw(Box::new(SpaceIndexTree { map })));
+        SpaceIndexStatus::Ok
+    })
+}
+
+/// Returns the region from `(min_x, min_y)` to `(max_x, max_y)`, if its area is finite.  The
+/// tree compares the areas of regions as it grows, which would fail for infinite areas.
+fn region(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Option<Rect<f64>> {
+    let area = (max_x - min_x) * (max_y - min_y);
+
+    area.is_finite()
+        .then(|| Rect::new((min_x, min_y), (max_x, max_y)))
+}
+
+/// Runs `body`, returning `fallback` if it panics, as unwinding into C is undefined behaviour.
+fn catch_panic<R>(fallback: R, body: impl FnOnce() -> R) -> R {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(fallback)
+}
+
+/// Writes up to `capacity` of `found` to `ids`, returning the number of ids found.
+unsafe fn write_ids(found: Vec<&u64>, ids: *mut u64, capacity: usize) -> usize {
+    if !ids.is_null() {
+        for (position, &&id) in found.iter().take(capacity).enumerate() {
+            ids.add(position).write(id);
+        }
+    }
+
+    found.len()
+}
+
+/// Calls `visitor` with each of `found` until it returns `false`.
+fn visit_ids(
+    found: Vec<&u64>,
+    visitor: extern "C" fn(u64, *mut c_void) -> bool,
+    user_data: *mut c_void,
+) {
+    for &id in found {
+        if !visitor(id, user_data) {
+            break;
+        }
+    }
+}
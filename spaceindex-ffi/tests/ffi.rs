@@ -0,0 +1,231 @@
+use std::ffi::c_void;
+use std::ptr;
+
+use spaceindex::RTree;
+use spaceindex_ffi::*;
+
+unsafe fn sorted_point_query(tree: *const SpaceIndexTree, x: f64, y: f64) -> Vec<u64> {
+    let mut ids = vec![0; 8];
+    let count = spaceindex_tree_query_point(tree, x, y, ids.as_mut_ptr(), ids.len());
+    ids.truncate(count);
+    ids.sort_unstable();
+    ids
+}
+
+#[test]
+fn test_insert_query_remove() {
+    unsafe {
+        let tree = spaceindex_tree_new();
+        assert_eq!(spaceindex_tree_len(tree), 0);
+
+        for (id, (min_x, min_y, max_x, max_y)) in [
+            (10, (0.0, 0.0, 2.0, 2.0)),
+            (20, (1.0, 1.0, 3.0, 3.0)),
+            (30, (5.0, 5.0, 6.0, 6.0)),
+        ] {
+            let status = spaceindex_tree_insert(tree, id, min_x, min_y, max_x, max_y);
+            assert_eq!(status, SpaceIndexStatus::Ok);
+        }
+        assert_eq!(spaceindex_tree_len(tree), 3);
+        assert_eq!(sorted_point_query(tree, 1.5, 1.5), vec![10, 20]);
+
+        // A buffer which is too small receives as many ids as fit, and the total is returned
+        let mut ids = [0; 1];
+        let count = spaceindex_tree_query_bbox(tree, 0.0, 0.0, 10.0, 10.0, ids.as_mut_ptr(), 1);
+        assert_eq!(count, 3);
+        assert!([10, 20, 30].contains(&ids[0]));
+        let count = spaceindex_tree_query_bbox(tree, 0.0, 0.0, 10.0, 10.0, ptr::null_mut(), 0);
+        assert_eq!(count, 3);
+
+        let mut ids = [0; 4];
+        let mut distances = [0.0; 4];
+        let count = spaceindex_tree_nearest(
+            tree,
+            7.0,
+            6.0,
+            2,
+            ids.as_mut_ptr(),
+            distances.as_mut_ptr(),
+            ids.len(),
+        );
+        assert_eq!(count, 2);
+        assert_eq!(ids[..2], [30, 20]);
+        assert_eq!(distances[..2], [1.0, 5.0]);
+
+        // Inserting an existing id moves its element
+        let status = spaceindex_tree_insert(tree, 10, 8.0, 8.0, 9.0, 9.0);
+        assert_eq!(status, SpaceIndexStatus::Ok);
+        assert_eq!(spaceindex_tree_len(tree), 3);
+        assert_eq!(sorted_point_query(tree, 1.5, 1.5), vec![20]);
+
+        assert_eq!(spaceindex_tree_remove(tree, 20), SpaceIndexStatus::Ok);
+        assert_eq!(spaceindex_tree_remove(tree, 20), SpaceIndexStatus::NotFound);
+        assert!(sorted_point_query(tree, 1.5, 1.5).is_empty());
+
+        let status = spaceindex_tree_insert(tree, 40, 0.0, f64::NAN, 1.0, 1.0);
+        assert_eq!(status, SpaceIndexStatus::InvalidRegion);
+        let status = spaceindex_tree_insert(ptr::null_mut(), 40, 0.0, 0.0, 1.0, 1.0);
+        assert_eq!(status, SpaceIndexStatus::NullArgument);
+        assert_eq!(spaceindex_tree_len(tree), 2);
+
+        spaceindex_tree_free(tree);
+        spaceindex_tree_free(ptr::null_mut());
+    }
+}
+
+extern "C" fn collect(id: u64, user_data: *mut c_void) -> bool {
+    let found = unsafe { &mut *user_data.cast::<Vec<u64>>() };
+    found.push(id);
+
+    // Stop after two elements
+    found.len() < 2
+}
+
+extern "C" fn collect_neighbor(id: u64, distance: f64, user_data: *mut c_void) -> bool {
+    let found = unsafe { &mut *user_data.cast::<Vec<(u64, f64)>>() };
+    found.push((id, distance));
+
+    true
+}
+
+#[test]
+fn test_visitors() {
+    unsafe {
+        let tree = spaceindex_tree_new();
+        for id in 0..5 {
+            let offset = id as f64;
+            spaceindex_tree_insert(tree, id, offset, 0.0, offset + 0.5, 0.5);
+        }
+
+        let mut found = Vec::<u64>::new();
+        let user_data = (&mut found as *mut Vec<u64>).cast();
+        let status = spaceindex_tree_query_point_each(tree, 1.25, 0.25, Some(collect), user_data);
+        assert_eq!(status, SpaceIndexStatus::Ok);
+        assert_eq!(found, vec![1]);
+
+        found.clear();
+        let status = spaceindex_tree_query_bbox_each(
+            tree,
+            0.0,
+            0.0,
+            10.0,
+            1.0,
+            Some(collect),
+            (&mut found as *mut Vec<u64>).cast(),
+        );
+        assert_eq!(status, SpaceIndexStatus::Ok);
+        assert_eq!(found.len(), 2);
+
+        let mut neighbors = Vec::<(u64, f64)>::new();
+        let status = spaceindex_tree_nearest_each(
+            tree,
+            -1.0,
+            0.25,
+            3,
+            Some(collect_neighbor),
+            (&mut neighbors as *mut Vec<(u64, f64)>).cast(),
+        );
+        assert_eq!(status, SpaceIndexStatus::Ok);
+        assert_eq!(neighbors, vec![(0, 1.0), (1, 2.0), (2, 3.0)]);
+
+        let status = spaceindex_tree_query_point_each(tree, 0.0, 0.0, None, ptr::null_mut());
+        assert_eq!(status, SpaceIndexStatus::NullArgument);
+
+        spaceindex_tree_free(tree);
+    }
+}
+
+#[test]
+fn test_serialize() {
+    unsafe {
+        let tree = spaceindex_tree_new();
+        for id in 0..100u64 {
+            let offset = id as f64;
+            spaceindex_tree_insert(tree, id * 3, offset, offset, offset + 1.5, offset + 1.5);
+        }
+
+        let mut bytes = ptr::null_mut();
+        let mut length = 0;
+        let status = spaceindex_tree_serialize(tree, &mut bytes, &mut length);
+        assert_eq!(status, SpaceIndexStatus::Ok);
+
+        // The bytes are readable by Rust code
+        let read = RTree::<u64, f64>::read_from(std::slice::from_raw_parts(bytes, length)).unwrap();
+        assert_eq!(read.len(), 100);
+
+        let mut copy = ptr::null_mut();
+        let status = spaceindex_tree_deserialize(bytes, length, &mut copy);
+        assert_eq!(status, SpaceIndexStatus::Ok);
+        assert_eq!(spaceindex_tree_len(copy), 100);
+        assert_eq!(sorted_point_query(copy, 10.25, 10.25), vec![27, 30]);
+
+        // Damaged bytes are rejected
+        *bytes.add(length / 2) ^= 0xff;
+        let mut damaged = ptr::null_mut();
+        let status = spaceindex_tree_deserialize(bytes, length, &mut damaged);
+        assert_eq!(status, SpaceIndexStatus::InvalidData);
+        assert!(damaged.is_null());
+
+        spaceindex_bytes_free(bytes, length);
+        spaceindex_tree_free(copy);
+        spaceindex_tree_free(tree);
+    }
+}
+
+#[test]
+fn test_regions_with_infinite_area_are_rejected() {
+    unsafe {
+        let tree = spaceindex_tree_new();
+
+        // Each coordinate is finite, but the area overflows, which would make the tree's area
+        // comparisons fail once the root splits.
+        for id in 0..20 {
+            let status = spaceindex_tree_insert(tree, id, -1e308, -1e308, 1e308, 1e308);
+            assert_eq!(status, SpaceIndexStatus::InvalidRegion);
+        }
+        let status = spaceindex_tree_insert(tree, 0, f64::INFINITY, 0.0, f64::INFINITY, 0.0);
+        assert_eq!(status, SpaceIndexStatus::InvalidRegion);
+        assert_eq!(spaceindex_tree_len(tree), 0);
+
+        // Large regions with a finite area are still accepted.
+        for id in 0..20 {
+            let status = spaceindex_tree_insert(tree, id, -1e150, -1e150, 1e150, 1e150);
+            assert_eq!(status, SpaceIndexStatus::Ok);
+        }
+        let count = spaceindex_tree_query_point(tree, 0.0, 0.0, ptr::null_mut(), 0);
+        assert_eq!(count, 20);
+
+        let status = spaceindex_tree_query_bbox_each(
+            tree,
+            -1e308,
+            -1e308,
+            1e308,
+            1e308,
+            Some(collect),
+            ptr::null_mut(),
+        );
+        assert_eq!(status, SpaceIndexStatus::InvalidRegion);
+
+        spaceindex_tree_free(tree);
+    }
+}
+
+#[test]
+fn test_header_is_current() {
+    let directory = env!("CARGO_MANIFEST_DIR");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", directory)).unwrap();
+
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(format!("{}/src/lib.rs", directory))
+        .generate()
+        .unwrap()
+        .write(&mut generated);
+
+    let header = std::fs::read_to_string(format!("{}/include/spaceindex.h", directory)).unwrap();
+    assert!(
+        String::from_utf8(generated).unwrap() == header,
+        "include/spaceindex.h is out of date; regenerate it with cbindgen"
+    );
+}
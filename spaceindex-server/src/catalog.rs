@@ -0,0 +1,138 @@
+//! The index files served by a [`Server`](crate::Server), and reloading them when they change.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use spaceindex::RTree;
+
+/// A tree read from an index file written by the `spaceindex` command-line tool, whose payloads
+/// are each a single line of CSV.
+pub type Tree = RTree<String, f64>;
+
+/// A set of named index files.
+pub struct Catalog {
+    entries: BTreeMap<String, Entry>,
+}
+
+struct Entry {
+    path: PathBuf,
+    loaded: RwLock<Loaded>,
+}
+
+struct Loaded {
+    tree: Arc<Tree>,
+
+    /// The modification time of the file when it was last read.
+    modified: Option<SystemTime>,
+}
+
+impl Catalog {
+    /// Reads every index file in `indexes`, each of which is served under the name it is
+    /// paired with.
+    ///
+    /// # Errors
+    /// This function will return an error if two indexes share a name, or if any index file
+    /// can't be read.
+    pub fn open<I: IntoIterator<Item = (String, PathBuf)>>(indexes: I) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+
+        for (name, path) in indexes {
+            if entries.contains_key(&name) {
+                bail!("more than one index is named `{}`", name);
+            }
+
+            let modified = modified(&path);
+            let tree = Arc::new(read_index(&path)?);
+            let loaded = RwLock::new(Loaded { tree, modified });
+
+            entries.insert(name, Entry { path, loaded });
+        }
+
+        Ok(Catalog { entries })
+    }
+
+    /// Returns an iterator over the names of every index, in sorted order.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Returns the path of the index named `name`, if there is one.
+    pub fn path(&self, name: &str) -> Option<&Path> {
+        self.entries.get(name).map(|entry| entry.path.as_path())
+    }
+
+    /// Returns the tree most recently read for the index named `name`, if there is one.
+    ///
+    /// The tree is shared, so a query against it is unaffected by the index being reloaded.
+    pub fn get(&self, name: &str) -> Option<Arc<Tree>> {
+        self.entries
+            .get(name)
+            .map(|entry| Arc::clone(&entry.loaded.read().unwrap().tree))
+    }
+
+    /// Reads the index named `name` from its file again, returning the number of elements in
+    /// the new tree.  Returns `None` if there is no such index.
+    ///
+    /// # Errors
+    /// This function will return an error if the index file can't be read, in which case the
+    /// tree previously read for the index continues to be served.
+    pub fn reload(&self, name: &str) -> Option<Result<usize>> {
+        let entry = self.entries.get(name)?;
+
+        Some(entry.reload(modified(&entry.path)))
+    }
+
+    /// Reloads every index whose file has been modified since it was last read, returning the
+    /// name of each index reloaded along with the outcome.
+    ///
+    /// A file which fails to read isn't retried until it is modified again.
+    pub fn reload_modified(&self) -> Vec<(&str, Result<usize>)> {
+        self.entries
+            .iter()
+            .filter_map(|(name, entry)| {
+                let modified = modified(&entry.path);
+                if modified == entry.loaded.read().unwrap().modified {
+                    return None;
+                }
+
+                Some((name.as_str(), entry.reload(modified)))
+            })
+            .collect()
+    }
+}
+
+impl Entry {
+    /// Reads this index from its file again, recording that it was modified at `modified`.
+    fn reload(&self, modified: Option<SystemTime>) -> Result<usize> {
+        let tree = read_index(&self.path);
+
+        // The new tree is read before taking the lock, so that queries aren't held up.
+        let mut loaded = self.loaded.write().unwrap();
+        loaded.modified = modified;
+
+        let tree = tree?;
+        let len = tree.len();
+        loaded.tree = Arc::new(tree);
+
+        Ok(len)
+    }
+}
+
+/// Reads the index file at `path`, checking that it describes a valid tree.
+fn read_index(path: &Path) -> Result<Tree> {
+    let file = File::open(path).with_context(|| format!("failed to open `{}`", path.display()))?;
+
+    RTree::read_from(BufReader::new(file))
+        .with_context(|| format!("`{}` isn't a valid index", path.display()))
+}
+
+/// Returns the modification time of the file at `path`, if it can be read.
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
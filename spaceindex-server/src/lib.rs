@@ -0,0 +1,339 @@
+//! A local HTTP server answering spatial queries against index files written by the
+//! `spaceindex` command-line tool.
+//!
+//! Every response is a JSON object.  The server offers:
+//! - `GET /indexes`, listing the name, path and number of entries of every index,
+//! - `GET /indexes/{name}/point?x=..&y=..`, for the rows whose region contains a point,
+//! - `GET /indexes/{name}/bbox?minx=..&miny=..&maxx=..&maxy=..`, for the rows whose region
+//!   intersects a bounding box,
+//! - `GET /indexes/{name}/nearest?x=..&y=..&k=..`, for the `k` rows (by default `1`) closest to
+//!   a point, and
+//! - `POST /indexes/{name}/reload`, which reads an index from its file again.
+//!
+//! Queries respond with `{"results": [...]}`, where each result holds the `region` of a row as
+//! `[min_x, min_y, max_x, max_y]` along with its `fields`, and the nearest neighbour query adds
+//! the Euclidean `distance` of each row.  Failed requests respond with `{"error": "..."}`.
+mod catalog;
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use spaceindex::rtree::metric::Euclidean;
+use spaceindex::{ItemId, Rect};
+use tiny_http::{Header, Method, Request, Response};
+
+pub use crate::catalog::{Catalog, Tree};
+
+/// An HTTP server for the indexes of a [`Catalog`].
+pub struct Server {
+    http: tiny_http::Server,
+    catalog: Arc<Catalog>,
+}
+
+impl Server {
+    /// Listens for requests on `address`, to be answered from `catalog`.
+    ///
+    /// # Errors
+    /// This function will return an error if `address` can't be listened on.
+    pub fn bind<A: ToSocketAddrs>(address: A, catalog: Catalog) -> Result<Self> {
+        let http = tiny_http::Server::http(address).map_err(|error| anyhow!(error))?;
+
+        Ok(Server {
+            http,
+            catalog: Arc::new(catalog),
+        })
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.http
+            .server_addr()
+            .to_ip()
+            .expect("server is listening on a TCP socket")
+    }
+
+    /// Returns the indexes served by this server.
+    pub fn catalog(&self) -> &Arc<Catalog> {
+        &self.catalog
+    }
+
+    /// Starts a thread which checks every `interval` whether any index file has been modified,
+    /// reloading those which have.
+    pub fn watch(&self, interval: Duration) -> JoinHandle<()> {
+        let catalog = Arc::clone(&self.catalog);
+
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+
+            for (name, outcome) in catalog.reload_modified() {
+                match outcome {
+                    Ok(len) => eprintln!("reloaded `{}` with {} entries", name, len),
+                    Err(error) => eprintln!("failed to reload `{}`: {:#}", name, error),
+                }
+            }
+        })
+    }
+
+    /// Answers requests on `threads` threads.  This never returns.
+    pub fn serve(&self, threads: usize) {
+        thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| loop {
+                    match self.http.recv() {
+                        Ok(request) => self.handle(request),
+                        Err(error) => eprintln!("failed to receive a request: {}", error),
+                    }
+                });
+            }
+        });
+    }
+
+    fn handle(&self, request: Request) {
+        let (status, body) = match self.route(request.method(), request.url()) {
+            Ok(body) => (200, body),
+            Err(failure) => (failure.status, json!({ "error": failure.message })),
+        };
+
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+            );
+
+        if let Err(error) = request.respond(response) {
+            eprintln!("failed to respond to a request: {}", error);
+        }
+    }
+
+    fn route(&self, method: &Method, url: &str) -> Result<Value, Failure> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+        match segments[..] {
+            ["indexes"] => {
+                expect_method(method, Method::Get)?;
+                Ok(self.list())
+            }
+            ["indexes", name, action] => {
+                let name = percent_decode(name)?;
+                let tree = self
+                    .catalog
+                    .get(&name)
+                    .ok_or_else(|| Failure::not_found(format!("no index is named `{}`", name)))?;
+                let parameters = Parameters::parse(query)?;
+
+                match action {
+                    "point" => {
+                        expect_method(method, Method::Get)?;
+                        let point = (parameters.number("x")?, parameters.number("y")?);
+
+                        Ok(results(&tree, tree.point_lookup(point), None))
+                    }
+                    "bbox" => {
+                        expect_method(method, Method::Get)?;
+                        let region = Rect::new(
+                            (parameters.number("minx")?, parameters.number("miny")?),
+                            (parameters.number("maxx")?, parameters.number("maxy")?),
+                        );
+
+                        Ok(results(
+                            &tree,
+                            tree.region_intersection_lookup(region),
+                            None,
+                        ))
+                    }
+                    "nearest" => {
+                        expect_method(method, Method::Get)?;
+                        let point = (parameters.number("x")?, parameters.number("y")?);
+                        let k = parameters.count("k", 1)?;
+
+                        let (ids, distances) = tree
+                            .nearest_neighbors(point, k, Euclidean)
+                            .into_iter()
+                            .unzip::<_, _, Vec<_>, Vec<_>>();
+
+                        Ok(results(&tree, ids, Some(distances)))
+                    }
+                    "reload" => {
+                        expect_method(method, Method::Post)?;
+
+                        match self.catalog.reload(&name).unwrap() {
+                            Ok(len) => Ok(json!({ "name": name, "entries": len })),
+                            Err(error) => Err(Failure {
+                                status: 500,
+                                message: format!("{:#}", error),
+                            }),
+                        }
+                    }
+                    _ => Err(Failure::not_found(format!("no such query `{}`", action))),
+                }
+            }
+            _ => Err(Failure::not_found(format!("no such path `{}`", path))),
+        }
+    }
+
+    fn list(&self) -> Value {
+        let indexes = self
+            .catalog
+            .names()
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "path": self.catalog.path(name).unwrap().display().to_string(),
+                    "entries": self.catalog.get(name).unwrap().len(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "indexes": indexes })
+    }
+}
+
+/// A request which couldn't be answered, with the status to respond with.
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+impl Failure {
+    fn bad_request(message: String) -> Self {
+        Failure {
+            status: 400,
+            message,
+        }
+    }
+
+    fn not_found(message: String) -> Self {
+        Failure {
+            status: 404,
+            message,
+        }
+    }
+}
+
+fn expect_method(method: &Method, expected: Method) -> Result<(), Failure> {
+    if *method == expected {
+        Ok(())
+    } else {
+        Err(Failure {
+            status: 405,
+            message: format!("expected a {} request", expected),
+        })
+    }
+}
+
+/// The parameters in the query string of a request.
+struct Parameters(HashMap<String, String>);
+
+impl Parameters {
+    fn parse(query: &str) -> Result<Self, Failure> {
+        query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((percent_decode(key)?, percent_decode(value)?))
+            })
+            .collect::<Result<_, _>>()
+            .map(Parameters)
+    }
+
+    fn get(&self, key: &str) -> Result<&str, Failure> {
+        self.0
+            .get(key)
+            .map(String::as_str)
+            .ok_or_else(|| Failure::bad_request(format!("missing parameter `{}`", key)))
+    }
+
+    /// Returns the finite number in the parameter `key`.
+    fn number(&self, key: &str) -> Result<f64, Failure> {
+        let value = self.get(key)?;
+
+        match value.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(number),
+            _ => Err(Failure::bad_request(format!(
+                "parameter `{}` isn't a number: `{}`",
+                key, value
+            ))),
+        }
+    }
+
+    /// Returns the count in the parameter `key`, or `default` if it is missing.
+    fn count(&self, key: &str, default: usize) -> Result<usize, Failure> {
+        match self.0.get(key) {
+            Some(value) => value.parse().map_err(|_| {
+                Failure::bad_request(format!("parameter `{}` isn't a count: `{}`", key, value))
+            }),
+            None => Ok(default),
+        }
+    }
+}
+
+/// Decodes a percent-encoded component of a URL, in which `+` also stands for a space.
+fn percent_decode(component: &str) -> Result<String, Failure> {
+    let invalid = || Failure::bad_request(format!("invalid percent-encoding `{}`", component));
+
+    let mut bytes = Vec::with_capacity(component.len());
+    let mut rest = component.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest.get(..2).ok_or_else(invalid)?;
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                rest = &rest[2..];
+            }
+            byte => bytes.push(byte),
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Returns the response to a query which found the elements `ids` of `tree`, which are at
+/// `distances` if given.
+fn results(tree: &Tree, ids: Vec<ItemId>, distances: Option<Vec<f64>>) -> Value {
+    let results = ids
+        .into_iter()
+        .enumerate()
+        .map(|(position, id)| {
+            let (region, payload) = tree.get(id).expect("query returned a missing element");
+
+            let mut result = json!({
+                "region": [region.min().x, region.min().y, region.max().x, region.max().y],
+                "fields": fields(payload),
+            });
+            if let Some(distances) = &distances {
+                result["distance"] = distances[position].into();
+            }
+
+            result
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "results": results })
+}
+
+/// Splits the payload of an element, which is a single line of CSV, into its fields.
+fn fields(payload: &str) -> Vec<String> {
+    if payload.is_empty() {
+        return Vec::new();
+    }
+
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(payload.as_bytes())
+        .records()
+        .next()
+        .and_then(Result::ok)
+        .map(|record| record.iter().map(String::from).collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,68 @@
+//! `spaceindex-server`: serves spatial queries against index files as JSON over HTTP.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use spaceindex_server::{Catalog, Server};
+
+#[derive(Parser)]
+#[command(
+    name = "spaceindex-server",
+    version,
+    about = "Serve point, bounding box and nearest neighbour queries against index files over HTTP"
+)]
+struct Cli {
+    /// The index files to serve, each either as `name=path` or as a path, which is served
+    /// under the name of the file without its extension
+    #[arg(required = true)]
+    indexes: Vec<String>,
+
+    /// The address to listen on
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    bind: String,
+
+    /// The number of threads answering requests
+    #[arg(long, default_value_t = 4)]
+    threads: usize,
+
+    /// How often, in seconds, to check whether an index file has been modified and reload it;
+    /// `0` only reloads an index when asked to
+    #[arg(long, default_value_t = 2)]
+    reload_interval: u64,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let indexes = cli
+        .indexes
+        .iter()
+        .map(|index| match index.split_once('=') {
+            Some((name, path)) => Ok((String::from(name), PathBuf::from(path))),
+            None => {
+                let path = PathBuf::from(index);
+                let name = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .with_context(|| {
+                        format!("can't name the index `{}`; use `name=path`", index)
+                    })?;
+
+                Ok((String::from(name), path))
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let server = Server::bind(&cli.bind, Catalog::open(indexes)?)
+        .with_context(|| format!("failed to listen on `{}`", cli.bind))?;
+
+    if cli.reload_interval > 0 {
+        server.watch(Duration::from_secs(cli.reload_interval));
+    }
+
+    eprintln!("listening on http://{}", server.local_addr());
+    server.serve(cli.threads);
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use spaceindex::{RTree, Rect};
+use spaceindex_server::{Catalog, Server};
+
+/// The bounds `(min_x, min_y, max_x, max_y)` and payload of a row of an index.
+type Row<'a> = ((f64, f64, f64, f64), &'a str);
+
+fn write_index(path: &Path, rows: &[Row]) {
+    let tree = rows
+        .iter()
+        .map(|&((min_x, min_y, max_x, max_y), payload)| {
+            (
+                Rect::new((min_x, min_y), (max_x, max_y)),
+                String::from(payload),
+            )
+        })
+        .collect::<RTree<_, _>>();
+
+    let mut writer = BufWriter::new(File::create(path).unwrap());
+    tree.write_to(&mut writer).unwrap();
+    writer.flush().unwrap();
+}
+
+fn start(indexes: Vec<(String, PathBuf)>) -> Arc<Server> {
+    let server = Arc::new(Server::bind("127.0.0.1:0", Catalog::open(indexes).unwrap()).unwrap());
+
+    let serving = Arc::clone(&server);
+    thread::spawn(move || serving.serve(2));
+
+    server
+}
+
+/// Sends a request to `address`, returning the status and body of the response.
+fn request(address: SocketAddr, method: &str, path: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, path, address
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn test_queries() {
+    let path = std::env::temp_dir().join(format!("spaceindex-server-{}.idx", std::process::id()));
+    write_index(
+        &path,
+        &[
+            ((0.0, 0.0, 2.0, 2.0), "house,\"1 Main St, Springfield\""),
+            ((5.0, 0.0, 6.0, 1.0), "shed,"),
+            ((-4.0, -4.0, -3.0, -3.0), ""),
+        ],
+    );
+
+    let server = start(vec![(String::from("plots"), path.clone())]);
+    let address = server.local_addr();
+    assert!(address.ip().is_loopback());
+
+    let (status, body) = request(address, "GET", "/indexes");
+    assert_eq!(status, 200);
+    assert_eq!(body["indexes"][0]["name"], "plots");
+    assert_eq!(body["indexes"][0]["entries"], 3);
+
+    let (status, body) = request(address, "GET", "/indexes/plots/point?x=1&y=1.5");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({ "results": [{
+            "region": [0.0, 0.0, 2.0, 2.0],
+            "fields": ["house", "1 Main St, Springfield"],
+        }]})
+    );
+
+    let (status, body) = request(
+        address,
+        "GET",
+        "/indexes/plots/bbox?minx=-10&miny=-10&maxx=0.5&maxy=0.5",
+    );
+    assert_eq!(status, 200);
+    let mut regions = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["region"][0].as_f64().unwrap())
+        .collect::<Vec<_>>();
+    regions.sort_by(f64::total_cmp);
+    assert_eq!(regions, vec![-4.0, 0.0]);
+
+    let (status, body) = request(address, "GET", "/indexes/plots/nearest?x=7&y=0.5&k=2");
+    assert_eq!(status, 200);
+    assert_eq!(body["results"][0]["fields"], json!(["shed", ""]));
+    assert_eq!(body["results"][0]["distance"], 1.0);
+    assert_eq!(body["results"][1]["distance"], 5.0);
+
+    let (status, body) = request(address, "GET", "/indexes/plots/nearest?x=-5&y=-5");
+    assert_eq!(status, 200);
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+    assert_eq!(body["results"][0]["fields"], json!([]));
+
+    // Requests which can't be answered
+    for (method, path, expected) in [
+        ("GET", "/indexes/fields/point?x=1&y=1", 404),
+        ("GET", "/indexes/plots/circle?x=1&y=1", 404),
+        ("GET", "/elsewhere", 404),
+        ("GET", "/indexes/plots/point?x=1", 400),
+        ("GET", "/indexes/plots/point?x=1&y=north", 400),
+        ("GET", "/indexes/plots/nearest?x=1&y=1&k=-1", 400),
+        ("POST", "/indexes/plots/point?x=1&y=1", 405),
+        ("GET", "/indexes/plots/reload", 405),
+    ] {
+        let (status, body) = request(address, method, path);
+        assert_eq!(status, expected, "{} {}", method, path);
+        assert!(body["error"].is_string());
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_reload() {
+    let path = std::env::temp_dir().join(format!(
+        "spaceindex-server-reload-{}.idx",
+        std::process::id()
+    ));
+    write_index(&path, &[((0.0, 0.0, 1.0, 1.0), "old")]);
+
+    let server = start(vec![(String::from("plots"), path.clone())]);
+    let address = server.local_addr();
+
+    // A reload on request picks up the new file
+    write_index(&path, &[((0.0, 0.0, 1.0, 1.0), "new")]);
+    let (status, body) = request(address, "POST", "/indexes/plots/reload");
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({ "name": "plots", "entries": 1 }));
+
+    let (_, body) = request(address, "GET", "/indexes/plots/point?x=0.5&y=0.5");
+    assert_eq!(body["results"][0]["fields"], json!(["new"]));
+
+    // A damaged file fails to reload, and the previous tree is still served
+    std::fs::write(&path, b"not an index").unwrap();
+    let (status, body) = request(address, "POST", "/indexes/plots/reload");
+    assert_eq!(status, 500);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("isn't a valid index"));
+
+    let (_, body) = request(address, "GET", "/indexes/plots/point?x=0.5&y=0.5");
+    assert_eq!(body["results"][0]["fields"], json!(["new"]));
+
+    // Once watched, a modified file is reloaded without being asked
+    server.watch(Duration::from_millis(20));
+    write_index(
+        &path,
+        &[((0.0, 0.0, 1.0, 1.0), "newer"), ((0.0, 0.0, 2.0, 2.0), "")],
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let (_, body) = request(address, "GET", "/indexes");
+        if body["indexes"][0]["entries"] == 2 {
+            break;
+        }
+
+        assert!(Instant::now() < deadline, "index was never reloaded");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
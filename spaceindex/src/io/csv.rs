@@ -0,0 +1,146 @@
+//! Loading trees from CSV files of bounding boxes or points.
+use std::io::Read;
+
+use geo_types::Rect;
+
+use crate::rtree::{RTree, RTreeError};
+
+/// The columns of a CSV file holding the region of each row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Columns {
+    /// Each row holds a bounding box, given by its minimum and maximum coordinates.
+    Bounds {
+        min_x: String,
+        min_y: String,
+        max_x: String,
+        max_y: String,
+    },
+
+    /// Each row holds a point, which is indexed as a zero-area region.
+    Point { x: String, y: String },
+}
+
+impl Columns {
+    /// Bounding boxes in the columns `minx`, `miny`, `maxx` and `maxy`.
+    pub fn bounds() -> Self {
+        Columns::Bounds {
+            min_x: String::from("minx"),
+            min_y: String::from("miny"),
+            max_x: String::from("maxx"),
+            max_y: String::from("maxy"),
+        }
+    }
+
+    /// Points in the columns `x` and `y`.
+    pub fn point() -> Self {
+        Columns::Point {
+            x: String::from("x"),
+            y: String::from("y"),
+        }
+    }
+
+    /// Returns the names of the columns, in the order they are used to build a region.
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Columns::Bounds {
+                min_x,
+                min_y,
+                max_x,
+                max_y,
+            } => vec![min_x, min_y, max_x, max_y],
+            Columns::Point { x, y } => vec![x, y],
+        }
+    }
+}
+
+/// Builds a tree from the CSV read from `reader`, whose first line must be a header naming its
+/// columns.  The region of each row is read from `columns`, and the values in the remaining
+/// columns, in the order they appear, are kept as its data.
+///
+/// The tree is built with [`RTree::bulk_load`].
+///
+/// # Example
+/// ```rust
+/// use spaceindex::io::csv::{self, Columns};
+/// use spaceindex::Rect;
+///
+/// let data = "name,minx,miny,maxx,maxy\nhouse,0,0,2,2\nshed,5,0,6,1\n";
+/// let tree = csv::load(data.as_bytes(), &Columns::bounds()).unwrap();
+///
+/// let (region, data) = tree.get(tree.point_lookup((1.0, 1.0))[0]).unwrap();
+/// assert_eq!(*region, Rect::new((0.0, 0.0), (2.0, 2.0)));
+/// assert_eq!(data, &vec![String::from("house")]);
+/// # tree.validate_consistency();
+/// ```
+///
+/// # Errors
+/// This function will return [`RTreeError::Csv`], along with the line at fault, if the header
+/// is missing any of `columns`, if any row can't be read, or if any coordinate isn't a finite
+/// number.
+pub fn load<R: Read>(reader: R, columns: &Columns) -> Result<RTree<Vec<String>, f64>, RTreeError> {
+    let mut reader = csv::Reader::from_reader(reader);
+
+    let header = reader.headers().map_err(csv_error)?.clone();
+    let names = columns.names();
+    let positions = names
+        .iter()
+        .map(|name| {
+            header
+                .iter()
+                .position(|column| column == *name)
+                .ok_or_else(|| RTreeError::Csv {
+                    line: 1,
+                    message: format!("missing column `{}`", name),
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let line = record.position().map_or(0, |position| position.line());
+
+        let coordinates = positions
+            .iter()
+            .zip(&names)
+            .map(|(&position, name)| {
+                let value = record.get(position).unwrap_or_default();
+
+                match value.trim().parse::<f64>() {
+                    Ok(coordinate) if coordinate.is_finite() => Ok(coordinate),
+                    _ => Err(RTreeError::Csv {
+                        line,
+                        message: format!("invalid value `{}` in column `{}`", value, name),
+                    }),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let region = match coordinates[..] {
+            [min_x, min_y, max_x, max_y] => Rect::new((min_x, min_y), (max_x, max_y)),
+            [x, y] => Rect::new((x, y), (x, y)),
+            _ => unreachable!(),
+        };
+
+        let data = record
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| !positions.contains(position))
+            .map(|(_, value)| String::from(value))
+            .collect();
+
+        entries.push((region, data));
+    }
+
+    Ok(RTree::bulk_load(entries))
+}
+
+/// Converts an error from the CSV reader, keeping the line it occurred on.
+fn csv_error(error: csv::Error) -> RTreeError {
+    let line = error.position().map_or(0, |position| position.line());
+
+    RTreeError::Csv {
+        line,
+        message: error.to_string(),
+    }
+}
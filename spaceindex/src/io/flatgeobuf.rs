@@ -0,0 +1,416 @@
+//! Writing and querying the packed Hilbert R-tree index of a
+//! [FlatGeobuf](https://flatgeobuf.org/) file.
+//!
+//! A FlatGeobuf file optionally holds a spatial index between its header and its features.
+//! The index is a static R-tree in which every node has `node_size` children (except the last
+//! node of each level), built bottom up over the features sorted along a Hilbert curve.  The
+//! nodes are stored level by level, starting with the root and ending with one node for every
+//! feature.  Each node is 40 bytes long, holding its bounding box as `min x, min y, max x,
+//! max y` followed by an offset (a `u64`), all little-endian.  The offset of a feature's node is
+//! the byte offset of the feature within the features section of the file, and the offset of
+//! any other node is the position of its first child.
+//!
+//! The number of features and the node size are stored in the header of the file, and are
+//! needed to interpret the index.
+use std::cmp::Reverse;
+use std::io::Write;
+
+use geo_types::Rect;
+
+use crate::rtree::{ItemId, RTree, RTreeError};
+
+/// The node size used by FlatGeobuf unless told otherwise.
+pub const DEFAULT_NODE_SIZE: u16 = 16;
+
+/// The length of each node in the index.
+const NODE_LENGTH: usize = 40;
+
+/// The largest coordinate on the Hilbert curve used to sort features.
+const HILBERT_MAX: f64 = ((1 << 16) - 1) as f64;
+
+/// A feature found by querying a [`PackedHilbertIndex`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hit {
+    /// The position of the feature in the features section.
+    pub index: usize,
+
+    /// The byte offset of the feature within the features section.
+    pub offset: u64,
+}
+
+impl<ND> RTree<ND, f64> {
+    /// Writes a FlatGeobuf spatial index over the elements of this tree to `writer`.
+    ///
+    /// The features of the file must be written in the order of the returned ids, which is
+    /// the order of the elements along a Hilbert curve.  `feature_length` gives the length in
+    /// bytes that the feature of each element will take up in the features section, so that
+    /// the index can store the offset of each feature.  Nothing is written for an empty tree,
+    /// as FlatGeobuf files without features have no index.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::io::flatgeobuf::{PackedHilbertIndex, DEFAULT_NODE_SIZE};
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let tree = (0..100)
+    ///     .map(|i| (Rect::new((i as f64, 0.0), (i as f64 + 1.0, 1.0)), i))
+    ///     .collect::<RTree<_, _>>();
+    ///
+    /// // Each feature takes up 64 bytes.
+    /// let mut bytes = Vec::new();
+    /// let order = tree
+    ///     .write_flatgeobuf_index(&mut bytes, DEFAULT_NODE_SIZE, |_, _| 64)
+    ///     .unwrap();
+    ///
+    /// let index = PackedHilbertIndex::new(bytes, tree.len(), DEFAULT_NODE_SIZE).unwrap();
+    /// let hits = index.region_intersection_lookup(Rect::new((10.2, 0.5), (10.8, 0.5)));
+    /// assert_eq!(hits.len(), 1);
+    /// assert_eq!(hits[0].offset, 64 * hits[0].index as u64);
+    /// assert_eq!(*tree.get(order[hits[0].index]).unwrap().1, 10);
+    /// ```
+    ///
+    /// # Panics
+    /// This function will panic if `node_size` is less than 2.
+    ///
+    /// # Errors
+    /// This function will return an error if writing to `writer` fails.
+    pub fn write_flatgeobuf_index<W, F>(
+        &self,
+        mut writer: W,
+        node_size: u16,
+        mut feature_length: F,
+    ) -> Result<Vec<ItemId>, RTreeError>
+    where
+        W: Write,
+        F: FnMut(ItemId, &ND) -> u64,
+    {
+        assert!(node_size >= 2);
+
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Sort the elements along a Hilbert curve over the extent of the tree.
+        let extent = self.root_node().get_region();
+        let mut items = self
+            .iter()
+            .map(|(id, region, data)| (hilbert_value(region, &extent), id, *region, data))
+            .collect::<Vec<_>>();
+        items.sort_by_key(|&(hilbert_value, ..)| Reverse(hilbert_value));
+
+        let level_bounds = level_bounds(items.len(), node_size as usize);
+        let node_count = level_bounds[0].1;
+        let mut nodes = vec![(Rect::new((0.0, 0.0), (0.0, 0.0)), 0u64); node_count];
+
+        let mut offset = 0;
+        let leaf_start = level_bounds[0].0;
+        for (position, (_, id, region, data)) in items.iter().enumerate() {
+            nodes[leaf_start + position] = (*region, offset);
+            offset += feature_length(*id, data);
+        }
+
+        // Build each level of nodes from the level below it.
+        for level in 0..level_bounds.len() - 1 {
+            let (start, end) = level_bounds[level];
+            let parents = level_bounds[level + 1].0..;
+
+            for (parent, first_child) in parents.zip((start..end).step_by(node_size as usize)) {
+                let last_child = (first_child + node_size as usize).min(end);
+                let region = nodes[first_child..last_child]
+                    .iter()
+                    .map(|(region, _)| *region)
+                    .reduce(combine)
+                    .unwrap();
+
+                nodes[parent] = (region, first_child as u64);
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(node_count * NODE_LENGTH);
+        for (region, offset) in nodes {
+            for value in [
+                region.min().x,
+                region.min().y,
+                region.max().x,
+                region.max().y,
+            ] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+
+        Ok(items.into_iter().map(|(_, id, _, _)| id).collect())
+    }
+}
+
+/// A FlatGeobuf spatial index, queried directly over its bytes.
+#[derive(Clone, Debug)]
+pub struct PackedHilbertIndex<B>
+where
+    B: AsRef<[u8]>,
+{
+    bytes: B,
+
+    /// The number of features in the file.
+    len: usize,
+
+    /// The number of children of each node.
+    node_size: usize,
+
+    /// The range of node positions making up each level, from the features up to the root.
+    level_bounds: Vec<(usize, usize)>,
+}
+
+impl<B> PackedHilbertIndex<B>
+where
+    B: AsRef<[u8]>,
+{
+    /// Wraps the index `bytes` of a FlatGeobuf file with `len` features and the given node
+    /// size, both of which are taken from the header of the file.
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::Corrupt`] if `node_size` is less than 2, or if
+    /// `bytes` doesn't have the length of such an index.
+    pub fn new(bytes: B, len: usize, node_size: u16) -> Result<Self, RTreeError> {
+        if node_size < 2 {
+            return Err(RTreeError::Corrupt("index node size is less than 2"));
+        }
+
+        // Every feature has its own node, which bounds the work done below for huge `len`.
+        if len > bytes.as_ref().len() / NODE_LENGTH {
+            return Err(RTreeError::Corrupt("index has the wrong length"));
+        }
+
+        let level_bounds = if len == 0 {
+            Vec::new()
+        } else {
+            level_bounds(len, node_size as usize)
+        };
+
+        let expected_length = level_bounds
+            .first()
+            .map_or(0, |&(_, end)| end * NODE_LENGTH);
+        if bytes.as_ref().len() != expected_length {
+            return Err(RTreeError::Corrupt("index has the wrong length"));
+        }
+
+        Ok(Self {
+            bytes,
+            len,
+            node_size: node_size as usize,
+            level_bounds,
+        })
+    }
+
+    /// Returns the number of features covered by the index.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the index covers no features, `false` otherwise.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the bounding box of every feature in the index, or `None` if it is empty.
+    pub fn extent(&self) -> Option<Rect<f64>> {
+        (!self.is_empty()).then(|| {
+            let ([min_x, min_y, max_x, max_y], _) = self.node(0);
+            Rect::new((min_x, min_y), (max_x, max_y))
+        })
+    }
+
+    /// Returns the features whose bounding box intersects the given region, with boundaries
+    /// included, ordered by their position in the features section.
+    pub fn region_intersection_lookup(&self, region: Rect<f64>) -> Vec<Hit> {
+        let mut hits = Vec::new();
+
+        if self.is_empty() {
+            return hits;
+        }
+
+        // Pairs `(position, level)` of nodes whose children still need to be checked.  The
+        // children of a node are found from the layout rather than its stored offset, so a
+        // damaged index can't send the search out of bounds.
+        let root_level = self.level_bounds.len() - 1;
+        let mut work_queue = vec![(0, root_level)];
+
+        while let Some((position, level)) = work_queue.pop() {
+            let (level_start, _) = self.level_bounds[level];
+            let (child_start, child_end) = self.level_bounds[level - 1];
+
+            let first_child = child_start + (position - level_start) * self.node_size;
+            let last_child = (first_child + self.node_size).min(child_end);
+
+            for child in first_child..last_child {
+                let (bounds, offset) = self.node(child);
+                if !intersects(bounds, &region) {
+                    continue;
+                }
+
+                if level == 1 {
+                    hits.push(Hit {
+                        index: child - child_start,
+                        offset,
+                    });
+                } else {
+                    work_queue.push((child, level - 1));
+                }
+            }
+        }
+
+        hits.sort_unstable();
+        hits
+    }
+
+    /// Returns the features whose bounding box contains the given point, with boundaries
+    /// included, ordered by their position in the features section.
+    pub fn point_lookup(&self, x: f64, y: f64) -> Vec<Hit> {
+        self.region_intersection_lookup(Rect::new((x, y), (x, y)))
+    }
+
+    /// Returns the bounds `[min x, min y, max x, max y]` and offset of the node at position
+    /// `position`.
+    fn node(&self, position: usize) -> ([f64; 4], u64) {
+        let bytes = &self.bytes.as_ref()[position * NODE_LENGTH..(position + 1) * NODE_LENGTH];
+        let value =
+            |offset: usize| f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+        (
+            [value(0), value(8), value(16), value(24)],
+            u64::from_le_bytes(bytes[32..].try_into().unwrap()),
+        )
+    }
+}
+
+/// Returns the length in bytes of the index over `len` features with the given node size.
+///
+/// # Panics
+/// This function will panic if `node_size` is less than 2.
+pub fn index_length(len: usize, node_size: u16) -> usize {
+    assert!(node_size >= 2);
+
+    if len == 0 {
+        return 0;
+    }
+
+    level_bounds(len, node_size as usize)[0].1 * NODE_LENGTH
+}
+
+/// Returns the range of node positions making up each level of an index over `len > 0` features
+/// with the given node size, starting from the features and ending with the root.
+fn level_bounds(len: usize, node_size: usize) -> Vec<(usize, usize)> {
+    let mut level_lengths = vec![len];
+    let mut n = len;
+    loop {
+        n = n.div_ceil(node_size);
+        level_lengths.push(n);
+        if n == 1 {
+            break;
+        }
+    }
+
+    // Levels are stored starting from the root, so the features come last.
+    let mut end = level_lengths.iter().sum::<usize>();
+    level_lengths
+        .into_iter()
+        .map(|length| {
+            let bounds = (end - length, end);
+            end -= length;
+            bounds
+        })
+        .collect()
+}
+
+/// Returns `true` if the node bounds `[min x, min y, max x, max y]` intersect `region`, with
+/// boundaries included.
+fn intersects([min_x, min_y, max_x, max_y]: [f64; 4], region: &Rect<f64>) -> bool {
+    min_x <= region.max().x
+        && min_y <= region.max().y
+        && max_x >= region.min().x
+        && max_y >= region.min().y
+}
+
+/// Returns the smallest region containing both `left` and `right`.
+fn combine(left: Rect<f64>, right: Rect<f64>) -> Rect<f64> {
+    Rect::new(
+        (
+            left.min().x.min(right.min().x),
+            left.min().y.min(right.min().y),
+        ),
+        (
+            left.max().x.max(right.max().x),
+            left.max().y.max(right.max().y),
+        ),
+    )
+}
+
+/// Returns the position of the center of `region` along a Hilbert curve covering `extent`,
+/// computed exactly as FlatGeobuf does.
+fn hilbert_value(region: &Rect<f64>, extent: &Rect<f64>) -> u32 {
+    let scale = |center: f64, min: f64, length: f64| {
+        if length == 0.0 {
+            0
+        } else {
+            (HILBERT_MAX * (center - min) / length).floor() as u32
+        }
+    };
+
+    let x = scale(region.center().x, extent.min().x, extent.width());
+    let y = scale(region.center().y, extent.min().y, extent.height());
+
+    hilbert(x, y)
+}
+
+/// Returns the position of the point `(x, y)` along a Hilbert curve of order 16, using the
+/// branchless algorithm from <https://github.com/rawrunprotected/hilbert_curves>.
+pub(crate) fn hilbert(x: u32, y: u32) -> u32 {
+    let mut a = x ^ y;
+    let mut b = 0xFFFF ^ a;
+    let mut c = 0xFFFF ^ (x | y);
+    let mut d = x & (y ^ 0xFFFF);
+
+    let mut big_a = a | (b >> 1);
+    let mut big_b = (a >> 1) ^ a;
+    let mut big_c = ((c >> 1) ^ (b & (d >> 1))) ^ c;
+    let mut big_d = ((a & (c >> 1)) ^ (d >> 1)) ^ d;
+
+    for shift in [2, 4] {
+        a = big_a;
+        b = big_b;
+        c = big_c;
+        d = big_d;
+
+        big_a = (a & (a >> shift)) ^ (b & (b >> shift));
+        big_b = (a & (b >> shift)) ^ (b & ((a ^ b) >> shift));
+        big_c ^= (a & (c >> shift)) ^ (b & (d >> shift));
+        big_d ^= (b & (c >> shift)) ^ ((a ^ b) & (d >> shift));
+    }
+
+    a = big_a;
+    b = big_b;
+    c = big_c;
+    d = big_d;
+
+    big_c ^= (a & (c >> 8)) ^ (b & (d >> 8));
+    big_d ^= (b & (c >> 8)) ^ ((a ^ b) & (d >> 8));
+
+    a = big_c ^ (big_c >> 1);
+    b = big_d ^ (big_d >> 1);
+
+    let interleave = |mut value: u32| {
+        value = (value | (value << 8)) & 0x00FF_00FF;
+        value = (value | (value << 4)) & 0x0F0F_0F0F;
+        value = (value | (value << 2)) & 0x3333_3333;
+        (value | (value << 1)) & 0x5555_5555
+    };
+
+    let i0 = x ^ y;
+    let i1 = b | (0xFFFF ^ (i0 | a));
+
+    (interleave(i1) << 1) | interleave(i0)
+}
@@ -0,0 +1,169 @@
+//! Importing trees from, and exporting trees to, [GeoJSON](https://geojson.org/).
+use std::io::Read;
+
+use geo::bounding_rect::BoundingRect;
+use geo_types::{Geometry, Rect};
+use geojson::{Feature, FeatureCollection, GeoJson, JsonObject, JsonValue};
+use serde::Serialize;
+
+use crate::rtree::{Index, RTree, RTreeError};
+
+impl RTree<Option<JsonObject>, f64> {
+    /// Builds a tree from the GeoJSON document read from `reader`, which may be a
+    /// `FeatureCollection`, a single `Feature` or a bare geometry.  Every feature is indexed by
+    /// the bounding box of its geometry, and its properties are kept as its data.
+    ///
+    /// The tree is built with [`RTree::bulk_load`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let document = r#"{
+    ///     "type": "FeatureCollection",
+    ///     "features": [{
+    ///         "type": "Feature",
+    ///         "geometry": { "type": "LineString", "coordinates": [[0.0, 0.0], [2.0, 1.0]] },
+    ///         "properties": { "name": "fence" }
+    ///     }]
+    /// }"#;
+    ///
+    /// let tree = RTree::from_geojson(document.as_bytes()).unwrap();
+    ///
+    /// let (region, properties) = tree.get(tree.point_lookup((1.0, 0.5))[0]).unwrap();
+    /// assert_eq!(*region, Rect::new((0.0, 0.0), (2.0, 1.0)));
+    /// assert_eq!(properties.as_ref().unwrap()["name"], "fence");
+    /// # tree.validate_consistency();
+    /// ```
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::GeoJson`] if the input isn't valid GeoJSON, or
+    /// if any feature has no geometry or an empty geometry.
+    pub fn from_geojson<R: Read>(reader: R) -> Result<Self, RTreeError> {
+        let features = match GeoJson::from_reader(reader).map_err(geojson::Error::from)? {
+            GeoJson::FeatureCollection(collection) => collection.features,
+            GeoJson::Feature(feature) => vec![feature],
+            GeoJson::Geometry(geometry) => vec![Feature::from(geometry)],
+        };
+
+        let entries = features
+            .into_iter()
+            .map(|feature| {
+                let region = feature
+                    .geometry
+                    .as_ref()
+                    .map(Geometry::<f64>::try_from)
+                    .transpose()?
+                    .and_then(|geometry| geometry.bounding_rect());
+
+                match region {
+                    Some(region) => Ok((region, feature.properties)),
+                    None => Err(geojson::Error::FeatureHasNoGeometry(feature).into()),
+                }
+            })
+            .collect::<Result<Vec<_>, RTreeError>>()?;
+
+        Ok(Self::bulk_load(entries))
+    }
+}
+
+impl<ND> RTree<ND, f64> {
+    /// Exports the tree as a GeoJSON `FeatureCollection`.
+    ///
+    /// Every element of the tree becomes a feature whose geometry is its region.  If its data
+    /// serializes to a JSON object, that object becomes the properties of the feature;
+    /// otherwise its data is stored under the `data` property.  Data which serializes to `null`
+    /// gives a feature without properties, so a tree built by [`RTree::from_geojson`] exports
+    /// its properties unchanged.
+    ///
+    /// If `include_nodes` is `true`, the minimum bounding region of every internal node is
+    /// also exported, as a feature whose only property is the `depth` of the node, starting
+    /// from `0` at the root.  This can be used to inspect the shape of a tree in a GIS.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 7).unwrap();
+    ///
+    /// let collection = tree.to_geojson(true).unwrap();
+    /// assert_eq!(collection.features.len(), 2);
+    ///
+    /// let depths = collection
+    ///     .features
+    ///     .iter()
+    ///     .filter_map(|feature| feature.property("depth"))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(depths, vec![0]);
+    /// ```
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::GeoJson`] if the data of any element fails to
+    /// serialize.
+    pub fn to_geojson(&self, include_nodes: bool) -> Result<FeatureCollection, RTreeError>
+    where
+        ND: Serialize,
+    {
+        let mut features = Vec::with_capacity(self.len());
+
+        if !self.is_empty() {
+            self.export_node(self.root_index(), 0, include_nodes, &mut features)?;
+        }
+
+        Ok(FeatureCollection {
+            bbox: None,
+            features,
+            foreign_members: None,
+        })
+    }
+
+    /// Appends features for the subtree of `index`, which is at depth `depth`, to `features`.
+    fn export_node(
+        &self,
+        index: Index,
+        depth: usize,
+        include_nodes: bool,
+        features: &mut Vec<Feature>,
+    ) -> Result<(), RTreeError>
+    where
+        ND: Serialize,
+    {
+        let node = self.get_node(index);
+
+        match node.get_data() {
+            Some(data) => {
+                let properties = match serde_json::to_value(data).map_err(geojson::Error::from)? {
+                    JsonValue::Object(properties) => Some(properties),
+                    JsonValue::Null => None,
+                    value => Some(JsonObject::from_iter([(String::from("data"), value)])),
+                };
+
+                features.push(region_feature(node.get_region(), properties));
+            }
+            None => {
+                if include_nodes {
+                    let properties = JsonObject::from_iter([(String::from("depth"), depth.into())]);
+                    features.push(region_feature(node.get_region(), Some(properties)));
+                }
+
+                for child_index in node.child_index_iter() {
+                    self.export_node(child_index, depth + 1, include_nodes, features)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns a feature whose geometry is the polygon `region`, with properties `properties`.
+fn region_feature(region: Rect<f64>, properties: Option<JsonObject>) -> Feature {
+    Feature {
+        bbox: None,
+        geometry: Some((&region.to_polygon()).into()),
+        id: None,
+        properties,
+        foreign_members: None,
+    }
+}
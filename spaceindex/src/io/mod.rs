@@ -0,0 +1,11 @@
+//! Loading trees from, and exporting trees to, common interchange formats.
+//!
+//! Each format is behind a feature of the same name.
+#[cfg(feature = "csv")]
+pub mod csv;
+#[cfg(feature = "flatgeobuf")]
+pub mod flatgeobuf;
+#[cfg(feature = "geojson")]
+pub mod geojson;
+#[cfg(feature = "wkt")]
+pub mod wkt;
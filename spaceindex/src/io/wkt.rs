@@ -0,0 +1,449 @@
+//! Indexing geometries given as Well-Known Text (WKT) or Well-Known Binary (WKB).
+//!
+//! Geometries are indexed by their bounding box, and kept as the data of their element so they
+//! can be written back out again.  WKT is parsed by the [`wkt`](::wkt) crate.  WKB is read and
+//! written by this module: both byte orders are read, along with the SRID of PostGIS extended
+//! WKB (which is discarded), while geometries are always written as little-endian 2D WKB.
+//! Geometries with Z or M coordinates aren't supported.
+use geo::bounding_rect::BoundingRect;
+use geo::coords_iter::CoordsIter;
+use geo_types::{
+    Coordinate, Geometry, GeometryCollection, LineString, MultiLineString, MultiPoint,
+    MultiPolygon, Point, Polygon, Rect,
+};
+use wkt::{ToWkt, TryFromWkt};
+
+use crate::rtree::{ItemId, RTree, RTreeError};
+
+/// The geometry type codes used by WKB.
+const WKB_POINT: u32 = 1;
+const WKB_LINE_STRING: u32 = 2;
+const WKB_POLYGON: u32 = 3;
+const WKB_MULTI_POINT: u32 = 4;
+const WKB_MULTI_LINE_STRING: u32 = 5;
+const WKB_MULTI_POLYGON: u32 = 6;
+const WKB_GEOMETRY_COLLECTION: u32 = 7;
+
+/// Set in the geometry type of PostGIS extended WKB if an SRID follows the type.
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+
+/// Geometry collections nested deeper than this are rejected, to bound recursion on malicious
+/// input.
+const MAX_NESTING: usize = 32;
+
+impl RTree<Geometry<f64>, f64> {
+    /// Builds a tree from the given WKT strings, using the bounding box of each geometry as its
+    /// region and keeping the geometry as its data.
+    ///
+    /// The tree is built with [`RTree::bulk_load`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let tree = RTree::from_wkt(["POINT(1 2)", "LINESTRING(0 0, 4 3)"]).unwrap();
+    ///
+    /// let hits = tree.region_lookup(Rect::new((1.0, 1.0), (2.0, 2.0)));
+    /// assert_eq!(tree.get(hits[0]).unwrap().0, &Rect::new((0.0, 0.0), (4.0, 3.0)));
+    /// # tree.validate_consistency();
+    /// ```
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::Wkt`] if any string isn't valid WKT, or
+    /// describes an empty geometry or one with non-finite coordinates.
+    pub fn from_wkt<I, S>(wkts: I) -> Result<Self, RTreeError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let entries = wkts
+            .into_iter()
+            .map(|wkt| {
+                let geometry = parse_wkt(wkt.as_ref())?;
+                let region = geometry_region(&geometry).ok_or_else(|| {
+                    RTreeError::Wkt(String::from("geometry is empty or not finite"))
+                })?;
+
+                Ok((region, geometry))
+            })
+            .collect::<Result<Vec<_>, RTreeError>>()?;
+
+        Ok(Self::bulk_load(entries))
+    }
+
+    /// Builds a tree from the given WKB geometries, using the bounding box of each geometry as
+    /// its region and keeping the geometry as its data.
+    ///
+    /// The tree is built with [`RTree::bulk_load`].
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::Wkb`] if any geometry isn't valid WKB, or is
+    /// empty or has non-finite coordinates.
+    pub fn from_wkb<I, B>(wkbs: I) -> Result<Self, RTreeError>
+    where
+        I: IntoIterator<Item = B>,
+        B: AsRef<[u8]>,
+    {
+        let entries = wkbs
+            .into_iter()
+            .map(|wkb| {
+                let geometry = read_wkb(wkb.as_ref())?;
+                let region = geometry_region(&geometry)
+                    .ok_or(RTreeError::Wkb("geometry is empty or not finite"))?;
+
+                Ok((region, geometry))
+            })
+            .collect::<Result<Vec<_>, RTreeError>>()?;
+
+        Ok(Self::bulk_load(entries))
+    }
+
+    /// Parses the WKT string `wkt` and inserts the geometry into the tree, using its bounding
+    /// box as its region.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::RTree;
+    ///
+    /// let mut tree = RTree::new();
+    /// let id = tree.insert_wkt("POLYGON((0 0, 2 0, 2 2, 0 0))").unwrap();
+    ///
+    /// assert_eq!(tree.point_lookup((1.0, 1.0)), vec![id]);
+    /// assert_eq!(tree.wkt_iter().next().unwrap().1, "POLYGON((0 0,2 0,2 2,0 0))");
+    /// ```
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::Wkt`] if `wkt` isn't valid WKT, or describes an
+    /// empty geometry or one with non-finite coordinates.
+    pub fn insert_wkt(&mut self, wkt: &str) -> Result<ItemId, RTreeError> {
+        let geometry = parse_wkt(wkt)?;
+        let region = geometry_region(&geometry)
+            .ok_or_else(|| RTreeError::Wkt(String::from("geometry is empty or not finite")))?;
+
+        self.insert(region, geometry)
+    }
+
+    /// Reads the WKB geometry `wkb` and inserts it into the tree, using its bounding box as its
+    /// region.
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::Wkb`] if `wkb` isn't valid WKB, or is empty or
+    /// has non-finite coordinates.
+    pub fn insert_wkb(&mut self, wkb: &[u8]) -> Result<ItemId, RTreeError> {
+        let geometry = read_wkb(wkb)?;
+        let region =
+            geometry_region(&geometry).ok_or(RTreeError::Wkb("geometry is empty or not finite"))?;
+
+        self.insert(region, geometry)
+    }
+
+    /// Returns an iterator over the elements of the tree, with each geometry written as WKT.
+    pub fn wkt_iter(&self) -> impl Iterator<Item = (ItemId, String)> + '_ {
+        self.iter()
+            .map(|(id, _, geometry)| (id, geometry.wkt_string()))
+    }
+
+    /// Returns an iterator over the elements of the tree, with each geometry written as WKB.
+    pub fn wkb_iter(&self) -> impl Iterator<Item = (ItemId, Vec<u8>)> + '_ {
+        self.iter().map(|(id, _, geometry)| {
+            let mut wkb = Vec::new();
+            write_wkb(geometry, &mut wkb);
+            (id, wkb)
+        })
+    }
+}
+
+/// Parses the WKT string `wkt` as a geometry.
+fn parse_wkt(wkt: &str) -> Result<Geometry<f64>, RTreeError> {
+    Geometry::try_from_wkt_str(wkt).map_err(|error| RTreeError::Wkt(error.to_string()))
+}
+
+/// Returns the bounding box of `geometry`, or `None` if it is empty or has a non-finite
+/// coordinate.
+fn geometry_region(geometry: &Geometry<f64>) -> Option<Rect<f64>> {
+    if geometry
+        .coords_iter()
+        .any(|coordinate| !coordinate.x.is_finite() || !coordinate.y.is_finite())
+    {
+        return None;
+    }
+
+    geometry.bounding_rect()
+}
+
+/// Appends the little-endian WKB encoding of `geometry` to `buffer`.  Lines, rectangles and
+/// triangles are written as line strings and polygons, as WKB has no equivalent types.
+pub fn write_wkb(geometry: &Geometry<f64>, buffer: &mut Vec<u8>) {
+    match geometry {
+        Geometry::Point(point) => {
+            put_header(buffer, WKB_POINT);
+            put_coordinate(buffer, point.0);
+        }
+        Geometry::Line(line) => {
+            put_header(buffer, WKB_LINE_STRING);
+            put_coordinates(buffer, [line.start, line.end]);
+        }
+        Geometry::LineString(line_string) => {
+            put_header(buffer, WKB_LINE_STRING);
+            put_coordinates(buffer, line_string.0.iter().copied());
+        }
+        Geometry::Polygon(polygon) => put_polygon(buffer, polygon),
+        Geometry::MultiPoint(multi_point) => {
+            put_header(buffer, WKB_MULTI_POINT);
+            put_count(buffer, multi_point.0.len());
+            for point in &multi_point.0 {
+                write_wkb(&Geometry::Point(*point), buffer);
+            }
+        }
+        Geometry::MultiLineString(multi_line_string) => {
+            put_header(buffer, WKB_MULTI_LINE_STRING);
+            put_count(buffer, multi_line_string.0.len());
+            for line_string in &multi_line_string.0 {
+                put_header(buffer, WKB_LINE_STRING);
+                put_coordinates(buffer, line_string.0.iter().copied());
+            }
+        }
+        Geometry::MultiPolygon(multi_polygon) => {
+            put_header(buffer, WKB_MULTI_POLYGON);
+            put_count(buffer, multi_polygon.0.len());
+            for polygon in &multi_polygon.0 {
+                put_polygon(buffer, polygon);
+            }
+        }
+        Geometry::GeometryCollection(collection) => {
+            put_header(buffer, WKB_GEOMETRY_COLLECTION);
+            put_count(buffer, collection.0.len());
+            for geometry in &collection.0 {
+                write_wkb(geometry, buffer);
+            }
+        }
+        Geometry::Rect(rect) => put_polygon(buffer, &rect.to_polygon()),
+        Geometry::Triangle(triangle) => put_polygon(buffer, &triangle.to_polygon()),
+    }
+}
+
+/// Reads a geometry from the WKB bytes `wkb`, which must hold exactly one geometry.
+///
+/// # Errors
+/// This function will return [`RTreeError::Wkb`] if `wkb` isn't a valid, supported WKB
+/// geometry.
+pub fn read_wkb(wkb: &[u8]) -> Result<Geometry<f64>, RTreeError> {
+    let mut reader = WkbReader {
+        bytes: wkb,
+        little_endian: true,
+    };
+    let geometry = reader.geometry(0)?;
+
+    if !reader.bytes.is_empty() {
+        return Err(RTreeError::Wkb("trailing bytes after geometry"));
+    }
+
+    Ok(geometry)
+}
+
+fn put_header(buffer: &mut Vec<u8>, kind: u32) {
+    buffer.push(1);
+    buffer.extend_from_slice(&kind.to_le_bytes());
+}
+
+fn put_count(buffer: &mut Vec<u8>, count: usize) {
+    buffer.extend_from_slice(&(count as u32).to_le_bytes());
+}
+
+fn put_coordinate(buffer: &mut Vec<u8>, coordinate: Coordinate<f64>) {
+    buffer.extend_from_slice(&coordinate.x.to_le_bytes());
+    buffer.extend_from_slice(&coordinate.y.to_le_bytes());
+}
+
+fn put_coordinates<I>(buffer: &mut Vec<u8>, coordinates: I)
+where
+    I: IntoIterator<Item = Coordinate<f64>>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let coordinates = coordinates.into_iter();
+    put_count(buffer, coordinates.len());
+    for coordinate in coordinates {
+        put_coordinate(buffer, coordinate);
+    }
+}
+
+fn put_polygon(buffer: &mut Vec<u8>, polygon: &Polygon<f64>) {
+    put_header(buffer, WKB_POLYGON);
+
+    // An empty polygon has no rings at all, rather than an empty exterior ring.
+    if polygon.exterior().0.is_empty() {
+        put_count(buffer, 0);
+        return;
+    }
+
+    put_count(buffer, 1 + polygon.interiors().len());
+    for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+        put_coordinates(buffer, ring.0.iter().copied());
+    }
+}
+
+/// Reads WKB geometries from a byte slice, consuming bytes as they are read.
+struct WkbReader<'a> {
+    bytes: &'a [u8],
+
+    /// The byte order of the geometry currently being read.
+    little_endian: bool,
+}
+
+impl<'a> WkbReader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], RTreeError> {
+        if self.bytes.len() < N {
+            return Err(RTreeError::Wkb("unexpected end of input"));
+        }
+
+        let (bytes, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, RTreeError> {
+        let bytes = self.take()?;
+
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn f64(&mut self) -> Result<f64, RTreeError> {
+        let bytes = self.take()?;
+
+        Ok(if self.little_endian {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        })
+    }
+
+    /// Reads a count of items, each at least `item_length` bytes long.  Counts which can't
+    /// possibly fit in the remaining input are rejected before anything is allocated.
+    fn count(&mut self, item_length: usize) -> Result<usize, RTreeError> {
+        let count = self.u32()? as usize;
+
+        if count.saturating_mul(item_length) > self.bytes.len() {
+            return Err(RTreeError::Wkb("count is larger than the input"));
+        }
+
+        Ok(count)
+    }
+
+    fn coordinate(&mut self) -> Result<Coordinate<f64>, RTreeError> {
+        Ok(Coordinate {
+            x: self.f64()?,
+            y: self.f64()?,
+        })
+    }
+
+    fn line_string(&mut self) -> Result<LineString<f64>, RTreeError> {
+        let count = self.count(16)?;
+
+        (0..count)
+            .map(|_| self.coordinate())
+            .collect::<Result<Vec<_>, _>>()
+            .map(LineString)
+    }
+
+    fn polygon(&mut self) -> Result<Polygon<f64>, RTreeError> {
+        let count = self.count(4)?;
+        let mut rings = (0..count)
+            .map(|_| self.line_string())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if rings.is_empty() {
+            return Ok(Polygon::new(LineString(Vec::new()), Vec::new()));
+        }
+        let exterior = rings.remove(0);
+
+        Ok(Polygon::new(exterior, rings))
+    }
+
+    /// Reads the members of a multi-geometry, each of which must have type `kind`.
+    fn members<S, F>(&mut self, kind: u32, depth: usize, read: F) -> Result<Vec<S>, RTreeError>
+    where
+        F: Fn(Geometry<f64>) -> Option<S>,
+    {
+        let count = self.count(5)?;
+
+        (0..count)
+            .map(|_| {
+                let (member_kind, geometry) = self.tagged_geometry(depth + 1)?;
+                if member_kind != kind {
+                    return Err(RTreeError::Wkb(
+                        "multi-geometry has a member of the wrong type",
+                    ));
+                }
+
+                read(geometry).ok_or(RTreeError::Wkb("multi-geometry has an invalid member"))
+            })
+            .collect()
+    }
+
+    fn geometry(&mut self, depth: usize) -> Result<Geometry<f64>, RTreeError> {
+        Ok(self.tagged_geometry(depth)?.1)
+    }
+
+    /// Reads a geometry, along with its WKB type code.
+    fn tagged_geometry(&mut self, depth: usize) -> Result<(u32, Geometry<f64>), RTreeError> {
+        if depth > MAX_NESTING {
+            return Err(RTreeError::Wkb("geometry is nested too deeply"));
+        }
+
+        self.little_endian = match self.take::<1>()?[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(RTreeError::Wkb("invalid byte order")),
+        };
+
+        let mut kind = self.u32()?;
+        if kind & EWKB_SRID_FLAG != 0 {
+            self.u32()?;
+            kind &= !EWKB_SRID_FLAG;
+        }
+
+        let geometry = match kind {
+            WKB_POINT => {
+                let coordinate = self.coordinate()?;
+                if coordinate.x.is_nan() && coordinate.y.is_nan() {
+                    return Err(RTreeError::Wkb("empty points are not supported"));
+                }
+
+                Geometry::Point(Point(coordinate))
+            }
+            WKB_LINE_STRING => Geometry::LineString(self.line_string()?),
+            WKB_POLYGON => Geometry::Polygon(self.polygon()?),
+            WKB_MULTI_POINT => {
+                Geometry::MultiPoint(MultiPoint(
+                    self.members(WKB_POINT, depth, |geometry| Point::try_from(geometry).ok())?,
+                ))
+            }
+            WKB_MULTI_LINE_STRING => Geometry::MultiLineString(MultiLineString(self.members(
+                WKB_LINE_STRING,
+                depth,
+                |geometry| LineString::try_from(geometry).ok(),
+            )?)),
+            WKB_MULTI_POLYGON => Geometry::MultiPolygon(MultiPolygon(self.members(
+                WKB_POLYGON,
+                depth,
+                |geometry| Polygon::try_from(geometry).ok(),
+            )?)),
+            WKB_GEOMETRY_COLLECTION => {
+                let count = self.count(5)?;
+                let geometries = (0..count)
+                    .map(|_| self.geometry(depth + 1))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Geometry::GeometryCollection(GeometryCollection(geometries))
+            }
+            _ => return Err(RTreeError::Wkb("unsupported geometry type")),
+        };
+
+        Ok((kind, geometry))
+    }
+}
@@ -88,7 +88,8 @@
 
 extern crate test;
 
+pub mod io;
 pub mod rtree;
 
-pub use crate::rtree::RTree;
+pub use crate::rtree::{ItemId, RTree, RTreeMap};
 pub use geo_types::{point, Point, Rect};
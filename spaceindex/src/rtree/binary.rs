@@ -0,0 +1,568 @@
+//! A compact, versioned binary format for storing a built [`RTree`].
+//!
+//! A file consists of a fixed-size header followed by two length-prefixed sections.  All
+//! integers and floats are little-endian, and every coordinate is stored as an `f64`.
+//!
+//! The header is 64 bytes long:
+//!
+//! | Offset | Size | Contents                                                       |
+//! |--------|------|----------------------------------------------------------------|
+//! | 0      | 8    | The magic bytes `SPCINDEX`                                     |
+//! | 8      | 2    | The format version, currently `1`                              |
+//! | 10     | 2    | Flags; bit 0 is set if the tree has a periodic domain          |
+//! | 12     | 4    | The minimum number of children of a node                       |
+//! | 16     | 4    | The maximum number of children of a node                       |
+//! | 20     | 8    | The number of elements in the tree                             |
+//! | 28     | 32   | The periodic domain as `min x, min y, max x, max y`, or zeroes |
+//! | 60     | 4    | The CRC-32 of the first 60 bytes of the header                 |
+//!
+//! Each section is stored as its length in bytes (a `u64`), its contents, then the CRC-32 of
+//! its contents (a `u32`).  The first section holds a record for every node of the tree in
+//! pre-order, starting at the root:
+//!
+//! - A tag byte, `0` for an internal node and `1` for a leaf,
+//! - The minimum bounding region of the node as `min x, min y, max x, max y`, and
+//! - For internal nodes only, the number of children of the node (a `u32`).
+//!
+//! The second section holds the data of every leaf, in the same order as the leaves appear in
+//! the first section.  The data of each leaf is written by its [`Encode`] implementation, and
+//! stored as its length in bytes (a `u32`) followed by the encoded bytes.
+//!
+//! A tree read back from this format has exactly the same shape as the tree that was written.
+use std::convert::Infallible;
+use std::io::{self, Read, Write};
+use std::string::FromUtf8Error;
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Coordinate, Rect};
+use thiserror::Error;
+
+use crate::rtree::{valid_node_capacity, Index, Node, RTree, RTreeError};
+
+/// The magic bytes at the start of every file.
+const MAGIC: &[u8; 8] = b"SPCINDEX";
+
+/// The version of the format written by this module.
+const VERSION: u16 = 1;
+
+/// The length of the header, including its checksum.
+const HEADER_LENGTH: usize = 64;
+
+/// Set in the header flags if the tree has a periodic domain.
+const FLAG_PERIODIC: u16 = 1;
+
+/// Tags identifying the kind of each node record.
+const TAG_INTERNAL: u8 = 0;
+const TAG_LEAF: u8 = 1;
+
+/// A type which can be written as the data of a leaf.
+pub trait Encode {
+    /// Appends the encoding of `self` to `buffer`.
+    fn encode(&self, buffer: &mut Vec<u8>);
+}
+
+/// A type which can be read back from the data of a leaf.
+pub trait Decode: Sized {
+    /// The error returned when `bytes` isn't a valid encoding.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Decodes a value from exactly the bytes `bytes`.
+    fn decode(bytes: &[u8]) -> Result<Self, Self::Error>;
+}
+
+/// The error returned when decoding a fixed-size value from the wrong number of bytes.
+#[derive(Error, Debug)]
+#[error("expected {expected} bytes, found {found}")]
+pub struct LengthMismatch {
+    expected: usize,
+    found: usize,
+}
+
+impl Encode for () {
+    fn encode(&self, _buffer: &mut Vec<u8>) {}
+}
+
+impl Decode for () {
+    type Error = LengthMismatch;
+
+    fn decode(bytes: &[u8]) -> Result<Self, Self::Error> {
+        match bytes.len() {
+            0 => Ok(()),
+            found => Err(LengthMismatch { expected: 0, found }),
+        }
+    }
+}
+
+macro_rules! impl_encode_decode_for_numbers {
+    ($($number:ty),*) => {
+        $(
+            impl Encode for $number {
+                fn encode(&self, buffer: &mut Vec<u8>) {
+                    buffer.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $number {
+                type Error = LengthMismatch;
+
+                fn decode(bytes: &[u8]) -> Result<Self, Self::Error> {
+                    let expected = std::mem::size_of::<$number>();
+
+                    if bytes.len() != expected {
+                        return Err(LengthMismatch { expected, found: bytes.len() });
+                    }
+
+                    Ok(<$number>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_encode_decode_for_numbers!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Encode for String {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    type Error = FromUtf8Error;
+
+    fn decode(bytes: &[u8]) -> Result<Self, Self::Error> {
+        String::from_utf8(bytes.to_vec())
+    }
+}
+
+impl Encode for Vec<u8> {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(self);
+    }
+}
+
+impl Decode for Vec<u8> {
+    type Error = Infallible;
+
+    fn decode(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// A node record read from the node section, before the tree is assembled.
+struct NodeRecord<T>
+where
+    T: CoordFloat,
+{
+    region: Rect<T>,
+    parent: Option<usize>,
+    is_leaf: bool,
+}
+
+impl<ND, T> RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Writes this tree to `writer` in the format described in the [module
+    /// documentation](crate::rtree::binary).
+    ///
+    /// # Errors
+    /// This function will return an error if writing to `writer` fails, or if the encoding of
+    /// any leaf is longer than `u32::MAX` bytes.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), String::from("a")).unwrap();
+    ///
+    /// let mut bytes = Vec::new();
+    /// tree.write_to(&mut bytes).unwrap();
+    ///
+    /// let restored = RTree::<String, f64>::read_from(bytes.as_slice()).unwrap();
+    /// assert!(restored.structurally_eq(&tree));
+    /// ```
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), RTreeError>
+    where
+        ND: Encode,
+    {
+        let mut nodes = Vec::new();
+        let mut payloads = Vec::new();
+        let mut scratch = Vec::new();
+        self.write_node(self.root, &mut nodes, &mut payloads, &mut scratch)?;
+
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+
+        let flags = if self.domain.is_some() {
+            FLAG_PERIODIC
+        } else {
+            0
+        };
+        header.extend_from_slice(&flags.to_le_bytes());
+        header.extend_from_slice(&(self.min_children as u32).to_le_bytes());
+        header.extend_from_slice(&(self.max_children as u32).to_le_bytes());
+        header.extend_from_slice(&(self.len as u64).to_le_bytes());
+
+        let zero = Rect::new(Coordinate::zero(), Coordinate::zero());
+        put_region(&mut header, self.domain.unwrap_or(zero));
+
+        let checksum = crc32fast::hash(&header);
+        header.extend_from_slice(&checksum.to_le_bytes());
+
+        writer.write_all(&header)?;
+        write_section(&mut writer, &nodes)?;
+        write_section(&mut writer, &payloads)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Appends the records for the subtree of `index` to `nodes`, and the encoded data of its
+    /// leaves to `payloads`.
+    fn write_node(
+        &self,
+        index: Index,
+        nodes: &mut Vec<u8>,
+        payloads: &mut Vec<u8>,
+        scratch: &mut Vec<u8>,
+    ) -> Result<(), RTreeError>
+    where
+        ND: Encode,
+    {
+        let node = self.get_node(index);
+
+        match node.get_data() {
+            Some(data) => {
+                nodes.push(TAG_LEAF);
+                put_region(nodes, node.get_region());
+
+                scratch.clear();
+                data.encode(scratch);
+
+                let length = u32::try_from(scratch.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "leaf data is too large")
+                })?;
+                payloads.extend_from_slice(&length.to_le_bytes());
+                payloads.extend_from_slice(scratch);
+            }
+            None => {
+                nodes.push(TAG_INTERNAL);
+                put_region(nodes, node.get_region());
+                nodes.extend_from_slice(&(node.child_count() as u32).to_le_bytes());
+
+                for child_index in node.child_index_iter() {
+                    self.write_node(child_index, nodes, payloads, scratch)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a tree written by [`RTree::write_to`] from `reader`.
+    ///
+    /// The input is fully validated, so a damaged file results in an error rather than a
+    /// corrupt tree.
+    ///
+    /// # Errors
+    /// This function will return:
+    /// - [`RTreeError::Io`] if reading from `reader` fails,
+    /// - [`RTreeError::Truncated`] if the input ends early,
+    /// - [`RTreeError::BadMagic`] if the input isn't in this format at all,
+    /// - [`RTreeError::UnsupportedVersion`] if the input was written by a newer version of
+    ///   this format,
+    /// - [`RTreeError::ChecksumMismatch`] if any part of the input has been damaged,
+    /// - [`RTreeError::Corrupt`] if the input doesn't describe a valid tree, or
+    /// - [`RTreeError::Decode`] if the data of any leaf fails to decode.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self, RTreeError>
+    where
+        ND: Decode,
+    {
+        let mut header = [0; HEADER_LENGTH];
+        read_exact(&mut reader, &mut header[..MAGIC.len()])?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(RTreeError::BadMagic);
+        }
+        read_exact(&mut reader, &mut header[MAGIC.len()..])?;
+
+        // Check the header is intact before trusting any of it, including its version.
+        let checksum = u32::from_le_bytes(header[HEADER_LENGTH - 4..].try_into().unwrap());
+        if crc32fast::hash(&header[..HEADER_LENGTH - 4]) != checksum {
+            return Err(RTreeError::ChecksumMismatch("header"));
+        }
+
+        let mut cursor = Cursor(&header[MAGIC.len()..]);
+        let version = cursor.u16()?;
+        if version != VERSION {
+            return Err(RTreeError::UnsupportedVersion(version));
+        }
+
+        let flags = cursor.u16()?;
+        let min_children = cursor.u32()? as usize;
+        let max_children = cursor.u32()? as usize;
+        let len = cursor.u64()?;
+        let domain = cursor.region::<T>()?;
+
+        if flags & !FLAG_PERIODIC != 0 {
+            return Err(RTreeError::Corrupt("unknown flags are set"));
+        }
+        if !valid_node_capacity(min_children, max_children) {
+            return Err(RTreeError::Corrupt("invalid node capacity"));
+        }
+
+        let domain = if flags & FLAG_PERIODIC != 0 {
+            if domain.width() <= T::zero() || domain.height() <= T::zero() {
+                return Err(RTreeError::Corrupt(
+                    "periodic domain has zero width or height",
+                ));
+            }
+            Some(domain)
+        } else {
+            None
+        };
+
+        let nodes = read_section(&mut reader, "node")?;
+        let payloads = read_section(&mut reader, "payload")?;
+
+        let records = read_node_records::<T>(&nodes, min_children, max_children)?;
+        let leaf_count = records.iter().filter(|record| record.is_leaf).count();
+        if leaf_count as u64 != len {
+            return Err(RTreeError::Corrupt(
+                "element count doesn't match the header",
+            ));
+        }
+
+        let mut cursor = Cursor(&payloads);
+        let mut data = Vec::new();
+        while !cursor.is_empty() {
+            let length = cursor.u32()? as usize;
+            let bytes = cursor.take(length)?;
+            data.push(ND::decode(bytes).map_err(|error| RTreeError::Decode(Box::new(error)))?);
+        }
+        if data.len() != leaf_count {
+            return Err(RTreeError::Corrupt(
+                "payload count doesn't match the element count",
+            ));
+        }
+
+        // Assemble the tree.  Records are in pre-order, so every parent is inserted before
+        // any of its children.
+        let mut tree = Self {
+            min_children,
+            max_children,
+            domain,
+            len: leaf_count,
+            ..Self::new()
+        };
+        tree.nodes.clear();
+
+        let mut indexes = Vec::with_capacity(records.len());
+        let mut data = data.into_iter();
+
+        for record in records {
+            let parent = record.parent.map(|parent| indexes[parent]);
+            let node = if record.is_leaf {
+                Node::new_leaf(record.region, data.next().unwrap(), parent)
+            } else {
+                Node::new_internal_node(record.region, parent)
+            };
+
+            let index = tree.nodes.insert(node);
+            match parent {
+                // This is fine as the child has its parent set, and containment is checked below.
+                Some(parent) => tree.get_node_mut(parent).add_child_unsafe(index),
+                None => tree.root = index,
+            }
+            indexes.push(index);
+        }
+
+        tree.check_consistency()?;
+
+        Ok(tree)
+    }
+}
+
+/// Parses the node section, checking that it describes a balanced tree whose nodes respect
+/// the given capacity.
+fn read_node_records<T: CoordFloat>(
+    bytes: &[u8],
+    min_children: usize,
+    max_children: usize,
+) -> Result<Vec<NodeRecord<T>>, RTreeError> {
+    let mut cursor = Cursor(bytes);
+    let mut records = Vec::new();
+
+    // Pairs `(record, remaining children)` of the internal nodes we are currently inside.
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+    let mut leaf_depth = None;
+
+    loop {
+        let parent = match stack.last_mut() {
+            None if records.is_empty() => None,
+            None => break,
+            Some((_, 0)) => {
+                stack.pop();
+                continue;
+            }
+            Some((parent, remaining)) => {
+                *remaining -= 1;
+                Some(*parent)
+            }
+        };
+
+        let tag = cursor.u8()?;
+        let region = cursor.region()?;
+
+        match tag {
+            TAG_LEAF => {
+                if parent.is_none() {
+                    return Err(RTreeError::Corrupt("the root is a leaf"));
+                }
+
+                // The stack holds every ancestor of this leaf.
+                if *leaf_depth.get_or_insert(stack.len()) != stack.len() {
+                    return Err(RTreeError::Corrupt("leaves are at different depths"));
+                }
+            }
+            TAG_INTERNAL => {
+                let child_count = cursor.u32()? as usize;
+
+                // Nodes are split once they reach `max_children` children.
+                let minimum = if parent.is_none() { 0 } else { min_children };
+                if child_count < minimum || child_count >= max_children {
+                    return Err(RTreeError::Corrupt("node has the wrong number of children"));
+                }
+
+                stack.push((records.len(), child_count));
+            }
+            _ => return Err(RTreeError::Corrupt("unknown node tag")),
+        }
+
+        records.push(NodeRecord {
+            region,
+            parent,
+            is_leaf: tag == TAG_LEAF,
+        });
+    }
+
+    if !cursor.is_empty() {
+        return Err(RTreeError::Corrupt("trailing bytes after the last node"));
+    }
+
+    Ok(records)
+}
+
+/// Writes `contents` as a section: its length, the contents themselves, then their checksum.
+fn write_section<W: Write>(writer: &mut W, contents: &[u8]) -> Result<(), RTreeError> {
+    writer.write_all(&(contents.len() as u64).to_le_bytes())?;
+    writer.write_all(contents)?;
+    writer.write_all(&crc32fast::hash(contents).to_le_bytes())?;
+
+    Ok(())
+}
+
+/// Reads a section written by [`write_section`], verifying its checksum.
+fn read_section<R: Read>(reader: &mut R, name: &'static str) -> Result<Vec<u8>, RTreeError> {
+    let mut length = [0; 8];
+    read_exact(reader, &mut length)?;
+    let length = u64::from_le_bytes(length);
+
+    // Don't trust `length` enough to allocate it up front.
+    let mut contents = Vec::new();
+    reader.by_ref().take(length).read_to_end(&mut contents)?;
+    if (contents.len() as u64) != length {
+        return Err(RTreeError::Truncated);
+    }
+
+    let mut checksum = [0; 4];
+    read_exact(reader, &mut checksum)?;
+    if crc32fast::hash(&contents) != u32::from_le_bytes(checksum) {
+        return Err(RTreeError::ChecksumMismatch(name));
+    }
+
+    Ok(contents)
+}
+
+/// Fills `buffer` from `reader`, reporting an early end of input as [`RTreeError::Truncated`].
+pub(crate) fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<(), RTreeError> {
+    reader
+        .read_exact(buffer)
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::UnexpectedEof => RTreeError::Truncated,
+            _ => RTreeError::Io(error),
+        })
+}
+
+/// Appends `region` to `buffer` as four `f64`s.
+pub(crate) fn put_region<T: CoordFloat>(buffer: &mut Vec<u8>, region: Rect<T>) {
+    for value in [
+        region.min().x,
+        region.min().y,
+        region.max().x,
+        region.max().y,
+    ] {
+        buffer.extend_from_slice(&value.to_f64().unwrap_or(f64::NAN).to_le_bytes());
+    }
+}
+
+/// A cursor over the contents of a section whose checksum has already been verified, so
+/// running out of bytes means the contents are corrupt rather than truncated.
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], RTreeError> {
+        if self.0.len() < length {
+            return Err(RTreeError::Corrupt(
+                "record extends past the end of its section",
+            ));
+        }
+
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, RTreeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, RTreeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, RTreeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, RTreeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, RTreeError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a region stored as four `f64`s, checking that every coordinate is finite.
+    fn region<T: CoordFloat>(&mut self) -> Result<Rect<T>, RTreeError> {
+        let mut coordinate = || {
+            let value = self.f64()?;
+
+            T::from(value)
+                .filter(|value| value.is_finite())
+                .ok_or(RTreeError::Corrupt("region has a non-finite coordinate"))
+        };
+
+        let (min_x, min_y) = (coordinate()?, coordinate()?);
+        let (max_x, max_y) = (coordinate()?, coordinate()?);
+
+        Ok(Rect::new((min_x, min_y), (max_x, max_y)))
+    }
+}
@@ -0,0 +1,149 @@
+use std::cmp::Ordering;
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Rect};
+
+use crate::rtree::{combine_rects, Index, Node, RTree, RTreeError};
+
+impl<ND, T> RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Creates a new [`RTree`] holding every pair `(region, data)` in `entries`.
+    ///
+    /// Rather than inserting the entries one at a time, the tree is packed bottom-up using
+    /// the Sort-Tile-Recursive algorithm.  This is much faster than repeated insertion, and
+    /// produces a tree with less overlap between nodes.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let entries = (0..100).map(|i| {
+    ///     let x = i as f64;
+    ///     (Rect::new((x, 0.0), (x + 1.0, 1.0)), i)
+    /// });
+    ///
+    /// let tree = RTree::bulk_load(entries);
+    /// assert_eq!(tree.len(), 100);
+    /// assert_eq!(tree.point_lookup((10.5, 0.5)).len(), 1);
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn bulk_load<I: IntoIterator<Item = (Rect<T>, ND)>>(entries: I) -> Self {
+        let mut tree = Self::new();
+
+        // Without a periodic domain there is nothing to wrap, so packing can't fail.
+        tree.pack(entries.into_iter().collect()).unwrap();
+
+        tree
+    }
+
+    /// Packs `entries` into this empty tree using the Sort-Tile-Recursive algorithm.
+    ///
+    /// # Errors
+    /// This function will return an error if this tree has a periodic domain and any region in
+    /// `entries` is larger than it, in which case the tree is left unchanged.
+    ///
+    /// # Panics
+    /// This function will panic if this tree is not empty.
+    pub(crate) fn pack(&mut self, entries: Vec<(Rect<T>, ND)>) -> Result<(), RTreeError> {
+        assert!(!self.root_node().has_children());
+
+        let entries = entries
+            .into_iter()
+            .map(|(region, data)| Ok((self.wrap_region(region)?, data)))
+            .collect::<Result<Vec<_>, RTreeError>>()?;
+
+        self.len = entries.len();
+
+        let mut level = entries
+            .into_iter()
+            .map(|(region, data)| {
+                (
+                    self.nodes.insert(Node::new_leaf(region, data, None)),
+                    region,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Nodes are split once they reach `max_children` children, so never fill them that far.
+        let capacity = self.max_children - 1;
+
+        while level.len() > capacity {
+            level = self.pack_level(level, capacity);
+        }
+
+        if let Some(region) = level
+            .iter()
+            .map(|(_, region)| *region)
+            .reduce(combine_rects)
+        {
+            self.set_children_safe(self.root, level.into_iter().map(|(index, _)| index));
+
+            // This is safe as `region` contains every child of the root.
+            self.get_node_mut(self.root)
+                .set_minimum_bounding_region_unsafe(region);
+        }
+
+        Ok(())
+    }
+
+    /// Groups the detached nodes in `level` under new internal nodes of at most `capacity`
+    /// children each, returning the new nodes along with their minimum bounding regions.
+    fn pack_level(
+        &mut self,
+        mut level: Vec<(Index, Rect<T>)>,
+        capacity: usize,
+    ) -> Vec<(Index, Rect<T>)> {
+        // Spread the nodes as evenly as possible between the groups, so that every group
+        // has at least `min_children` children.
+        let group_count = level.len().div_ceil(capacity);
+        let group_sizes = (0..group_count)
+            .map(|group| level.len() / group_count + usize::from(group < level.len() % group_count))
+            .collect::<Vec<_>>();
+
+        // Tile the plane into vertical slices of roughly `sqrt(group_count)` groups each.
+        let slice_count = (group_count as f64).sqrt().ceil() as usize;
+        let groups_per_slice = group_count.div_ceil(slice_count);
+
+        level.sort_by(|(_, left), (_, right)| compare_centers(left.center().x, right.center().x));
+
+        let mut nodes = level.into_iter();
+        let mut packed = Vec::with_capacity(group_count);
+
+        for slice_sizes in group_sizes.chunks(groups_per_slice) {
+            let mut slice = nodes
+                .by_ref()
+                .take(slice_sizes.iter().sum())
+                .collect::<Vec<_>>();
+            slice.sort_by(|(_, left), (_, right)| {
+                compare_centers(left.center().y, right.center().y)
+            });
+
+            let mut slice = slice.into_iter();
+            for &size in slice_sizes {
+                let children = slice.by_ref().take(size).collect::<Vec<_>>();
+
+                // Every group is non-empty, so this can't fail.
+                let region = children
+                    .iter()
+                    .map(|(_, region)| *region)
+                    .reduce(combine_rects)
+                    .unwrap();
+
+                let index = self.nodes.insert(Node::new_internal_node(region, None));
+                self.set_children_safe(index, children.into_iter().map(|(index, _)| index));
+
+                packed.push((index, region));
+            }
+        }
+
+        packed
+    }
+}
+
+/// Compares two coordinates of region centers, treating incomparable values as equal.
+#[inline(always)]
+fn compare_centers<T: CoordFloat>(left: T, right: T) -> Ordering {
+    left.partial_cmp(&right).unwrap_or(Ordering::Equal)
+}
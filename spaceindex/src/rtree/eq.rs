@@ -0,0 +1,149 @@
+use std::cmp::Ordering;
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Rect};
+
+use crate::rtree::{Index, RTree};
+
+impl<ND, T> PartialEq for RTree<ND, T>
+where
+    ND: PartialEq,
+    T: CoordFloat + HasKernel,
+{
+    /// Two trees are equal if they hold the same pairs `(region, data)`, counted with
+    /// multiplicity, regardless of how those pairs are laid out in each tree.
+    /// Use [`RTree::structurally_eq`] to also compare the layout.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let entries = (0..50).map(|i| (Rect::new((i as f64, 0.0), (i as f64 + 1.0, 1.0)), i));
+    ///
+    /// let mut inserted = RTree::new();
+    /// for (region, data) in entries.clone() {
+    ///     inserted.insert(region, data).unwrap();
+    /// }
+    /// let packed = entries.collect::<RTree<_, _>>();
+    ///
+    /// assert!(inserted == packed);
+    /// assert!(!inserted.structurally_eq(&packed));
+    /// ```
+    fn eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+
+        let mut left = self.iter().map(|(_, r, d)| (r, d)).collect::<Vec<_>>();
+        let mut right = other.iter().map(|(_, r, d)| (r, d)).collect::<Vec<_>>();
+        left.sort_by(|(a, _), (b, _)| compare_regions(a, b));
+        right.sort_by(|(a, _), (b, _)| compare_regions(a, b));
+
+        // Both sides are sorted by region, so we only need to match up data within each run of
+        // equal regions.  `ND` is only `PartialEq`, so this is done pairwise.
+        let mut start = 0;
+        while start < left.len() {
+            let region = left[start].0;
+            let end = start
+                + left[start..]
+                    .iter()
+                    .take_while(|(other_region, _)| *other_region == region)
+                    .count();
+
+            if right[start..end]
+                .iter()
+                .any(|(other_region, _)| *other_region != region)
+            {
+                return false;
+            }
+
+            let mut matched = vec![false; end - start];
+            for (_, data) in &left[start..end] {
+                let position = right[start..end]
+                    .iter()
+                    .zip(matched.iter())
+                    .position(|((_, other_data), matched)| !matched && data == other_data);
+
+                match position {
+                    Some(position) => matched[position] = true,
+                    None => return false,
+                }
+            }
+
+            start = end;
+        }
+
+        true
+    }
+}
+
+impl<ND, T> RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Returns `true` if this tree and `other` have the same configuration and exactly the same
+    /// shape: every node has the same region and the same children in the same order, and every
+    /// leaf holds the same data.  A tree is always structurally equal to its clone.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a').unwrap();
+    ///
+    /// let mut fork = tree.clone();
+    /// assert!(tree.structurally_eq(&fork));
+    ///
+    /// fork.insert(Rect::new((2.0, 2.0), (3.0, 3.0)), 'b').unwrap();
+    /// assert!(!tree.structurally_eq(&fork));
+    /// ```
+    pub fn structurally_eq(&self, other: &Self) -> bool
+    where
+        ND: PartialEq,
+    {
+        self.min_children == other.min_children
+            && self.max_children == other.max_children
+            && self.domain == other.domain
+            && self.len == other.len
+            && self._structurally_eq(self.root, other, other.root)
+    }
+
+    /// Recursively checks that the subtree of `index` in this tree matches the subtree of
+    /// `other_index` in `other`.
+    fn _structurally_eq(&self, index: Index, other: &Self, other_index: Index) -> bool
+    where
+        ND: PartialEq,
+    {
+        let node = self.get_node(index);
+        let other_node = other.get_node(other_index);
+
+        node.get_region() == other_node.get_region()
+            && node.get_data() == other_node.get_data()
+            && node.child_count() == other_node.child_count()
+            && node
+                .child_index_iter()
+                .zip(other_node.child_index_iter())
+                .all(|(child, other_child)| self._structurally_eq(child, other, other_child))
+    }
+}
+
+/// Orders regions lexicographically by their minimum and then maximum corners, treating
+/// incomparable coordinates as equal.
+fn compare_regions<T: CoordFloat>(left: &Rect<T>, right: &Rect<T>) -> Ordering {
+    let key = |region: &Rect<T>| {
+        [
+            region.min().x,
+            region.min().y,
+            region.max().x,
+            region.max().y,
+        ]
+    };
+
+    key(left)
+        .iter()
+        .zip(key(right).iter())
+        .map(|(a, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
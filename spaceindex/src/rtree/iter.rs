@@ -0,0 +1,96 @@
+use std::iter::FromIterator;
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Rect};
+
+use crate::rtree::{Node, RTree};
+
+/// An owning iterator over the pairs `(region, data)` stored in an [`RTree`], in no particular
+/// order.  Returned by [`RTree::drain`] and the [`IntoIterator`] implementation of [`RTree`].
+pub struct IntoIter<ND, T>
+where
+    T: CoordFloat,
+{
+    nodes: generational_arena::IntoIter<Node<ND, T>>,
+}
+
+impl<ND, T> IntoIter<ND, T>
+where
+    T: CoordFloat,
+{
+    pub(crate) fn new(nodes: generational_arena::Arena<Node<ND, T>>) -> Self {
+        Self {
+            nodes: nodes.into_iter(),
+        }
+    }
+}
+
+impl<ND, T> Iterator for IntoIter<ND, T>
+where
+    T: CoordFloat,
+{
+    type Item = (Rect<T>, ND);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for node in self.nodes.by_ref() {
+            let region = node.get_region();
+
+            if let Some(data) = node.into_data() {
+                return Some((region, data));
+            }
+        }
+
+        None
+    }
+}
+
+impl<ND, T> IntoIterator for RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    type Item = (Rect<T>, ND);
+    type IntoIter = IntoIter<ND, T>;
+
+    /// Consumes the tree, returning an iterator over the pairs `(region, data)` it held.
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain()
+    }
+}
+
+impl<ND, T> FromIterator<(Rect<T>, ND)> for RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Packs the pairs `(region, data)` into a new tree using [`RTree::bulk_load`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let tree: RTree<_, _> = (0..10)
+    ///     .map(|i| (Rect::new((i as f64, 0.0), (i as f64 + 1.0, 1.0)), i))
+    ///     .collect();
+    ///
+    /// assert_eq!(tree.len(), 10);
+    /// # tree.validate_consistency();
+    /// ```
+    fn from_iter<I: IntoIterator<Item = (Rect<T>, ND)>>(entries: I) -> Self {
+        Self::bulk_load(entries)
+    }
+}
+
+impl<ND, T> Extend<(Rect<T>, ND)> for RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Inserts each pair `(region, data)` into the tree in turn.
+    ///
+    /// # Panics
+    /// This function will panic if this tree has a periodic domain and any of the regions
+    /// is larger than it.
+    fn extend<I: IntoIterator<Item = (Rect<T>, ND)>>(&mut self, entries: I) {
+        for (region, data) in entries {
+            self.insert(region, data).unwrap();
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Point, Rect};
+
+use crate::rtree::metric::Metric;
+use crate::rtree::{ItemId, RTree, RTreeError};
+
+/// An [`RTree`] whose elements are identified by user supplied keys.
+///
+/// Alongside the tree, an `RTreeMap` maintains a map from each key to the [`ItemId`] of its element,
+/// so elements can be looked up, moved and removed by key.
+///
+/// # Example
+/// ```rust
+/// use spaceindex::{Rect, RTreeMap};
+///
+/// let mut map = RTreeMap::new();
+///
+/// map.insert("house", Rect::new((0.0, 0.0), (2.0, 2.0)), 3).unwrap();
+/// map.insert("shed", Rect::new((5.0, 0.0), (6.0, 1.0)), 1).unwrap();
+/// assert_eq!(map.point_lookup((1.0, 1.0)), vec![&"house"]);
+///
+/// // Move the shed next to the house
+/// map.update_region("shed", Rect::new((2.0, 0.0), (3.0, 1.0))).unwrap();
+/// assert_eq!(map.point_lookup((2.5, 0.5)), vec![&"shed"]);
+///
+/// // Knock down the house
+/// assert_eq!(map.remove("house"), Some((Rect::new((0.0, 0.0), (2.0, 2.0)), 3)));
+/// assert!(map.point_lookup((1.0, 1.0)).is_empty());
+/// ```
+#[derive(Clone, Debug)]
+pub struct RTreeMap<K, ND, T>
+where
+    K: Eq + Hash,
+    T: CoordFloat + HasKernel,
+{
+    /// The underlying tree.  Each leaf also stores its key, so query results can be mapped
+    /// back to keys.
+    tree: RTree<(K, ND), T>,
+
+    /// The id of the element for each key.
+    ids: HashMap<K, ItemId>,
+}
+
+impl<K, ND, T> Default for RTreeMap<K, ND, T>
+where
+    K: Eq + Hash + Clone,
+    T: CoordFloat + HasKernel,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, ND, T> RTreeMap<K, ND, T>
+where
+    K: Eq + Hash + Clone,
+    T: CoordFloat + HasKernel,
+{
+    /// Creates a new, empty [`RTreeMap`].
+    pub fn new() -> Self {
+        Self::with_tree(RTree::new())
+    }
+
+    /// Creates a new [`RTreeMap`] backed by the empty tree `tree`.  This can be used to create
+    /// a map over a periodic domain, for instance.
+    ///
+    /// # Panics
+    /// This function will panic if `tree` is not empty.
+    pub fn with_tree(tree: RTree<(K, ND), T>) -> Self {
+        assert!(!tree.root_node().has_children());
+
+        Self {
+            tree,
+            ids: HashMap::new(),
+        }
+    }
+
+    /// Creates a new [`RTreeMap`] holding every triple `(key, region, data)` in `entries`, with
+    /// the tree packed by [`RTree::bulk_load`].  If a key appears more than once, only its last
+    /// element is kept.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTreeMap};
+    ///
+    /// let entries = (0..100).map(|i| {
+    ///     let x = i as f64;
+    ///     (i, Rect::new((x, 0.0), (x + 1.0, 1.0)), ())
+    /// });
+    ///
+    /// let map = RTreeMap::bulk_load(entries);
+    /// assert_eq!(map.len(), 100);
+    /// assert_eq!(map.point_lookup((10.5, 0.5)), vec![&10]);
+    /// ```
+    pub fn bulk_load<I: IntoIterator<Item = (K, Rect<T>, ND)>>(entries: I) -> Self {
+        let mut entries = entries
+            .into_iter()
+            .map(|(key, region, data)| (region, (key, data)))
+            .collect::<Vec<_>>();
+
+        let last_positions = entries
+            .iter()
+            .enumerate()
+            .map(|(position, (_, (key, _)))| (key.clone(), position))
+            .collect::<HashMap<_, _>>();
+
+        if last_positions.len() < entries.len() {
+            let mut position = 0;
+            entries.retain(|(_, (key, _))| {
+                position += 1;
+                last_positions[key] == position - 1
+            });
+        }
+
+        let tree = RTree::bulk_load(entries);
+        let ids = tree
+            .iter()
+            .map(|(id, _, (key, _))| (key.clone(), id))
+            .collect();
+
+        Self { tree, ids }
+    }
+
+    /// Returns a reference to the underlying tree.
+    #[inline(always)]
+    pub fn tree(&self) -> &RTree<(K, ND), T> {
+        &self.tree
+    }
+
+    /// Returns the number of elements in the map.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns `true` if the map contains no elements, `false` otherwise.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns `true` if the map contains an element with key `key`, `false` otherwise.
+    #[inline(always)]
+    pub fn contains_key<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.ids.contains_key(key)
+    }
+
+    /// Returns an iterator over the keys in this map.
+    #[inline(always)]
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.ids.keys()
+    }
+
+    /// Returns an iterator over the triples `(key, region, data)` in this map, in no particular
+    /// order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Rect<T>, &ND)> + '_ {
+        self.tree
+            .iter()
+            .map(|(_, region, (key, data))| (key, region, data))
+    }
+
+    /// Returns an iterator over the triples `(key, region, data)` in this map, in no particular
+    /// order, allowing the data of each element to be modified.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &Rect<T>, &mut ND)> + '_ {
+        self.tree
+            .iter_mut()
+            .map(|(_, region, (key, data))| (&*key, region, data))
+    }
+
+    /// Removes every element from the map.
+    pub fn clear(&mut self) {
+        self.tree.clear();
+        self.ids.clear();
+    }
+
+    /// Inserts an element with key `key` into the map, replacing any existing element with the
+    /// same key.  The region and data of the replaced element are returned.
+    ///
+    /// # Errors
+    /// This function will return an error if the underlying tree fails to insert `region`, in
+    /// which case the map is left unchanged.
+    pub fn insert(
+        &mut self,
+        key: K,
+        region: Rect<T>,
+        data: ND,
+    ) -> Result<Option<(Rect<T>, ND)>, RTreeError> {
+        let id = self.tree.insert(region, (key.clone(), data))?;
+
+        Ok(self
+            .ids
+            .insert(key, id)
+            .and_then(|old_id| self.tree.remove(old_id))
+            .map(|(region, (_, data))| (region, data)))
+    }
+
+    /// Returns the region and data of the element with key `key`, if it exists.
+    pub fn get<Q: Eq + Hash + ?Sized>(&self, key: &Q) -> Option<(&Rect<T>, &ND)>
+    where
+        K: Borrow<Q>,
+    {
+        let (region, (_, data)) = self.tree.get(*self.ids.get(key)?)?;
+
+        Some((region, data))
+    }
+
+    /// Returns a mutable reference to the data of the element with key `key`, if it exists.
+    pub fn get_mut<Q: Eq + Hash + ?Sized>(&mut self, key: &Q) -> Option<&mut ND>
+    where
+        K: Borrow<Q>,
+    {
+        let (_, data) = self.tree.get_mut(*self.ids.get(key)?)?;
+
+        Some(data)
+    }
+
+    /// Removes the element with key `key` from the map, returning its region and data.
+    pub fn remove<Q: Eq + Hash + ?Sized>(&mut self, key: &Q) -> Option<(Rect<T>, ND)>
+    where
+        K: Borrow<Q>,
+    {
+        let id = self.ids.remove(key)?;
+
+        self.tree
+            .remove(id)
+            .map(|(region, (_, data))| (region, data))
+    }
+
+    /// Moves the element with key `key` to the region `region`.
+    ///
+    /// # Errors
+    /// This function will return an error if there is no element with key `key`, or if the
+    /// underlying tree fails to insert `region`.
+    pub fn update_region<Q: Eq + Hash + ?Sized>(
+        &mut self,
+        key: &Q,
+        region: Rect<T>,
+    ) -> Result<(), RTreeError>
+    where
+        K: Borrow<Q>,
+    {
+        let id = *self.ids.get(key).ok_or(RTreeError::ItemNotFound)?;
+
+        self.tree.update_region(id, region)
+    }
+
+    /// Returns the keys of those elements in the map intersecting the given point `point`.
+    pub fn point_lookup<P: Into<Point<T>>>(&self, point: P) -> Vec<&K> {
+        self.keys_of(self.tree.point_lookup(point))
+    }
+
+    /// Returns the keys of those elements in the map whose region intersects the given region.
+    pub fn region_intersection_lookup(&self, region: Rect<T>) -> Vec<&K> {
+        self.keys_of(self.tree.region_intersection_lookup(region))
+    }
+
+    /// Returns the keys of those elements in the map whose region contains the given region.
+    pub fn region_lookup(&self, region: Rect<T>) -> Vec<&K> {
+        self.keys_of(self.tree.region_lookup(region))
+    }
+
+    /// Returns up to `k` pairs `(key, distance)` of the elements in the map closest to `point`
+    /// as measured by `metric`, ordered by increasing distance.
+    pub fn nearest_neighbors<P: Into<Point<T>>, M: Metric<T>>(
+        &self,
+        point: P,
+        k: usize,
+        metric: M,
+    ) -> Vec<(&K, T)> {
+        self.tree
+            .nearest_neighbors(point, k, metric)
+            .into_iter()
+            .map(|(id, distance)| (self.key_of(id), distance))
+            .collect()
+    }
+
+    /// Returns the key of the element `id`.
+    #[inline(always)]
+    fn key_of(&self, id: ItemId) -> &K {
+        // Every id returned from a query on our tree refers to an element holding a key.
+        &self.tree.get(id).unwrap().1 .0
+    }
+
+    /// Maps a vector of element ids to their keys.
+    #[inline(always)]
+    fn keys_of(&self, ids: Vec<ItemId>) -> Vec<&K> {
+        ids.into_iter().map(|id| self.key_of(id)).collect()
+    }
+}
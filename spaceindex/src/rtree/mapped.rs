@@ -0,0 +1,563 @@
+//! A read-only tree which is queried directly from its serialized bytes.
+//!
+//! [`RTree::write_mapped`] writes a tree in a flat layout designed to be memory-mapped.  A
+//! [`MappedRTree`] then answers queries by reading node records straight out of the mapped
+//! bytes, so nothing is deserialized, and several processes mapping the same file share a
+//! single copy of it through the page cache.
+//!
+//! All integers and floats are little-endian.  The file starts with a 64 byte header:
+//!
+//! | Offset | Size | Contents                                         |
+//! |--------|------|--------------------------------------------------|
+//! | 0      | 8    | The magic bytes `SPCMAPPD`                       |
+//! | 8      | 2    | The format version, currently `1`                |
+//! | 10     | 6    | Reserved, zero                                   |
+//! | 16     | 8    | The number of nodes in the tree                  |
+//! | 24     | 8    | The number of entries (leaves) in the tree       |
+//! | 32     | 32   | Reserved, zero                                   |
+//!
+//! The header is followed by a 48 byte record for every node, in breadth-first order starting
+//! at the root, so the children of every node are stored contiguously:
+//!
+//! | Offset | Size | Contents                                                             |
+//! |--------|------|----------------------------------------------------------------------|
+//! | 0      | 32   | The minimum bounding region as `min x, min y, max x, max y` (`f64`s)  |
+//! | 32     | 8    | For internal nodes, the index of the first child.  For leaves, the   |
+//! |        |      | ordinal of the entry                                                 |
+//! | 40     | 4    | The number of children                                               |
+//! | 44     | 4    | `0` for an internal node, `1` for a leaf                             |
+//!
+//! Finally, for `n` entries there are `n + 1` offsets (`u64`s) into the payload bytes which
+//! follow them; the payload of entry `i` lies between offsets `i` and `i + 1`.  Payloads are
+//! written using the [`Encode`] implementation of the data of each leaf.
+//!
+//! Entries are identified by their ordinal, which is a number in `0..len()`.
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+use std::ops::Range;
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Point, Rect};
+
+use crate::rtree::binary::{Decode, Encode};
+use crate::rtree::metric::Metric;
+use crate::rtree::nearest::Candidate;
+use crate::rtree::{Index, RTree, RTreeError};
+
+/// The magic bytes at the start of every file.
+const MAGIC: &[u8; 8] = b"SPCMAPPD";
+
+/// The version of the layout written by this module.
+const VERSION: u16 = 1;
+
+/// The length of the header.
+const HEADER_LENGTH: usize = 64;
+
+/// The length of a single node record.
+const RECORD_LENGTH: usize = 48;
+
+/// Identifies the kind of each node record.
+const KIND_INTERNAL: u32 = 0;
+const KIND_LEAF: u32 = 1;
+
+impl<ND, T> RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Writes this tree to `writer` in the flat layout read by [`MappedRTree`], as described in
+    /// the [module documentation](crate::rtree::mapped).
+    ///
+    /// # Errors
+    /// This function will return an error if writing to `writer` fails, or if this tree has
+    /// a periodic domain, which the flat layout doesn't support.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    /// use spaceindex::rtree::mapped::MappedRTree;
+    ///
+    /// let mut tree = RTree::new();
+    /// tree.insert(Rect::new((0.0, 0.0), (2.0, 2.0)), 7u32).unwrap();
+    ///
+    /// let mut bytes = Vec::new();
+    /// tree.write_mapped(&mut bytes).unwrap();
+    ///
+    /// let mapped = MappedRTree::new(bytes).unwrap();
+    /// let hits = mapped.point_lookup((1.0, 1.0));
+    /// assert_eq!(hits.len(), 1);
+    /// assert_eq!(mapped.get::<u32>(hits[0]).unwrap().1, 7);
+    /// ```
+    pub fn write_mapped<W: Write>(&self, mut writer: W) -> Result<(), RTreeError>
+    where
+        ND: Encode,
+    {
+        if self.domain.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "trees with a periodic domain can't be written as a mapped tree",
+            )
+            .into());
+        }
+
+        // Lay the nodes out breadth-first, so the children of each node are contiguous.
+        let mut order: Vec<Index> = vec![self.root];
+        let mut position = 0;
+        while position < order.len() {
+            order.extend(self.get_node(order[position]).child_index_iter());
+            position += 1;
+        }
+
+        let mut records = Vec::with_capacity(order.len() * RECORD_LENGTH);
+        let mut offsets = vec![0u64];
+        let mut payloads = Vec::new();
+        let mut next_child = 1;
+
+        for &index in order.iter() {
+            let node = self.get_node(index);
+            let region = node.get_region();
+
+            for value in [
+                region.min().x,
+                region.min().y,
+                region.max().x,
+                region.max().y,
+            ] {
+                records.extend_from_slice(&value.to_f64().unwrap_or(f64::NAN).to_le_bytes());
+            }
+
+            match node.get_data() {
+                Some(data) => {
+                    records.extend_from_slice(&((offsets.len() - 1) as u64).to_le_bytes());
+                    records.extend_from_slice(&0u32.to_le_bytes());
+                    records.extend_from_slice(&KIND_LEAF.to_le_bytes());
+
+                    data.encode(&mut payloads);
+                    offsets.push(payloads.len() as u64);
+                }
+                None => {
+                    records.extend_from_slice(&(next_child as u64).to_le_bytes());
+                    records.extend_from_slice(&(node.child_count() as u32).to_le_bytes());
+                    records.extend_from_slice(&KIND_INTERNAL.to_le_bytes());
+
+                    next_child += node.child_count();
+                }
+            }
+        }
+
+        let mut header = [0; HEADER_LENGTH];
+        header[..8].copy_from_slice(MAGIC);
+        header[8..10].copy_from_slice(&VERSION.to_le_bytes());
+        header[16..24].copy_from_slice(&(order.len() as u64).to_le_bytes());
+        header[24..32].copy_from_slice(&((offsets.len() - 1) as u64).to_le_bytes());
+
+        writer.write_all(&header)?;
+        writer.write_all(&records)?;
+        for offset in offsets {
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        writer.write_all(&payloads)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// A read-only tree which answers queries directly from bytes written by
+/// [`RTree::write_mapped`], typically a memory-mapped file.
+///
+/// Entries are identified by their ordinal, a number in `0..len()`.
+///
+/// Only the header and the lengths of each section are checked when the tree is created, so
+/// opening a large file is cheap.  Every node record and payload offset is bounds-checked as it
+/// is read, so queries on damaged bytes never read out of bounds or loop forever, though they
+/// may return the wrong entries; [`MappedRTree::validate`] checks the whole tree up front.
+#[derive(Debug)]
+pub struct MappedRTree<B: AsRef<[u8]>> {
+    /// The underlying bytes.
+    bytes: B,
+
+    /// The number of nodes in the tree.
+    node_count: usize,
+
+    /// The number of entries in the tree.
+    len: usize,
+
+    /// The offset of the payload offsets table in `bytes`.
+    offsets_start: usize,
+
+    /// The offset of the payloads in `bytes`.
+    payloads_start: usize,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedRTree<memmap2::Mmap> {
+    /// Memory-maps the file at `path`, which should have been written by
+    /// [`RTree::write_mapped`].
+    ///
+    /// The file must not be modified while it is mapped.
+    ///
+    /// # Errors
+    /// This function will return an error if the file can't be mapped, or doesn't hold a valid
+    /// tree.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, RTreeError> {
+        let file = std::fs::File::open(path)?;
+
+        // This is sound as long as nobody modifies the file while it's mapped, as documented.
+        let bytes = unsafe { memmap2::Mmap::map(&file)? };
+
+        Self::new(bytes)
+    }
+}
+
+impl<B: AsRef<[u8]>> MappedRTree<B> {
+    /// Creates a [`MappedRTree`] over `bytes`, which should have been written by
+    /// [`RTree::write_mapped`].
+    ///
+    /// This takes constant time: only the header and the lengths of each section are checked.
+    /// Use [`MappedRTree::validate`] to check the rest of the tree too.
+    ///
+    /// # Errors
+    /// This function will return:
+    /// - [`RTreeError::Truncated`] if `bytes` ends early,
+    /// - [`RTreeError::BadMagic`] if `bytes` doesn't hold a mapped tree,
+    /// - [`RTreeError::UnsupportedVersion`] if `bytes` was written by a newer version of the
+    ///   layout, or
+    /// - [`RTreeError::Corrupt`] if `bytes` doesn't describe a valid tree.
+    pub fn new(bytes: B) -> Result<Self, RTreeError> {
+        let data = bytes.as_ref();
+
+        if data.len() < MAGIC.len() {
+            return Err(RTreeError::Truncated);
+        }
+        if &data[..MAGIC.len()] != MAGIC {
+            return Err(RTreeError::BadMagic);
+        }
+        if data.len() < HEADER_LENGTH {
+            return Err(RTreeError::Truncated);
+        }
+
+        let version = u16::from_le_bytes(data[8..10].try_into().unwrap());
+        if version != VERSION {
+            return Err(RTreeError::UnsupportedVersion(version));
+        }
+
+        let too_large = || RTreeError::Corrupt("tree is too large for this platform");
+        let node_count = usize::try_from(read_u64(data, 16)).map_err(|_| too_large())?;
+        let len = usize::try_from(read_u64(data, 24)).map_err(|_| too_large())?;
+
+        let offsets_start = node_count
+            .checked_mul(RECORD_LENGTH)
+            .and_then(|length| length.checked_add(HEADER_LENGTH))
+            .ok_or_else(too_large)?;
+        let payloads_start = len
+            .checked_add(1)
+            .and_then(|count| count.checked_mul(8))
+            .and_then(|length| length.checked_add(offsets_start))
+            .ok_or_else(too_large)?;
+
+        if data.len() < payloads_start {
+            return Err(RTreeError::Truncated);
+        }
+
+        // The last payload offset gives the length of the payload section.
+        if read_u64(data, payloads_start - 8) != (data.len() - payloads_start) as u64 {
+            return Err(RTreeError::Corrupt(
+                "payload offsets don't match the payloads",
+            ));
+        }
+
+        let tree = Self {
+            bytes,
+            node_count,
+            len,
+            offsets_start,
+            payloads_start,
+        };
+
+        // Every entry is a leaf, which isn't the root.
+        if node_count == 0 || tree.record(0).kind != KIND_INTERNAL {
+            return Err(RTreeError::Corrupt("the root is missing or is a leaf"));
+        }
+        if len >= node_count {
+            return Err(RTreeError::Corrupt("there are more entries than nodes"));
+        }
+
+        Ok(tree)
+    }
+
+    /// Checks that the node records describe a tree, and that the payload offsets are in
+    /// bounds.  This takes time linear in the size of the tree, as every node record and
+    /// payload offset is read.
+    ///
+    /// Queries on a tree which fails this check are still safe, as every read is
+    /// bounds-checked, but may return the wrong entries.
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::Corrupt`] if the tree isn't valid.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    /// use spaceindex::rtree::mapped::MappedRTree;
+    ///
+    /// let tree = (0..20)
+    ///     .map(|i| (Rect::new((i as f64, 0.0), (i as f64 + 1.0, 1.0)), i as u32))
+    ///     .collect::<RTree<_, f64>>();
+    ///
+    /// let mut bytes = Vec::new();
+    /// tree.write_mapped(&mut bytes).unwrap();
+    ///
+    /// let mapped = MappedRTree::new(bytes).unwrap();
+    /// mapped.validate().unwrap();
+    /// ```
+    pub fn validate(&self) -> Result<(), RTreeError> {
+        // Nodes are stored breadth-first, so the children of the internal nodes, taken in
+        // order, must exactly tile the nodes after the root, and every node must come before
+        // its children.  This means every node other than the root has exactly one parent,
+        // so queries always terminate.  Leaves are all on the last level of the tree, so come
+        // last, in order of their ordinals.
+        let mut next_child = 1;
+        let mut leaf_count = 0;
+
+        for index in 0..self.node_count {
+            let record = self.record(index);
+
+            match record.kind {
+                KIND_INTERNAL => {
+                    if record.first != next_child as u64
+                        || (record.count > 0 && record.first <= index as u64)
+                    {
+                        return Err(RTreeError::Corrupt("nodes are not in breadth-first order"));
+                    }
+                    next_child += record.count;
+                }
+                KIND_LEAF => {
+                    if record.count != 0
+                        || record.first != leaf_count as u64
+                        || index + self.len != self.node_count + leaf_count
+                    {
+                        return Err(RTreeError::Corrupt("leaf has an invalid entry ordinal"));
+                    }
+                    leaf_count += 1;
+                }
+                _ => return Err(RTreeError::Corrupt("unknown node kind")),
+            }
+        }
+
+        if next_child != self.node_count {
+            return Err(RTreeError::Corrupt("children don't match the node count"));
+        }
+        if leaf_count != self.len {
+            return Err(RTreeError::Corrupt("leaves don't match the entry count"));
+        }
+
+        // Payload offsets must be non-decreasing, and end exactly at the end of the bytes.
+        let payload_length = self.bytes.as_ref().len() - self.payloads_start;
+        let mut previous = 0;
+        for ordinal in 0..=self.len {
+            let offset = self.offset(ordinal);
+
+            if offset < previous || offset > payload_length as u64 {
+                return Err(RTreeError::Corrupt("payload offsets are out of bounds"));
+            }
+            previous = offset;
+        }
+        if previous != payload_length as u64 {
+            return Err(RTreeError::Corrupt("trailing bytes after the last payload"));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of entries in the tree.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the tree contains no entries, `false` otherwise.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the encoded payload of the entry `ordinal`, if it exists and its payload offsets
+    /// are in bounds.
+    pub fn payload(&self, ordinal: usize) -> Option<&[u8]> {
+        self.try_payload(ordinal).ok()
+    }
+
+    /// Returns the region of the entry `ordinal` along with its decoded payload.
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::ItemNotFound`] if there is no entry `ordinal`,
+    /// [`RTreeError::Corrupt`] if its payload offsets are out of bounds, or
+    /// [`RTreeError::Decode`] if its payload fails to decode.
+    pub fn get<ND: Decode>(&self, ordinal: usize) -> Result<(Rect<f64>, ND), RTreeError> {
+        let payload = self.try_payload(ordinal)?;
+        let data = ND::decode(payload).map_err(|error| RTreeError::Decode(Box::new(error)))?;
+
+        Ok((self.entry_region(ordinal), data))
+    }
+
+    /// Returns the region of the entry `ordinal`, if it exists.
+    pub fn region(&self, ordinal: usize) -> Option<Rect<f64>> {
+        (ordinal < self.len).then(|| self.entry_region(ordinal))
+    }
+
+    /// Returns the ordinals of those entries intersecting the given point `point`.
+    pub fn point_lookup<P: Into<Point<f64>>>(&self, point: P) -> Vec<usize> {
+        let point = point.into();
+
+        self.region_intersection_lookup(Rect::new(point.0, point.0))
+    }
+
+    /// Returns the ordinals of those entries whose region intersects the given region.
+    pub fn region_intersection_lookup(&self, region: Rect<f64>) -> Vec<usize> {
+        let mut hits = Vec::new();
+        let mut work_queue = vec![0];
+
+        while let Some(index) = work_queue.pop() {
+            let record = self.record(index);
+
+            if record.kind == KIND_LEAF {
+                hits.extend(self.ordinal(&record));
+                continue;
+            }
+
+            for child_index in self.children(index, &record) {
+                if intersects(self.record(child_index).region, region) {
+                    work_queue.push(child_index);
+                }
+            }
+        }
+
+        hits
+    }
+
+    /// Returns up to `k` pairs `(ordinal, distance)` of the entries closest to `point` as
+    /// measured by `metric`, ordered by increasing distance.
+    pub fn nearest_neighbors<P: Into<Point<f64>>, M: Metric<f64>>(
+        &self,
+        point: P,
+        k: usize,
+        metric: M,
+    ) -> Vec<(usize, f64)> {
+        let point = point.into();
+        let mut hits = Vec::new();
+
+        if k == 0 {
+            return hits;
+        }
+
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: 0.0,
+            index: 0,
+        });
+
+        while let Some(Candidate { distance, index }) = queue.pop() {
+            let record = self.record(index);
+
+            if record.kind == KIND_LEAF {
+                hits.extend(self.ordinal(&record).map(|ordinal| (ordinal, distance)));
+
+                if hits.len() == k {
+                    break;
+                }
+
+                continue;
+            }
+
+            for child_index in self.children(index, &record) {
+                queue.push(Candidate {
+                    distance: metric.distance_to_rect(point, self.record(child_index).region),
+                    index: child_index,
+                });
+            }
+        }
+
+        hits
+    }
+
+    /// Returns the region of the entry `ordinal`, which must exist.  Leaves are the last nodes
+    /// in breadth-first order, and appear in order of their ordinals.
+    fn entry_region(&self, ordinal: usize) -> Rect<f64> {
+        self.record(self.node_count - self.len + ordinal).region
+    }
+
+    /// Returns the payload of the entry `ordinal`, checking that its offsets are in bounds.
+    fn try_payload(&self, ordinal: usize) -> Result<&[u8], RTreeError> {
+        if ordinal >= self.len {
+            return Err(RTreeError::ItemNotFound);
+        }
+
+        let payload_length = (self.bytes.as_ref().len() - self.payloads_start) as u64;
+        let (start, end) = (self.offset(ordinal), self.offset(ordinal + 1));
+        if start > end || end > payload_length {
+            return Err(RTreeError::Corrupt("payload offsets are out of bounds"));
+        }
+
+        Ok(&self.bytes.as_ref()[self.payloads_start + start as usize..][..(end - start) as usize])
+    }
+
+    /// Returns the ordinal of the leaf `record`, if it is in range.
+    fn ordinal(&self, record: &Record) -> Option<usize> {
+        (record.first < self.len as u64).then_some(record.first as usize)
+    }
+
+    /// Returns the indexes of the children of the internal node `index`, or an empty range if
+    /// they are out of bounds.  Children must come after their parent, so traversals always
+    /// terminate, even over damaged records.
+    fn children(&self, index: usize, record: &Record) -> Range<usize> {
+        usize::try_from(record.first)
+            .ok()
+            .filter(|&first| first > index)
+            .and_then(|first| Some(first..first.checked_add(record.count)?))
+            .filter(|children| children.end <= self.node_count)
+            .unwrap_or(0..0)
+    }
+
+    /// Reads the record of the node `index`, which must be less than `node_count`.
+    fn record(&self, index: usize) -> Record {
+        let start = HEADER_LENGTH + index * RECORD_LENGTH;
+        let data = &self.bytes.as_ref()[start..start + RECORD_LENGTH];
+        let float =
+            |offset: usize| f64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+        Record {
+            region: Rect::new((float(0), float(8)), (float(16), float(24))),
+            first: read_u64(data, 32),
+            count: u32::from_le_bytes(data[40..44].try_into().unwrap()) as usize,
+            kind: u32::from_le_bytes(data[44..48].try_into().unwrap()),
+        }
+    }
+
+    /// Reads payload offset `ordinal`, which must be at most `len`.
+    fn offset(&self, ordinal: usize) -> u64 {
+        read_u64(self.bytes.as_ref(), self.offsets_start + ordinal * 8)
+    }
+}
+
+/// A node record read from the mapped bytes.
+struct Record {
+    region: Rect<f64>,
+    first: u64,
+    count: usize,
+    kind: u32,
+}
+
+/// Reads a little-endian `u64` from `bytes` at `offset`.
+#[inline(always)]
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Returns `true` if the closed rectangles `left` and `right` intersect.
+#[inline(always)]
+fn intersects(left: Rect<f64>, right: Rect<f64>) -> bool {
+    left.min().x <= right.max().x
+        && right.min().x <= left.max().x
+        && left.min().y <= right.max().y
+        && right.min().y <= left.max().y
+}
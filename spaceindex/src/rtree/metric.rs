@@ -0,0 +1,98 @@
+use geo_types::{CoordFloat, Point, Rect};
+
+/// A distance function used by the proximity queries on [`RTree`](crate::RTree).
+///
+/// Implementations only need to provide [`Metric::distance`].  The default implementation
+/// of [`Metric::distance_to_rect`] measures the distance to the point of the rectangle closest
+/// to `point` along each axis, which is the exact distance for every metric that is monotone
+/// in the per-axis differences (this includes all of the metrics in this module).
+pub trait Metric<T>
+where
+    T: CoordFloat,
+{
+    /// Returns the distance between the points `a` and `b`.
+    fn distance(&self, a: Point<T>, b: Point<T>) -> T;
+
+    /// Returns the distance between `point` and the closest point of `rect`.
+    ///
+    /// This is used both as the distance to a leaf and as a lower bound on the distance to
+    /// anything stored beneath an internal node, so it must never be larger than the distance
+    /// from `point` to any point contained in `rect`.  Otherwise subtrees could be pruned
+    /// incorrectly.
+    fn distance_to_rect(&self, point: Point<T>, rect: Rect<T>) -> T {
+        self.distance(point, closest_point(point, rect))
+    }
+}
+
+impl<T, M> Metric<T> for &M
+where
+    T: CoordFloat,
+    M: Metric<T> + ?Sized,
+{
+    #[inline(always)]
+    fn distance(&self, a: Point<T>, b: Point<T>) -> T {
+        (**self).distance(a, b)
+    }
+
+    #[inline(always)]
+    fn distance_to_rect(&self, point: Point<T>, rect: Rect<T>) -> T {
+        (**self).distance_to_rect(point, rect)
+    }
+}
+
+/// The usual straight line distance.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Euclidean;
+
+impl<T: CoordFloat> Metric<T> for Euclidean {
+    #[inline(always)]
+    fn distance(&self, a: Point<T>, b: Point<T>) -> T {
+        (a.x() - b.x()).hypot(a.y() - b.y())
+    }
+}
+
+/// The square of the [`Euclidean`] distance.  This orders results identically to [`Euclidean`]
+/// while avoiding a square root for every node visited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SquaredEuclidean;
+
+impl<T: CoordFloat> Metric<T> for SquaredEuclidean {
+    #[inline(always)]
+    fn distance(&self, a: Point<T>, b: Point<T>) -> T {
+        let (dx, dy) = (a.x() - b.x(), a.y() - b.y());
+        dx * dx + dy * dy
+    }
+}
+
+/// The taxicab distance, i.e. the sum of the absolute differences along each axis.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Manhattan;
+
+impl<T: CoordFloat> Metric<T> for Manhattan {
+    #[inline(always)]
+    fn distance(&self, a: Point<T>, b: Point<T>) -> T {
+        (a.x() - b.x()).abs() + (a.y() - b.y()).abs()
+    }
+}
+
+/// The chessboard distance, i.e. the largest absolute difference along any axis.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Chebyshev;
+
+impl<T: CoordFloat> Metric<T> for Chebyshev {
+    #[inline(always)]
+    fn distance(&self, a: Point<T>, b: Point<T>) -> T {
+        (a.x() - b.x()).abs().max((a.y() - b.y()).abs())
+    }
+}
+
+/// Returns the point of `rect` closest to `point` along each axis.
+#[inline(always)]
+fn closest_point<T: CoordFloat>(point: Point<T>, rect: Rect<T>) -> Point<T> {
+    let (min, max) = (rect.min(), rect.max());
+
+    Point::new(
+        point.x().max(min.x).min(max.x),
+        point.y().max(min.y).min(max.y),
+    )
+}
@@ -10,26 +10,97 @@ use geo::kernels::HasKernel;
 use geo_types::{CoordFloat, CoordNum, Coordinate, Geometry, Line, Point, Rect};
 use thiserror::Error;
 
+pub use iter::IntoIter;
+pub use map::RTreeMap;
 pub use node::Node;
 
+pub mod binary;
+mod bulk;
+mod eq;
+mod iter;
+mod map;
+pub mod mapped;
+pub mod metric;
+pub mod moving;
+mod nearest;
 mod node;
+pub mod paged;
+mod periodic;
+mod remove;
 pub mod rendering;
+#[cfg(feature = "serde")]
+mod serialization;
 #[cfg(test)]
 mod tests;
+pub mod wal;
 
+/// An error from one of the trees in this crate.
+///
+/// Some variants only exist when the feature they belong to is enabled, and new variants may
+/// be added, so matches on this type need a wildcard arm.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum RTreeError {
     #[error("failed to insert item in tree")]
     FailedToInsert,
+    #[error("region is larger than the periodic domain of the tree")]
+    RegionLargerThanDomain,
+    #[error("time is before the reference time of the tree")]
+    TimeBeforeReference,
+    #[error("item not found in tree")]
+    ItemNotFound,
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unexpected end of input")]
+    Truncated,
+    #[error("input is not a spaceindex file")]
+    BadMagic,
+    #[error("unsupported format version {0}")]
+    UnsupportedVersion(u16),
+    #[error("checksum mismatch in {0} section")]
+    ChecksumMismatch(&'static str),
+    #[error("corrupt tree: {0}")]
+    Corrupt(&'static str),
+    #[error("failed to decode payload: {0}")]
+    Decode(Box<dyn std::error::Error + Send + Sync>),
+    #[error("payload is too large to store in a page")]
+    PayloadTooLarge,
+    #[cfg(feature = "geojson")]
+    #[error("invalid GeoJSON: {0}")]
+    GeoJson(Box<geojson::Error>),
+    #[cfg(feature = "wkt")]
+    #[error("invalid WKT: {0}")]
+    Wkt(String),
+    #[cfg(feature = "wkt")]
+    #[error("invalid WKB: {0}")]
+    Wkb(&'static str),
+    #[cfg(feature = "csv")]
+    #[error("invalid CSV on line {line}: {message}")]
+    Csv { line: u64, message: String },
 }
 
-#[derive(Debug)]
+#[cfg(feature = "geojson")]
+impl From<geojson::Error> for RTreeError {
+    fn from(error: geojson::Error) -> Self {
+        // GeoJSON errors can hold an entire feature, so are boxed to keep `RTreeError` small.
+        RTreeError::GeoJson(Box::new(error))
+    }
+}
+
+/// An opaque handle to an element stored in an [`RTree`].
+///
+/// An `ItemId` only ever refers to a leaf of the tree, and stays valid until that element is
+/// removed from the tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ItemId(Index);
+
+#[derive(Clone, Debug)]
 pub struct RTree<ND, T>
 where
     T: CoordFloat + HasKernel,
 {
     /// Nodes are stored in a generational arena.
-    pub nodes: Arena<Node<ND, T>>,
+    nodes: Arena<Node<ND, T>>,
 
     /// The index of the root node of this tree.
     root: Index,
@@ -39,6 +110,13 @@ where
 
     /// The maximum number of children a node can have
     max_children: usize,
+
+    /// The periodic domain of this tree, if any.  Entries are wrapped into this domain, and
+    /// queries find entries across its boundaries.
+    domain: Option<Rect<T>>,
+
+    /// The number of elements (i.e. leaves) in this tree.
+    len: usize,
 }
 
 impl<ND, T> Default for RTree<ND, T>
@@ -77,13 +155,16 @@ where
             root: root_index,
             min_children: 2,
             max_children: 8,
+            domain: None,
+            len: 0,
         }
     }
 
-    /// Attempts to insert a given object into the tree.
+    /// Attempts to insert a given object into the tree, returning the [`ItemId`] of the new element.
     ///
     /// # Errors
-    /// This function will return an error if `region` does not have the same dimension as this tree.
+    /// This function will return an error if this tree has a periodic domain and `region` is
+    /// larger than it.
     ///
     /// # Example
     /// ```rust
@@ -95,9 +176,176 @@ where
     ///
     /// # tree.validate_consistency();
     /// ```
-    pub fn insert(&mut self, region: Rect<T>, data: ND) -> Result<(), RTreeError> {
-        // If we only have the root node, then set the MBR of the root node to be our input region.
-        if self.nodes.len() == 1 {
+    pub fn insert(&mut self, region: Rect<T>, data: ND) -> Result<ItemId, RTreeError> {
+        let region = self.wrap_region(region)?;
+
+        // Add the new leaf to our arena, then attach it to the tree.
+        let leaf_index = self.nodes.insert(Node::new_leaf(region, data, None));
+        self.insert_leaf(leaf_index)?;
+        self.len += 1;
+
+        Ok(ItemId(leaf_index))
+    }
+
+    /// Returns references to the region and data of the element `id`, if it is in this tree.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// let id = tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a').unwrap();
+    ///
+    /// assert_eq!(tree.get(id), Some((&Rect::new((0.0, 0.0), (1.0, 1.0)), &'a')));
+    ///
+    /// tree.remove(id);
+    /// assert_eq!(tree.get(id), None);
+    /// ```
+    #[inline(always)]
+    pub fn get(&self, id: ItemId) -> Option<(&Rect<T>, &ND)> {
+        let node = self.nodes.get(id.0)?;
+
+        node.get_data().map(|data| (node.get_region_ref(), data))
+    }
+
+    /// Returns a mutable reference to the data of the element `id`, if it is in this tree.
+    /// The region of an element can be changed with [`RTree::update_region`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// let id = tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 1).unwrap();
+    ///
+    /// *tree.get_mut(id).unwrap() += 1;
+    /// assert_eq!(tree.get(id).unwrap().1, &2);
+    /// ```
+    #[inline(always)]
+    pub fn get_mut(&mut self, id: ItemId) -> Option<&mut ND> {
+        self.nodes.get_mut(id.0)?.get_data_mut()
+    }
+
+    /// Returns the number of elements in the tree.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the tree contains no elements, `false` otherwise.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the minimum number of children of every node other than the root.
+    #[inline(always)]
+    pub fn min_children(&self) -> usize {
+        self.min_children
+    }
+
+    /// Returns the number of children at which a node is split.  Nodes never have more than
+    /// `max_children - 1` children once an insert completes.
+    #[inline(always)]
+    pub fn max_children(&self) -> usize {
+        self.max_children
+    }
+
+    /// Returns an iterator over every element in the tree, in no particular order.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 1);
+    /// tree.insert(Rect::new((1.0, 1.0), (2.0, 2.0)), 2);
+    ///
+    /// assert_eq!(tree.iter().map(|(_, _, data)| data).sum::<i32>(), 3);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (ItemId, &Rect<T>, &ND)> + '_ {
+        self.nodes.iter().filter_map(|(index, node)| {
+            node.get_data()
+                .map(|data| (ItemId(index), node.get_region_ref(), data))
+        })
+    }
+
+    /// Returns an iterator over every element in the tree, in no particular order, allowing
+    /// the data of each element to be modified.  Regions can't be modified this way, as that
+    /// would break the invariants of the tree; use [`RTree::update_region`] instead.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), false);
+    /// tree.insert(Rect::new((5.0, 5.0), (6.0, 6.0)), false);
+    ///
+    /// // Select everything to the left of x = 3.0
+    /// for (_, region, selected) in tree.iter_mut() {
+    ///     *selected = region.max().x < 3.0;
+    /// }
+    ///
+    /// assert_eq!(tree.iter().filter(|(_, _, selected)| **selected).count(), 1);
+    /// ```
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (ItemId, &Rect<T>, &mut ND)> + '_ {
+        self.nodes.iter_mut().filter_map(|(index, node)| {
+            node.get_region_and_data_mut()
+                .map(|(region, data)| (ItemId(index), region, data))
+        })
+    }
+
+    /// Removes every element from the tree.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), ());
+    ///
+    /// tree.clear();
+    /// assert!(tree.is_empty());
+    /// assert!(tree.point_lookup((0.5, 0.5)).is_empty());
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn clear(&mut self) {
+        let node = Node::new_internal_node(Rect::new(Coordinate::zero(), Coordinate::zero()), None);
+
+        self.nodes.clear();
+        self.root = self.nodes.insert(node);
+        self.len = 0;
+    }
+
+    /// Removes every element from the tree, returning an iterator over the removed
+    /// pairs `(region, data)` in no particular order.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a');
+    ///
+    /// assert_eq!(tree.drain().collect::<Vec<_>>(), vec![(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a')]);
+    /// assert!(tree.is_empty());
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn drain(&mut self) -> IntoIter<ND, T> {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.clear();
+
+        IntoIter::new(nodes)
+    }
+
+    /// Attaches the detached leaf with index `leaf_index` to the tree.  This is also used to
+    /// reinsert orphaned leaves, so that the `Index` of a leaf never changes while it is in the tree.
+    pub(crate) fn insert_leaf(&mut self, leaf_index: Index) -> Result<(), RTreeError> {
+        let region = self.get_node(leaf_index).get_region();
+
+        // If the root node is empty, then set the MBR of the root node to be our input region.
+        if !self.root_node().has_children() {
             // This call is fine because the root node currently has no children.
             self.get_node_mut(self.root)
                 .set_minimum_bounding_region_unsafe(region);
@@ -109,19 +357,19 @@ where
         }
 
         // The internal `root` node always contains everything.
-        self.insert_at_node(region, data, self.root)
+        self.insert_at_node(region, leaf_index, self.root)
     }
 
-    /// Inserts a node with data `data` into the tree at the given index.  This function is unsafe
-    /// as using it incorrectly can use to inconsistent data.  A key assumption here is that
-    /// `region` must be contained in the minimum bounding region of the node corresponding to `index`.
-    fn _insert(&mut self, region: Rect<T>, data: ND, index: Index) {
+    /// Attaches the leaf `leaf_index` to the tree as a child of the node at the given index.
+    /// This function is unsafe as using it incorrectly can use to inconsistent data.  A key
+    /// assumption here is that `region` must be contained in the minimum bounding region of the
+    /// node corresponding to `index`.
+    fn _insert(&mut self, region: Rect<T>, leaf_index: Index, index: Index) {
         // Parent node should always contain the input region
         assert!(self.nodes[index].get_region().contains(&region),);
 
-        // add a new leaf as a child of this node
-        let leaf_node = Node::new_leaf(region, data, Some(index));
-        let leaf_index = self.nodes.insert(leaf_node);
+        // make the leaf a child of this node
+        self.get_node_mut(leaf_index).set_parent(index);
 
         // This call is safe as `leaf_index` has their parent attribute set to `Some(index)`, i.e.
         // the index of the current node, and the child node is contained in this tree.
@@ -137,7 +385,7 @@ where
     fn insert_at_node(
         &mut self,
         region: Rect<T>,
-        data: ND,
+        leaf_index: Index,
         index: Index,
     ) -> Result<(), RTreeError> {
         // current node under consideration
@@ -148,88 +396,30 @@ where
             // If we've reached a leaf node, insert this as a leaf of the parent
             // This call is safe as `region` is guaranteed to be contained in the minimum
             // bounding region of this node.
-            self._insert(region, data, index);
+            self._insert(region, leaf_index, index);
             return Ok(());
         }
 
-        // Does any child of this node have an MBR containing our input region?
-        let mut child_containing_region = None;
-
-        'mbr_search: for (child_index, child_node) in self.child_iter(index) {
-            if child_node.get_region().contains(&region) {
-                child_containing_region = Some(child_index);
-                break 'mbr_search;
-            }
-        }
-
-        // If we found a child node containing our region, recurse into that node
-        if let Some(child_index) = child_containing_region {
-            return self.insert_at_node(region, data, child_index);
-        }
-
-        // Otherwise there is no child MBR containing our input `region`.  Thus find
-        // the bounding box in this node such that enlarging it to contain
-        // `minimum_bounding_region` will add the least amount of area.
-        if let Some((_, combined_region, child_index)) = self
+        // Find the child to descend into, along with its region enlarged to contain `region`.
+        let regions = self
             .child_iter(index)
-            .map(|(child_index, child_node)| {
-                let initial_area = child_node.get_region().unsigned_area();
-                let combined_region = combine_rects(child_node.get_region(), region);
-                (
-                    combined_region.unsigned_area() - initial_area,
-                    combined_region,
-                    child_index,
-                )
-            })
-            .min_by(|(left_change, _, _), (right_change, _, _)| {
-                // TODO: this should be fine, but worth investigating.
-                left_change.partial_cmp(right_change).unwrap()
-            })
-        {
-            // Enlarge `child_index`'s bounding box.  This call is safe as `combined_region`
-            // is enlarged from the MBR of the child node.
-            self.get_node_mut(child_index)
-                .set_minimum_bounding_region_unsafe(combined_region);
-
-            // Since the enlarged bounding box now contains our object, recurse into that subtree
-            return self.insert_at_node(region, data, child_index);
-        }
-
-        panic!("something weird happened");
-    }
-
-    /// Given a set of nodes, finds the pair of nodes whose combined bounding box is
-    /// the worst.  To be concrete, we find the pair whose combined bounding box
-    /// has the maximum difference to the sum of the areas of the bounding boxes
-    /// for the original two nodes.
-    fn find_worst_pair(&self, leaves: &[Index]) -> (usize, usize) {
-        // This would be silly.
-        debug_assert!(leaves.len() >= 2);
-
-        let mut worst_pair = None;
-        let mut worst_area = T::neg_infinity();
-
-        // find the two leaves of this node that would be the most terrible together
-        for (l1_index, node1) in leaves.iter().enumerate() {
-            let r1 = &self.nodes[*node1].get_region();
-            let a1 = r1.unsigned_area();
-
-            for (l2_index, node2) in leaves.iter().enumerate().skip(l1_index + 1) {
-                let r2 = &self.nodes[*node2].get_region();
-                let a2 = r2.unsigned_area();
+            .map(|(_, child_node)| child_node.get_region())
+            .collect::<Vec<_>>();
+        let (position, combined_region) = choose_subtree(&regions, region);
+        let child_index = self
+            .get_node(index)
+            .child_index_iter()
+            .nth(position)
+            .unwrap();
 
-                // combine these two regions together
-                let combined_region = combine_rects(*r1, *r2);
-                let combined_area = combined_region.unsigned_area() - a1 - a2;
+        // Enlarge `child_index`'s bounding box.  This call is safe as `combined_region`
+        // is enlarged from the MBR of the child node.
+        self.get_node_mut(child_index)
+            .set_minimum_bounding_region_unsafe(combined_region);
 
-                if combined_area > worst_area {
-                    worst_pair = Some((l1_index, l2_index));
-                    worst_area = combined_area;
-                }
-            }
-        }
-
-        worst_pair.unwrap()
+        // Since the (possibly enlarged) bounding box now contains our object, recurse into
+        // that subtree
+        self.insert_at_node(region, leaf_index, child_index)
     }
 
     /// Splits a vector of nodes into two groups using the QuadraticSplit algorithm.
@@ -237,87 +427,14 @@ where
         &self,
         children: Vec<Index>,
     ) -> (Vec<Index>, Vec<Index>, Rect<T>, Rect<T>) {
-        let (ix1, ix2) = self.find_worst_pair(&children);
-
-        let mut unpicked_children: HashSet<usize> = (0..children.len()).collect();
-        unpicked_children.remove(&ix1);
-        unpicked_children.remove(&ix2);
-
-        // Keep track of nodes in the first group
-        let mut group1 = Vec::with_capacity(self.max_children - self.min_children);
-        group1.push(ix1);
-
-        // Keep track of the minimum bounding regions for the first and second group
-        let mut group1_mbr = self.nodes[children[ix1]].get_region();
-        let mut group2_mbr = self.nodes[children[ix2]].get_region();
-
-        // Partition the nodes into two groups.  The basic strategy is that at each stepp
-        // we find the unpicked node
-        // If one of the groups gets too large, stop.
-        while !unpicked_children.is_empty()
-            && group1.len() < self.max_children - self.min_children
-            && (children.len() - group1.len() - unpicked_children.len())
-                < self.max_children - self.min_children
-        {
-            let mut best_d = T::infinity();
-            let mut best_index = None;
-
-            for &index in unpicked_children.iter() {
-                let g1r = combine_rects(group1_mbr, self.nodes[children[index]].get_region());
-                let g2r = combine_rects(group2_mbr, self.nodes[children[index]].get_region());
-
-                let d1 = g1r.unsigned_area() - group1_mbr.unsigned_area();
-                let d2 = g2r.unsigned_area() - group2_mbr.unsigned_area();
-
-                if d1 < d2 && d1 < best_d {
-                    best_index = Some((index, 1));
-                    best_d = d1;
-                } else if d2 < d1 && d2 < best_d {
-                    best_index = Some((index, 2));
-                    best_d = d2;
-                } else if (d1 - d2).abs() < T::epsilon() && d1 < best_d {
-                    // in case of ties, assign to MBR with smallest area
-                    if group1_mbr.unsigned_area() < group2_mbr.unsigned_area() {
-                        best_index = Some((index, 1));
-                    } else {
-                        best_index = Some((index, 2));
-                    }
-                    best_d = d1;
-                }
-            }
-
-            let (best_index, side) = best_index.unwrap();
-            unpicked_children.remove(&best_index);
-
-            if side == 1 {
-                // add to group 1
-                group1.push(best_index);
-                group1_mbr =
-                    combine_rects(group1_mbr, self.nodes[children[best_index]].get_region());
-            } else {
-                group2_mbr =
-                    combine_rects(group2_mbr, self.nodes[children[best_index]].get_region());
-            }
-        }
+        let regions = children
+            .iter()
+            .map(|&child_index| self.nodes[child_index].get_region())
+            .collect::<Vec<_>>();
 
-        if !unpicked_children.is_empty() {
-            if group1.len() < self.min_children {
-                // rest of the unpicked children go in group 1
-                for child_index in unpicked_children {
-                    group1_mbr =
-                        combine_rects(group1_mbr, self.nodes[children[child_index]].get_region());
-                    group1.push(child_index);
-                }
-            } else {
-                // rest of the unpicked children go in group 2
-                for child_index in unpicked_children {
-                    group2_mbr =
-                        combine_rects(group2_mbr, self.nodes[children[child_index]].get_region());
-                }
-            }
-        }
-
-        let (group1, group2) = Self::assemble(children, group1.into_iter().collect());
+        let (group1, group1_mbr, group2_mbr) =
+            quadratic_partition(&regions, self.min_children, self.max_children);
+        let (group1, group2) = Self::assemble(children, group1);
 
         (group1, group2, group1_mbr, group2_mbr)
     }
@@ -449,35 +566,69 @@ where
     /// - Every child is contained in the minimum bounding region of its parent, and
     /// - The total number of descendants of the root node is equal to the number
     ///   of nodes in the tree minus one.
+    ///
+    /// # Panics
+    /// This function will panic if the tree is inconsistent.  Use [`RTree::check_consistency`]
+    /// to get an error instead.
     pub fn validate_consistency(&self) {
+        if let Err(error) = self.check_consistency() {
+            panic!("{}", error);
+        }
+    }
+
+    /// Checks the consistency of the tree, as described in [`RTree::validate_consistency`].
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::Corrupt`] describing the first problem found
+    /// if the tree is inconsistent.
+    pub fn check_consistency(&self) -> Result<(), RTreeError> {
         let mut node_counter = 0;
 
-        self._validate_consistency(self.root, &mut node_counter);
+        self._check_consistency(self.root, &mut node_counter)?;
 
         // check we have the expected number of nodes.
-        assert_eq!(node_counter, self.nodes.len());
+        if node_counter != self.nodes.len() {
+            return Err(RTreeError::Corrupt("tree contains unreachable nodes"));
+        }
+
+        Ok(())
     }
 
-    /// Recursively validates that the children of each node are contained in the MBR
+    /// Recursively checks that the children of each node are contained in the MBR
     /// of their parent.
-    fn _validate_consistency(&self, index: Index, node_counter: &mut usize) {
-        let node = &self.nodes[index];
+    fn _check_consistency(&self, index: Index, node_counter: &mut usize) -> Result<(), RTreeError> {
+        let node = self.nodes.get(index).ok_or(RTreeError::Corrupt(
+            "child does not refer to a node in the tree",
+        ))?;
 
-        // increment the node counter
+        // increment the node counter, bailing out if we've seen more nodes than there are
         *node_counter += 1;
+        if *node_counter > self.nodes.len() {
+            return Err(RTreeError::Corrupt("tree contains a cycle"));
+        }
+
+        for child_index in node.child_index_iter() {
+            let child_node = self.nodes.get(child_index).ok_or(RTreeError::Corrupt(
+                "child does not refer to a node in the tree",
+            ))?;
 
-        for (_, child_node) in self.child_iter(index) {
             // are all children of this node contained in the MBR of this node?
-            assert!(node.get_region().contains(&child_node.get_region()));
+            if !node.get_region().contains(&child_node.get_region()) {
+                return Err(RTreeError::Corrupt("child is not contained in its parent"));
+            }
 
             // does every child have its parent attribute set correctly?
-            assert_eq!(child_node.get_parent(), Some(index));
+            if child_node.get_parent() != Some(index) {
+                return Err(RTreeError::Corrupt("child has the wrong parent"));
+            }
         }
 
         // validate all children of this node
         for child_index in node.child_index_iter() {
-            self._validate_consistency(child_index, node_counter);
+            self._check_consistency(child_index, node_counter)?;
         }
+
+        Ok(())
     }
 
     /// Returns an iterator of pairs `(Index, &Node)` of children of the node corresponding to the
@@ -492,7 +643,9 @@ where
             .map(move |index| (index, self.get_node(index)))
     }
 
-    /// Returns a reference to the [`Node`] with index `index`.
+    /// Returns a reference to the [`Node`] with index `index`.  Together with
+    /// [`RTree::root_index`] and [`Node::child_index_iter`], this allows the internal structure
+    /// of the tree to be inspected.
     ///
     /// # Panics
     /// This function will panic if `index` does not refer to a node in this tree.
@@ -506,7 +659,7 @@ where
     /// # Panics
     /// This function will panic if `index` does not refer to a node in this tree.
     #[inline(always)]
-    pub fn get_node_mut(&mut self, index: Index) -> &mut Node<ND, T> {
+    pub(crate) fn get_node_mut(&mut self, index: Index) -> &mut Node<ND, T> {
         &mut self.nodes[index]
     }
 
@@ -567,7 +720,7 @@ where
         shape: S,
         pred: F,
         index: Index,
-    ) -> Vec<Index> {
+    ) -> Vec<ItemId> {
         let mut hits = Vec::new();
         let mut work_queue = vec![index];
 
@@ -576,7 +729,7 @@ where
 
             // If we're at a leaf node, then add it to our hits vector.
             if node.is_leaf() {
-                hits.push(index);
+                hits.push(ItemId(index));
                 continue 'work_loop;
             }
 
@@ -592,7 +745,7 @@ where
         hits
     }
 
-    /// Returns a `Vec<Index>` of those regions in the tree intersecting the given point `point`.
+    /// Returns a `Vec<ItemId>` of those regions in the tree intersecting the given point `point`.
     ///
     /// # Example
     /// ```rust
@@ -620,12 +773,16 @@ where
     /// assert_eq!(tree.point_lookup((2.5, 2.5)).len(), 1);
     /// ```
     #[inline(always)]
-    pub fn point_lookup<P: Into<Point<T>>>(&self, point: P) -> Vec<Index> {
-        self._point_lookup(point.into())
+    pub fn point_lookup<P: Into<Point<T>>>(&self, point: P) -> Vec<ItemId> {
+        let point = point.into();
+
+        self.periodic_lookup(Rect::new(point.0, point.0), true, |image| {
+            self._point_lookup(image.min().into())
+        })
     }
 
     #[inline(always)]
-    fn _point_lookup(&self, point: Point<T>) -> Vec<Index> {
+    fn _point_lookup(&self, point: Point<T>) -> Vec<ItemId> {
         self._lookup(
             point,
             |point, child_region| child_region.intersects(point),
@@ -633,7 +790,7 @@ where
         )
     }
 
-    /// Returns a `Vec<Index>` of those elements in the tree whose minimum bounding box
+    /// Returns a `Vec<ItemId>` of those elements in the tree whose minimum bounding box
     /// intersects the given region.
     ///
     /// # Example
@@ -660,12 +817,14 @@ where
     /// # tree.validate_consistency();
     /// ```
     #[inline(always)]
-    pub fn region_intersection_lookup(&self, region: Rect<T>) -> Vec<Index> {
-        self._region_intersection_lookup(region)
+    pub fn region_intersection_lookup(&self, region: Rect<T>) -> Vec<ItemId> {
+        self.periodic_lookup(region, false, |image| {
+            self._region_intersection_lookup(image)
+        })
     }
 
     #[inline(always)]
-    fn _region_intersection_lookup(&self, region: Rect<T>) -> Vec<Index> {
+    fn _region_intersection_lookup(&self, region: Rect<T>) -> Vec<ItemId> {
         self._lookup(
             Geometry::Rect(region),
             |region, child_region| child_region.intersects(region),
@@ -673,7 +832,7 @@ where
         )
     }
 
-    /// Returns a `Vec<Index>` of those elements in the tree whose minimum bounding box
+    /// Returns a `Vec<ItemId>` of those elements in the tree whose minimum bounding box
     /// contains the given region.
     ///
     /// # Example
@@ -698,12 +857,12 @@ where
     /// # tree.validate_consistency();
     /// ```
     #[inline(always)]
-    pub fn region_lookup(&self, region: Rect<T>) -> Vec<Index> {
-        self._region_lookup(region)
+    pub fn region_lookup(&self, region: Rect<T>) -> Vec<ItemId> {
+        self.periodic_lookup(region, true, |image| self._region_lookup(image))
     }
 
     #[inline(always)]
-    fn _region_lookup(&self, region: Rect<T>) -> Vec<Index> {
+    fn _region_lookup(&self, region: Rect<T>) -> Vec<ItemId> {
         self._lookup(
             Geometry::Rect(region),
             |region, child_region| {
@@ -719,10 +878,10 @@ where
         )
     }
 
-    /// Returns a `Vec<Index>` of those elements in the tree whose minimum bounding box
+    /// Returns a `Vec<ItemId>` of those elements in the tree whose minimum bounding box
     /// contains the given line.
     #[inline(always)]
-    pub fn line_lookup(&self, line: Line<T>) -> Vec<Index> {
+    pub fn line_lookup(&self, line: Line<T>) -> Vec<ItemId> {
         let minimum_bounding_region = line.bounding_rect();
         self.region_lookup(minimum_bounding_region)
     }
@@ -744,3 +903,170 @@ fn combine_rects<T: CoordNum>(r1: Rect<T>, r2: Rect<T>) -> Rect<T> {
         Coordinate { x: x_max, y: y_max },
     )
 }
+
+/// Returns `true` if a tree whose nodes have between `min_children` and `max_children`
+/// children can be split and condensed correctly.  Used to validate stored trees.
+pub(crate) fn valid_node_capacity(min_children: usize, max_children: usize) -> bool {
+    min_children >= 2 && max_children >= 2 * min_children
+}
+
+/// Chooses which of the child regions `regions` a new region `region` should be inserted
+/// beneath, returning the position of the chosen child along with its region enlarged to
+/// contain `region`.
+///
+/// The first child already containing `region` is preferred.  Otherwise the child whose area
+/// grows the least when enlarged to contain `region` is chosen.
+///
+/// # Panics
+/// This function will panic if `regions` is empty.
+pub(crate) fn choose_subtree<T: CoordFloat>(
+    regions: &[Rect<T>],
+    region: Rect<T>,
+) -> (usize, Rect<T>) {
+    // Does any child of this node have an MBR containing our input region?
+    if let Some(position) = regions
+        .iter()
+        .position(|child_region| child_region.contains(&region))
+    {
+        return (position, regions[position]);
+    }
+
+    // Otherwise there is no child MBR containing our input `region`.  Thus find
+    // the bounding box such that enlarging it to contain `region` will add the least
+    // amount of area.
+    regions
+        .iter()
+        .map(|&child_region| {
+            let initial_area = child_region.unsigned_area();
+            let combined_region = combine_rects(child_region, region);
+            (
+                combined_region.unsigned_area() - initial_area,
+                combined_region,
+            )
+        })
+        .enumerate()
+        .min_by(|(_, (left_change, _)), (_, (right_change, _))| {
+            // TODO: this should be fine, but worth investigating.
+            left_change.partial_cmp(right_change).unwrap()
+        })
+        .map(|(position, (_, combined_region))| (position, combined_region))
+        .expect("something weird happened")
+}
+
+/// Given a set of regions, finds the pair of regions whose combined bounding box is
+/// the worst.  To be concrete, we find the pair whose combined bounding box
+/// has the maximum difference to the sum of the areas of the bounding boxes
+/// for the original two regions.
+fn find_worst_pair<T: CoordFloat>(regions: &[Rect<T>]) -> (usize, usize) {
+    // This would be silly.
+    debug_assert!(regions.len() >= 2);
+
+    let mut worst_pair = None;
+    let mut worst_area = T::neg_infinity();
+
+    // find the two regions that would be the most terrible together
+    for (l1_index, r1) in regions.iter().enumerate() {
+        let a1 = r1.unsigned_area();
+
+        for (l2_index, r2) in regions.iter().enumerate().skip(l1_index + 1) {
+            let a2 = r2.unsigned_area();
+
+            // combine these two regions together
+            let combined_region = combine_rects(*r1, *r2);
+            let combined_area = combined_region.unsigned_area() - a1 - a2;
+
+            if combined_area > worst_area {
+                worst_pair = Some((l1_index, l2_index));
+                worst_area = combined_area;
+            }
+        }
+    }
+
+    worst_pair.unwrap()
+}
+
+/// Splits a set of regions into two groups using the QuadraticSplit algorithm, so that each
+/// group has at least `min_children` regions.  Returns the positions of the regions in the
+/// first group, along with the minimum bounding regions of the two groups.
+pub(crate) fn quadratic_partition<T: CoordFloat>(
+    regions: &[Rect<T>],
+    min_children: usize,
+    max_children: usize,
+) -> (HashSet<usize>, Rect<T>, Rect<T>) {
+    let (ix1, ix2) = find_worst_pair(regions);
+
+    let mut unpicked_children: HashSet<usize> = (0..regions.len()).collect();
+    unpicked_children.remove(&ix1);
+    unpicked_children.remove(&ix2);
+
+    // Keep track of nodes in the first group
+    let mut group1 = HashSet::with_capacity(max_children - min_children);
+    group1.insert(ix1);
+
+    // Keep track of the minimum bounding regions for the first and second group
+    let mut group1_mbr = regions[ix1];
+    let mut group2_mbr = regions[ix2];
+
+    // Partition the nodes into two groups.  The basic strategy is that at each stepp
+    // we find the unpicked node
+    // If one of the groups gets too large, stop.
+    while !unpicked_children.is_empty()
+        && group1.len() < max_children - min_children
+        && (regions.len() - group1.len() - unpicked_children.len()) < max_children - min_children
+    {
+        let mut best_d = T::infinity();
+        let mut best_index = None;
+
+        for &index in unpicked_children.iter() {
+            let g1r = combine_rects(group1_mbr, regions[index]);
+            let g2r = combine_rects(group2_mbr, regions[index]);
+
+            let d1 = g1r.unsigned_area() - group1_mbr.unsigned_area();
+            let d2 = g2r.unsigned_area() - group2_mbr.unsigned_area();
+
+            if d1 < d2 && d1 < best_d {
+                best_index = Some((index, 1));
+                best_d = d1;
+            } else if d2 < d1 && d2 < best_d {
+                best_index = Some((index, 2));
+                best_d = d2;
+            } else if (d1 - d2).abs() < T::epsilon() && d1 < best_d {
+                // in case of ties, assign to MBR with smallest area
+                if group1_mbr.unsigned_area() < group2_mbr.unsigned_area() {
+                    best_index = Some((index, 1));
+                } else {
+                    best_index = Some((index, 2));
+                }
+                best_d = d1;
+            }
+        }
+
+        let (best_index, side) = best_index.unwrap();
+        unpicked_children.remove(&best_index);
+
+        if side == 1 {
+            // add to group 1
+            group1.insert(best_index);
+            group1_mbr = combine_rects(group1_mbr, regions[best_index]);
+        } else {
+            group2_mbr = combine_rects(group2_mbr, regions[best_index]);
+        }
+    }
+
+    if !unpicked_children.is_empty() {
+        if group1.len() < min_children {
+            // rest of the unpicked children go in group 1
+            for child_index in unpicked_children {
+                group1_mbr = combine_rects(group1_mbr, regions[child_index]);
+                group1.insert(child_index);
+            }
+        } else {
+            // rest of the unpicked children go in group 2
+            for child_index in unpicked_children {
+                group2_mbr = combine_rects(group2_mbr, regions[child_index]);
+            }
+        }
+    }
+
+    (group1, group1_mbr, group2_mbr)
+}
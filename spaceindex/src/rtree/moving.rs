@@ -0,0 +1,927 @@
+use std::cmp::Ordering;
+
+use generational_arena::{Arena, Index};
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Coordinate, Rect};
+
+use crate::rtree::{combine_rects, ItemId, RTreeError};
+
+/// The minimum number of children of every node other than the root, as for [`RTree::new`].
+///
+/// [`RTree::new`]: crate::RTree::new
+const MIN_CHILDREN: usize = 2;
+
+/// Nodes are split once they reach this many children, as for [`RTree::new`].
+///
+/// [`RTree::new`]: crate::RTree::new
+const MAX_CHILDREN: usize = 8;
+
+/// An object moving with constant velocity, as stored in a [`MovingRTree`].
+#[derive(Clone, Debug)]
+pub struct MovingObject<ND, T>
+where
+    T: CoordFloat,
+{
+    /// The region occupied by the object at `time`.
+    region: Rect<T>,
+
+    /// The velocity of the object.
+    velocity: Coordinate<T>,
+
+    /// The time at which the object occupied `region`.
+    time: T,
+
+    /// Some data owned by this object.
+    data: ND,
+}
+
+impl<ND, T> MovingObject<ND, T>
+where
+    T: CoordFloat,
+{
+    /// Returns the region occupied by this object at time `time`.
+    #[inline(always)]
+    pub fn region_at(&self, time: T) -> Rect<T> {
+        let offset = self.velocity * (time - self.time);
+
+        Rect::new(self.region.min() + offset, self.region.max() + offset)
+    }
+
+    /// Returns the velocity of this object.
+    #[inline(always)]
+    pub fn velocity(&self) -> Coordinate<T> {
+        self.velocity
+    }
+
+    /// Returns a reference to the data owned by this object.
+    #[inline(always)]
+    pub fn get_data(&self) -> &ND {
+        &self.data
+    }
+
+    /// Returns the bounds of this object, relative to time `reference_time`.
+    fn bounds_at(&self, reference_time: T) -> MovingBounds<T> {
+        MovingBounds {
+            region: self.region_at(reference_time),
+            velocity: Rect::new(self.velocity, self.velocity),
+        }
+    }
+
+    /// Returns `true` if this object intersects `region` at some time in `[start, end]`.
+    fn intersects_during(&self, region: Rect<T>, start: T, end: T) -> bool {
+        self.bounds_at(self.time)
+            .intersects_during(region, start - self.time, end - self.time)
+    }
+}
+
+/// A time-parameterised bounding rectangle, as used by a TPR-tree.  At `s` time units past the
+/// reference time, the rectangle spans from `region.min() + velocity.min() * s` to
+/// `region.max() + velocity.max() * s`, so it grows to cover everything beneath it for every
+/// `s >= 0`.
+#[derive(Clone, Copy, Debug)]
+struct MovingBounds<T>
+where
+    T: CoordFloat,
+{
+    /// The rectangle at the reference time.
+    region: Rect<T>,
+
+    /// The velocities of the lower edges (`velocity.min()`) and upper edges (`velocity.max()`)
+    /// of the rectangle.
+    velocity: Rect<T>,
+}
+
+impl<T> MovingBounds<T>
+where
+    T: CoordFloat,
+{
+    /// Returns empty bounds, for a node without any children.
+    fn empty() -> Self {
+        let zero = Rect::new(Coordinate::zero(), Coordinate::zero());
+
+        Self {
+            region: zero,
+            velocity: zero,
+        }
+    }
+
+    /// Returns the smallest bounds containing both `self` and `other` for all `s >= 0`.
+    fn combine(self, other: Self) -> Self {
+        Self {
+            region: combine_rects(self.region, other.region),
+            velocity: combine_rects(self.velocity, other.velocity),
+        }
+    }
+
+    /// Returns `true` if `self` contains `other` for all `s >= 0`.
+    fn contains(&self, other: &Self) -> bool {
+        covers(&self.region, &other.region) && covers(&self.velocity, &other.velocity)
+    }
+
+    /// Returns the area of the rectangle integrated over `s` in `[start, end]`.
+    fn integrated_area(&self, start: T, end: T) -> T {
+        let (width, height) = (self.region.width(), self.region.height());
+        let (width_growth, height_growth) = (self.velocity.width(), self.velocity.height());
+
+        let two = T::one() + T::one();
+        let three = two + T::one();
+
+        width * height * (end - start)
+            + (width * height_growth + height * width_growth) * (end * end - start * start) / two
+            + width_growth * height_growth * (end * end * end - start * start * start) / three
+    }
+
+    /// Returns `true` if the rectangle intersects `region` for some `s` in `[start, end]`.
+    fn intersects_during(&self, region: Rect<T>, start: T, end: T) -> bool {
+        let (min, max) = (self.region.min(), self.region.max());
+        let (velocity_min, velocity_max) = (self.velocity.min(), self.velocity.max());
+        let (query_min, query_max) = (region.min(), region.max());
+
+        // Along each axis, we need both `min + velocity_min * s <= query_max` and
+        // `max + velocity_max * s >= query_min`.  Each holds on a half-line of times.
+        let axes: [fn(Coordinate<T>) -> T; 2] = [|c| c.x, |c| c.y];
+        let mut interval = (start, end);
+        for axis in axes {
+            interval = constrain(interval, axis(min), axis(velocity_min), axis(query_max));
+            interval = constrain(interval, -axis(max), -axis(velocity_max), -axis(query_min));
+        }
+
+        interval.0 <= interval.1
+    }
+}
+
+/// A node of a [`MovingRTree`].
+#[derive(Clone, Debug)]
+struct MovingNode<ND, T>
+where
+    T: CoordFloat,
+{
+    /// Bounds containing everything beneath this node, relative to the reference time of the
+    /// tree.
+    bounds: MovingBounds<T>,
+
+    /// The index of the parent of this node, if it has one.
+    parent: Option<Index>,
+
+    contents: Contents<ND, T>,
+}
+
+/// What a [`MovingNode`] holds.
+#[derive(Clone, Debug)]
+enum Contents<ND, T>
+where
+    T: CoordFloat,
+{
+    /// The indexes of the children of an internal node.
+    Internal(Vec<Index>),
+
+    /// The object stored in a leaf.
+    Leaf(MovingObject<ND, T>),
+}
+
+impl<ND, T> MovingNode<ND, T>
+where
+    T: CoordFloat,
+{
+    fn new_internal_node(bounds: MovingBounds<T>, parent: Option<Index>) -> Self {
+        Self {
+            bounds,
+            parent,
+            contents: Contents::Internal(Vec::new()),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self.contents, Contents::Leaf(_))
+    }
+
+    /// Returns the children of this node, which are empty for a leaf.
+    fn children(&self) -> &[Index] {
+        match &self.contents {
+            Contents::Internal(children) => children,
+            Contents::Leaf(_) => &[],
+        }
+    }
+
+    /// Returns the children of this node.
+    ///
+    /// # Panics
+    /// This function will panic if this node is a leaf.
+    fn children_mut(&mut self) -> &mut Vec<Index> {
+        match &mut self.contents {
+            Contents::Internal(children) => children,
+            Contents::Leaf(_) => panic!("leaves have no children"),
+        }
+    }
+
+    fn object(&self) -> Option<&MovingObject<ND, T>> {
+        match &self.contents {
+            Contents::Leaf(object) => Some(object),
+            Contents::Internal(_) => None,
+        }
+    }
+}
+
+/// An index of objects moving with constant velocity, in the form of a TPR-tree.
+///
+/// Each leaf stores an object's region at some time together with its velocity, and each
+/// internal node stores a time-parameterised bounding rectangle: a rectangle at the reference
+/// time of the tree, together with the least and greatest velocities of the objects beneath
+/// it along each axis.  The bounds of a node at a later time are found by moving its lower
+/// edges with the least velocities and its upper edges with the greatest, so they always
+/// contain everything beneath the node.  Queries at any time from the reference time onwards
+/// are answered by evaluating these bounds at the time of the query, without any per-tick
+/// updates of the tree.
+///
+/// Node bounds grow as time passes, so queries far past the reference time prune the tree less
+/// well.  To counter this, inserts choose subtrees and split nodes to minimize the area of the
+/// bounds integrated over the `horizon` following the time of the insert, as for a TPR-tree,
+/// and [rebasing](MovingRTree::rebase) the tree recomputes tight bounds at a later reference
+/// time.
+#[derive(Clone, Debug)]
+pub struct MovingRTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Nodes are stored in a generational arena, as for an [`RTree`](crate::RTree).
+    nodes: Arena<MovingNode<ND, T>>,
+
+    /// The index of the root node of this tree.
+    root: Index,
+
+    /// The time from which node bounds are measured, and so the earliest time that can be
+    /// queried.
+    reference_time: T,
+
+    /// How far past the time of an insert its effect on node bounds is optimized for.
+    horizon: T,
+
+    /// The number of objects (i.e. leaves) in this tree.
+    len: usize,
+}
+
+impl<ND, T> MovingRTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Creates a new [`MovingRTree`] supporting queries at any time from `reference_time`
+    /// onwards.  The tree is organised to answer queries well over the `horizon` following
+    /// each insert.
+    ///
+    /// # Panics
+    /// This function will panic if `horizon` is negative.
+    pub fn new(reference_time: T, horizon: T) -> Self {
+        assert!(horizon >= T::zero());
+
+        let mut nodes = Arena::new();
+        let root = nodes.insert(MovingNode::new_internal_node(MovingBounds::empty(), None));
+
+        Self {
+            nodes,
+            root,
+            reference_time,
+            horizon,
+            len: 0,
+        }
+    }
+
+    /// Returns the earliest time that can be queried.
+    #[inline(always)]
+    pub fn reference_time(&self) -> T {
+        self.reference_time
+    }
+
+    /// Returns how far past the time of each insert the tree is organised to answer queries.
+    #[inline(always)]
+    pub fn horizon(&self) -> T {
+        self.horizon
+    }
+
+    /// Returns the number of objects in this tree.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if this tree contains no objects.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts an object which occupies `region` at time `time`, and moves with constant
+    /// velocity `velocity`, returning the [`ItemId`] of the new object.
+    ///
+    /// # Errors
+    /// This function will return an error if any coordinate of `region` or `velocity`, or
+    /// `time`, isn't finite.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::Rect;
+    /// use spaceindex::rtree::moving::MovingRTree;
+    ///
+    /// let mut tree = MovingRTree::new(0.0, 10.0);
+    ///
+    /// // A unit square at the origin moving right at one unit per second.
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), (1.0, 0.0), 0.0, 'a').unwrap();
+    ///
+    /// // The square hasn't reached (5.5, 0.5) at time 2, but has at time 5.
+    /// let query = Rect::new((5.5, 0.5), (5.5, 0.5));
+    /// assert!(tree.intersecting_at(query, 2.0).unwrap().is_empty());
+    /// assert_eq!(tree.intersecting_at(query, 5.0).unwrap().len(), 1);
+    ///
+    /// // It passes over (5.5, 0.5) at some point between times 3 and 6.
+    /// assert_eq!(tree.intersecting_during(query, 3.0, 6.0).unwrap().len(), 1);
+    ///
+    /// // Queries can be made at any later time, without updating the tree.
+    /// let query = Rect::new((1000.5, 0.5), (1000.5, 0.5));
+    /// assert_eq!(tree.intersecting_at(query, 1000.0).unwrap().len(), 1);
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn insert<V: Into<Coordinate<T>>>(
+        &mut self,
+        region: Rect<T>,
+        velocity: V,
+        time: T,
+        data: ND,
+    ) -> Result<ItemId, RTreeError> {
+        let velocity = velocity.into();
+        check_motion(region, velocity, time)?;
+
+        let object = MovingObject {
+            region,
+            velocity,
+            time,
+            data,
+        };
+        let leaf = self.nodes.insert(MovingNode {
+            bounds: object.bounds_at(self.reference_time),
+            parent: None,
+            contents: Contents::Leaf(object),
+        });
+
+        self.insert_leaf(leaf);
+        self.len += 1;
+
+        Ok(ItemId(leaf))
+    }
+
+    /// Removes the object `id` from the tree, returning it.
+    /// Returns `None` if `id` isn't in this tree.
+    pub fn remove(&mut self, id: ItemId) -> Option<MovingObject<ND, T>> {
+        if !self.nodes.get(id.0).is_some_and(|node| node.is_leaf()) {
+            return None;
+        }
+
+        self.detach(id.0);
+        self.len -= 1;
+
+        match self.nodes.remove(id.0).unwrap().contents {
+            Contents::Leaf(object) => Some(object),
+            Contents::Internal(_) => unreachable!(),
+        }
+    }
+
+    /// Changes the motion of the object `id`, so that it occupies `region` at time `time` and
+    /// moves with constant velocity `velocity`.  The object keeps its [`ItemId`] and its data.
+    ///
+    /// # Errors
+    /// This function will return an error if `id` isn't in this tree, or if any coordinate of
+    /// `region` or `velocity`, or `time`, isn't finite.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::Rect;
+    /// use spaceindex::rtree::moving::MovingRTree;
+    ///
+    /// let mut tree = MovingRTree::new(0.0, 10.0);
+    /// let id = tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), (1.0, 0.0), 0.0, 'a').unwrap();
+    ///
+    /// // At time 2 the square turns to move up instead.
+    /// tree.update(id, Rect::new((2.0, 0.0), (3.0, 1.0)), (0.0, 1.0), 2.0).unwrap();
+    ///
+    /// let query = Rect::new((2.5, 3.5), (2.5, 3.5));
+    /// assert_eq!(tree.intersecting_at(query, 5.0).unwrap(), vec![id]);
+    /// assert!(tree.intersecting_at(Rect::new((5.5, 0.5), (5.5, 0.5)), 5.0).unwrap().is_empty());
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn update<V: Into<Coordinate<T>>>(
+        &mut self,
+        id: ItemId,
+        region: Rect<T>,
+        velocity: V,
+        time: T,
+    ) -> Result<(), RTreeError> {
+        if !self.nodes.get(id.0).is_some_and(|node| node.is_leaf()) {
+            return Err(RTreeError::ItemNotFound);
+        }
+
+        let velocity = velocity.into();
+        check_motion(region, velocity, time)?;
+
+        // Detach the leaf from the tree, condensing the tree around where it used to be, then
+        // reinsert it with its new motion.
+        self.detach(id.0);
+
+        let reference_time = self.reference_time;
+        let node = &mut self.nodes[id.0];
+        if let Contents::Leaf(object) = &mut node.contents {
+            object.region = region;
+            object.velocity = velocity;
+            object.time = time;
+            node.bounds = object.bounds_at(reference_time);
+        }
+
+        self.insert_leaf(id.0);
+
+        Ok(())
+    }
+
+    /// Returns a `Vec<ItemId>` of those objects intersecting `region` at time `time`.
+    ///
+    /// # Errors
+    /// This function will return an error if `time` is before the reference time of the tree.
+    pub fn intersecting_at(&self, region: Rect<T>, time: T) -> Result<Vec<ItemId>, RTreeError> {
+        self.intersecting_during(region, time, time)
+    }
+
+    /// Returns a `Vec<ItemId>` of those objects intersecting `region` at some time in
+    /// `[start, end]`.
+    ///
+    /// # Errors
+    /// This function will return an error if `start` is before the reference time of the tree,
+    /// or after `end`.
+    pub fn intersecting_during(
+        &self,
+        region: Rect<T>,
+        start: T,
+        end: T,
+    ) -> Result<Vec<ItemId>, RTreeError> {
+        if start > end || start < self.reference_time {
+            return Err(RTreeError::TimeBeforeReference);
+        }
+
+        let mut hits = Vec::new();
+
+        if self.is_empty() {
+            return Ok(hits);
+        }
+
+        // Node bounds only contain everything beneath them from the reference time onwards, so
+        // measure times from there.
+        let (node_start, node_end) = (start - self.reference_time, end - self.reference_time);
+        let mut work_queue = vec![self.root];
+
+        while let Some(index) = work_queue.pop() {
+            let node = &self.nodes[index];
+
+            match &node.contents {
+                Contents::Internal(children) => {
+                    if node.bounds.intersects_during(region, node_start, node_end) {
+                        work_queue.extend(children.iter().copied());
+                    }
+                }
+                Contents::Leaf(object) => {
+                    // Leaves are checked exactly, relative to the time of their own object.
+                    if object.intersects_during(region, start, end) {
+                        hits.push(ItemId(index));
+                    }
+                }
+            }
+        }
+
+        Ok(hits)
+    }
+
+    /// Returns a reference to the object `id`, if it is in this tree.
+    #[inline(always)]
+    pub fn get(&self, id: ItemId) -> Option<&MovingObject<ND, T>> {
+        self.nodes.get(id.0)?.object()
+    }
+
+    /// Moves the reference time of this tree to `reference_time`, recomputing the bounds of
+    /// every node from there.  Rebasing to a later time gives tighter bounds, and so faster
+    /// queries, for times from `reference_time` onwards, but queries before `reference_time`
+    /// are no longer possible.
+    ///
+    /// The structure of the tree is unchanged, so every [`ItemId`] stays valid.
+    pub fn rebase(&mut self, reference_time: T) {
+        self.reference_time = reference_time;
+
+        if !self.is_empty() {
+            self.refit(self.root);
+        }
+    }
+
+    /// Recomputes the bounds of the node `index` and everything beneath it, relative to the
+    /// reference time of the tree, returning the new bounds of the node.
+    fn refit(&mut self, index: Index) -> MovingBounds<T> {
+        let bounds = match &self.nodes[index].contents {
+            Contents::Leaf(object) => object.bounds_at(self.reference_time),
+            Contents::Internal(children) => children
+                .clone()
+                .into_iter()
+                .map(|child| self.refit(child))
+                .reduce(MovingBounds::combine)
+                .unwrap_or_else(MovingBounds::empty),
+        };
+
+        self.nodes[index].bounds = bounds;
+
+        bounds
+    }
+
+    /// Returns the interval over which inserting an object which was at its given region at
+    /// time `time` is optimized, relative to the reference time of the tree.
+    fn insert_interval(&self, time: T) -> (T, T) {
+        let start = (time - self.reference_time).max(T::zero());
+
+        (start, start + self.horizon)
+    }
+
+    /// Attaches the detached leaf `leaf` to the tree.  This is also used to reinsert orphaned
+    /// and updated leaves, so that the `Index` of a leaf never changes while it is in the tree.
+    fn insert_leaf(&mut self, leaf: Index) {
+        let bounds = self.nodes[leaf].bounds;
+        let time = self.nodes[leaf].object().unwrap().time;
+        let (start, end) = self.insert_interval(time);
+
+        // Descend to a node whose children are leaves, enlarging bounds along the way.
+        let mut index = self.root;
+        loop {
+            let node = &mut self.nodes[index];
+            node.bounds = if node.children().is_empty() {
+                bounds
+            } else {
+                node.bounds.combine(bounds)
+            };
+
+            let node = &self.nodes[index];
+            if node
+                .children()
+                .first()
+                .is_none_or(|&child| self.nodes[child].is_leaf())
+            {
+                break;
+            }
+
+            let children = node
+                .children()
+                .iter()
+                .map(|&child| self.nodes[child].bounds)
+                .collect::<Vec<_>>();
+            index = node.children()[choose_subtree(&children, bounds, start, end)];
+        }
+
+        self.nodes[leaf].parent = Some(index);
+        self.nodes[index].children_mut().push(leaf);
+
+        if self.nodes[index].children().len() >= MAX_CHILDREN {
+            self.split_node(index, start, end);
+        }
+    }
+
+    /// Splits the overfull node `index`, optimizing the new nodes over `[start, end]`.
+    fn split_node(&mut self, index: Index, start: T, end: T) {
+        let children = std::mem::take(self.nodes[index].children_mut());
+        let bounds = children
+            .iter()
+            .map(|&child| self.nodes[child].bounds)
+            .collect::<Vec<_>>();
+
+        let group1 = quadratic_partition(&bounds, start, end);
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        for (position, child) in children.into_iter().enumerate() {
+            if group1.contains(&position) {
+                left.push(child);
+            } else {
+                right.push(child);
+            }
+        }
+
+        if index == self.root {
+            // Splitting the root: the root keeps the two halves as its children.
+            let left = self.new_internal_node(left, Some(index));
+            let right = self.new_internal_node(right, Some(index));
+            *self.nodes[index].children_mut() = vec![left, right];
+        } else {
+            // The current node becomes the left half, and the right half is added to the
+            // parent.
+            let parent = self.nodes[index].parent.unwrap();
+
+            self.nodes[index].bounds = self.bounds_of(&left);
+            *self.nodes[index].children_mut() = left;
+
+            let right = self.new_internal_node(right, Some(parent));
+            self.nodes[parent].children_mut().push(right);
+
+            if self.nodes[parent].children().len() >= MAX_CHILDREN {
+                self.split_node(parent, start, end);
+            }
+        }
+    }
+
+    /// Adds an internal node with children `children` and parent `parent` to the tree,
+    /// returning its index.
+    fn new_internal_node(&mut self, children: Vec<Index>, parent: Option<Index>) -> Index {
+        let bounds = self.bounds_of(&children);
+        let index = self
+            .nodes
+            .insert(MovingNode::new_internal_node(bounds, parent));
+
+        for &child in &children {
+            self.nodes[child].parent = Some(index);
+        }
+        *self.nodes[index].children_mut() = children;
+
+        index
+    }
+
+    /// Returns the smallest bounds containing each of `children`.
+    fn bounds_of(&self, children: &[Index]) -> MovingBounds<T> {
+        children
+            .iter()
+            .map(|&child| self.nodes[child].bounds)
+            .reduce(MovingBounds::combine)
+            .unwrap_or_else(MovingBounds::empty)
+    }
+
+    /// Detaches the leaf `leaf` from the tree, condensing the tree around where it used to be.
+    ///
+    /// Working up from the parent of the leaf, any non-root node left with fewer than
+    /// `MIN_CHILDREN` children is dissolved and its leaves are reinserted, while every other
+    /// node has its bounds shrunk to fit its remaining children.
+    fn detach(&mut self, leaf: Index) {
+        let mut orphans = Vec::new();
+        let mut current = self.nodes[leaf].parent.take();
+
+        if let Some(parent) = current {
+            self.nodes[parent]
+                .children_mut()
+                .retain(|&child| child != leaf);
+        }
+
+        while let Some(index) = current {
+            let parent = self.nodes[index].parent;
+
+            match parent {
+                Some(parent) if self.nodes[index].children().len() < MIN_CHILDREN => {
+                    self.nodes[parent]
+                        .children_mut()
+                        .retain(|&child| child != index);
+                    self.dissolve(index, &mut orphans);
+                }
+                _ => {
+                    let bounds = self.bounds_of(self.nodes[index].children());
+                    self.nodes[index].bounds = bounds;
+                }
+            }
+
+            current = parent;
+        }
+
+        // If the root has a single internal child, we can remove a level from the tree.
+        while let [child] = *self.nodes[self.root].children() {
+            if self.nodes[child].is_leaf() {
+                break;
+            }
+
+            self.nodes.remove(self.root);
+            self.nodes[child].parent = None;
+            self.root = child;
+        }
+
+        for orphan in orphans {
+            self.insert_leaf(orphan);
+        }
+    }
+
+    /// Removes the internal node `index` and every internal node beneath it from the tree,
+    /// detaching all of its leaves and adding them to `orphans`.
+    fn dissolve(&mut self, index: Index, orphans: &mut Vec<Index>) {
+        let node = self.nodes.remove(index).unwrap();
+
+        for &child in node.children() {
+            if self.nodes[child].is_leaf() {
+                self.nodes[child].parent = None;
+                orphans.push(child);
+            } else {
+                self.dissolve(child, orphans);
+            }
+        }
+    }
+
+    /// Validates the consistency of the tree.  In particular, this function checks that:
+    /// - The bounds of every node contain the bounds of each of its children,
+    /// - The children of every node have that node as their parent,
+    /// - Every node other than the root has between `MIN_CHILDREN` and `MAX_CHILDREN - 1`
+    ///   children, and every leaf is at the same depth, and
+    /// - Every node in the tree is reachable from the root.
+    ///
+    /// # Panics
+    /// This function will panic if the tree is inconsistent.
+    pub fn validate_consistency(&self) {
+        let mut reachable = 1;
+        let mut leaves = 0;
+        let mut leaf_depth = None;
+        let mut work_queue = vec![(self.root, 0)];
+
+        assert!(self.nodes[self.root].parent.is_none(), "root has a parent");
+
+        while let Some((index, depth)) = work_queue.pop() {
+            let node = &self.nodes[index];
+
+            if node.is_leaf() {
+                leaves += 1;
+                assert_eq!(
+                    *leaf_depth.get_or_insert(depth),
+                    depth,
+                    "leaves are at different depths"
+                );
+                continue;
+            }
+
+            if index != self.root {
+                assert!(
+                    (MIN_CHILDREN..MAX_CHILDREN).contains(&node.children().len()),
+                    "node has {} children",
+                    node.children().len()
+                );
+            }
+
+            for &child in node.children() {
+                let child_node = self.nodes.get(child).expect("child isn't in the tree");
+
+                assert_eq!(child_node.parent, Some(index), "child has the wrong parent");
+                assert!(
+                    node.bounds.contains(&child_node.bounds),
+                    "child bounds exceed their parent"
+                );
+
+                reachable += 1;
+                work_queue.push((child, depth + 1));
+            }
+        }
+
+        assert_eq!(leaves, self.len, "tree length doesn't match its leaves");
+        assert_eq!(
+            reachable,
+            self.nodes.len(),
+            "tree contains unreachable nodes"
+        );
+    }
+}
+
+/// Checks that the motion of an object is finite.
+fn check_motion<T: CoordFloat>(
+    region: Rect<T>,
+    velocity: Coordinate<T>,
+    time: T,
+) -> Result<(), RTreeError> {
+    let finite = [
+        region.min().x,
+        region.min().y,
+        region.max().x,
+        region.max().y,
+        velocity.x,
+        velocity.y,
+        time,
+    ]
+    .iter()
+    .all(|value| value.is_finite());
+
+    if finite {
+        Ok(())
+    } else {
+        Err(RTreeError::FailedToInsert)
+    }
+}
+
+/// Returns `true` if every point of `inner`, including its boundary, lies in `outer`.
+fn covers<T: CoordFloat>(outer: &Rect<T>, inner: &Rect<T>) -> bool {
+    outer.min().x <= inner.min().x
+        && outer.min().y <= inner.min().y
+        && outer.max().x >= inner.max().x
+        && outer.max().y >= inner.max().y
+}
+
+/// Narrows the interval of times `(lo, hi)` to those `s` for which `a + b * s <= c`.  The
+/// returned interval is empty (`lo > hi`) if there are no such times.
+fn constrain<T: CoordFloat>((lo, hi): (T, T), a: T, b: T, c: T) -> (T, T) {
+    match b.partial_cmp(&T::zero()) {
+        Some(Ordering::Greater) => (lo, hi.min((c - a) / b)),
+        Some(Ordering::Less) => (lo.max((c - a) / b), hi),
+        _ if a <= c => (lo, hi),
+        _ => (T::infinity(), T::neg_infinity()),
+    }
+}
+
+/// Compares two costs, treating incomparable costs as equal.
+fn compare<T: CoordFloat>(left: T, right: T) -> Ordering {
+    left.partial_cmp(&right).unwrap_or(Ordering::Equal)
+}
+
+/// Chooses which of the child bounds `children` new bounds `bounds` should be inserted beneath,
+/// returning the position of the chosen child.  As for a TPR-tree, the child whose area
+/// integrated over `[start, end]` grows the least is chosen, with ties going to the child with
+/// the least integrated area.
+///
+/// # Panics
+/// This function will panic if `children` is empty.
+fn choose_subtree<T: CoordFloat>(
+    children: &[MovingBounds<T>],
+    bounds: MovingBounds<T>,
+    start: T,
+    end: T,
+) -> usize {
+    children
+        .iter()
+        .map(|child| {
+            let area = child.integrated_area(start, end);
+            let growth = child.combine(bounds).integrated_area(start, end) - area;
+
+            (growth, area)
+        })
+        .enumerate()
+        .min_by(
+            |(_, (left_growth, left_area)), (_, (right_growth, right_area))| {
+                compare(*left_growth, *right_growth).then(compare(*left_area, *right_area))
+            },
+        )
+        .map(|(position, _)| position)
+        .expect("nodes being descended into always have children")
+}
+
+/// Splits a set of bounds into two groups using the QuadraticSplit algorithm, measuring area
+/// integrated over `[start, end]`, so that each group has at least `MIN_CHILDREN` bounds.
+/// Returns the positions of the bounds in the first group.
+fn quadratic_partition<T: CoordFloat>(bounds: &[MovingBounds<T>], start: T, end: T) -> Vec<usize> {
+    let area = |bounds: &MovingBounds<T>| bounds.integrated_area(start, end);
+
+    // Seed the groups with the pair of bounds which would waste the most area together.
+    let mut seeds = (0, 1);
+    let mut worst_waste = T::neg_infinity();
+    for (position1, bounds1) in bounds.iter().enumerate() {
+        for (position2, bounds2) in bounds.iter().enumerate().skip(position1 + 1) {
+            let waste = area(&bounds1.combine(*bounds2)) - area(bounds1) - area(bounds2);
+
+            if compare(waste, worst_waste) == Ordering::Greater {
+                seeds = (position1, position2);
+                worst_waste = waste;
+            }
+        }
+    }
+
+    let mut group1 = vec![seeds.0];
+    let mut group2 = vec![seeds.1];
+    let (mut group1_bounds, mut group2_bounds) = (bounds[seeds.0], bounds[seeds.1]);
+    let mut unpicked = (0..bounds.len())
+        .filter(|&position| position != seeds.0 && position != seeds.1)
+        .collect::<Vec<_>>();
+
+    while !unpicked.is_empty() {
+        // If one group needs everything that's left to reach the minimum size, it gets it.
+        if group1.len() + unpicked.len() <= MIN_CHILDREN {
+            group1.append(&mut unpicked);
+            break;
+        }
+        if group2.len() + unpicked.len() <= MIN_CHILDREN {
+            break;
+        }
+
+        // Otherwise assign the bounds with the strongest preference for one group.
+        let (choice, (growth1, growth2)) = unpicked
+            .iter()
+            .map(|&position| {
+                let growth1 = area(&group1_bounds.combine(bounds[position])) - area(&group1_bounds);
+                let growth2 = area(&group2_bounds.combine(bounds[position])) - area(&group2_bounds);
+
+                (growth1, growth2)
+            })
+            .enumerate()
+            .max_by(|(_, (left1, left2)), (_, (right1, right2))| {
+                compare((*left1 - *left2).abs(), (*right1 - *right2).abs())
+            })
+            .unwrap();
+        let position = unpicked.swap_remove(choice);
+
+        let prefers_group1 = match compare(growth1, growth2) {
+            Ordering::Less => true,
+            Ordering::Greater => false,
+            Ordering::Equal => {
+                compare(area(&group1_bounds), area(&group2_bounds)) != Ordering::Greater
+            }
+        };
+
+        if prefers_group1 {
+            group1.push(position);
+            group1_bounds = group1_bounds.combine(bounds[position]);
+        } else {
+            group2.push(position);
+            group2_bounds = group2_bounds.combine(bounds[position]);
+        }
+    }
+
+    group1
+}
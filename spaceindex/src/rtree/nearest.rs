@@ -0,0 +1,186 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Point, Rect};
+
+use crate::rtree::metric::Metric;
+use crate::rtree::{ItemId, RTree};
+
+/// A node waiting to be visited by a best-first search, together with a lower bound on the
+/// distance to everything beneath it.
+pub(crate) struct Candidate<T, I> {
+    pub(crate) distance: T,
+    pub(crate) index: I,
+}
+
+impl<T: CoordFloat, I> PartialEq for Candidate<T, I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: CoordFloat, I> Eq for Candidate<T, I> {}
+
+impl<T: CoordFloat, I> PartialOrd for Candidate<T, I> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: CoordFloat, I> Ord for Candidate<T, I> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap, so reverse the comparison to pop the closest node first.
+        other
+            .distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl<ND, T> RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Returns up to `k` pairs `(ItemId, distance)` of the elements in the tree closest to
+    /// `point`, ordered by increasing distance.  The distance to an element is the distance
+    /// from `point` to the closest point of its region as measured by `metric`, so any region
+    /// containing `point` has distance zero.  For trees with a periodic domain, this is the
+    /// distance to the closest periodic image of each region.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    /// use spaceindex::rtree::metric::{Euclidean, Manhattan};
+    ///
+    /// let mut tree = RTree::new();
+    ///
+    /// // insert a couple of regions
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a');
+    /// tree.insert(Rect::new((4.0, 3.0), (5.0, 5.0)), 'b');
+    ///
+    /// // The first region is closest to the point (2.0, 2.0)
+    /// let nearest = tree.nearest_neighbors((2.0, 2.0), 1, Euclidean);
+    /// assert_eq!(nearest.len(), 1);
+    /// assert_eq!(tree.get(nearest[0].0).unwrap().1, &'a');
+    /// assert_eq!(nearest[0].1, 2.0f64.sqrt());
+    ///
+    /// // Both regions are returned in order of their Manhattan distance from (3.5, 2.0)
+    /// let nearest = tree.nearest_neighbors((3.5, 2.0), 5, Manhattan);
+    /// assert_eq!(nearest.iter().map(|(_, d)| *d).collect::<Vec<_>>(), vec![1.5, 3.5]);
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn nearest_neighbors<P: Into<Point<T>>, M: Metric<T>>(
+        &self,
+        point: P,
+        k: usize,
+        metric: M,
+    ) -> Vec<(ItemId, T)> {
+        let point = point.into();
+
+        self._nearest(
+            |region| {
+                self.periodic_distance(point, region, |point, region| {
+                    metric.distance_to_rect(point, region)
+                })
+            },
+            Some(k),
+            None,
+        )
+    }
+
+    /// Returns all pairs `(ItemId, distance)` of elements in the tree within `distance` of
+    /// `point` as measured by `metric`, ordered by increasing distance.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    /// use spaceindex::rtree::metric::Chebyshev;
+    ///
+    /// let mut tree = RTree::new();
+    ///
+    /// // insert a couple of regions
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), ());
+    /// tree.insert(Rect::new((3.0, 3.0), (4.0, 4.0)), ());
+    ///
+    /// // Only the first region is within a distance of 1.5 of (1.0, 2.0)
+    /// assert_eq!(tree.within_distance((1.0, 2.0), 1.5, Chebyshev).len(), 1);
+    ///
+    /// // Both regions are within a distance of 1.5 of (2.0, 2.0)
+    /// assert_eq!(tree.within_distance((2.0, 2.0), 1.5, Chebyshev).len(), 2);
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn within_distance<P: Into<Point<T>>, M: Metric<T>>(
+        &self,
+        point: P,
+        distance: T,
+        metric: M,
+    ) -> Vec<(ItemId, T)> {
+        let point = point.into();
+
+        self._nearest(
+            |region| {
+                self.periodic_distance(point, region, |point, region| {
+                    metric.distance_to_rect(point, region)
+                })
+            },
+            None,
+            Some(distance),
+        )
+    }
+
+    /// Performs a best-first search of the tree, returning leaves in order of increasing
+    /// distance.  `distance` should compute a lower bound on the distance to anything contained
+    /// in the given region, which is exact when the region is that of a leaf.  The search stops
+    /// once `k` leaves have been found or the next candidate is further than `max_distance`.
+    pub(crate) fn _nearest<F: Fn(Rect<T>) -> T>(
+        &self,
+        distance: F,
+        k: Option<usize>,
+        max_distance: Option<T>,
+    ) -> Vec<(ItemId, T)> {
+        let mut hits = Vec::new();
+
+        if k == Some(0) {
+            return hits;
+        }
+
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: T::zero(),
+            index: self.root,
+        });
+
+        while let Some(Candidate {
+            distance: candidate_distance,
+            index,
+        }) = queue.pop()
+        {
+            // Every remaining candidate is at least this far away, so we're done.
+            if matches!(max_distance, Some(max_distance) if candidate_distance > max_distance) {
+                break;
+            }
+
+            let node = self.get_node(index);
+
+            if node.is_leaf() {
+                hits.push((ItemId(index), candidate_distance));
+
+                if Some(hits.len()) == k {
+                    break;
+                }
+
+                continue;
+            }
+
+            for (child_index, child_node) in self.child_iter(index) {
+                queue.push(Candidate {
+                    distance: distance(child_node.get_region()),
+                    index: child_index,
+                });
+            }
+        }
+
+        hits
+    }
+}
@@ -1,7 +1,7 @@
 use crate::rtree::{combine_rects, Index};
 use geo_types::{CoordNum, Rect};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Node<S, T>
 where
     T: CoordNum,
@@ -63,6 +63,12 @@ where
         self.minimum_bounding_region
     }
 
+    /// Returns a reference to the minimum bounding region of this node.
+    #[inline(always)]
+    pub(crate) fn get_region_ref(&self) -> &Rect<T> {
+        &self.minimum_bounding_region
+    }
+
     /// Returns an iterator over the `Index`es of children of this node.
     #[inline(always)]
     pub fn child_index_iter(&self) -> impl Iterator<Item = Index> + '_ {
@@ -125,6 +131,16 @@ where
         self.children.push(child_index);
     }
 
+    /// Removes the child with index `child_index` from the current node, if present.  This method
+    /// is unsafe, as using it incorrectly will lead to corrupt data.
+    ///
+    /// To use this function safely, the removed child must either be removed from the tree or
+    /// reattached elsewhere, and the minimum bounding region of this node should be recomputed.
+    #[inline(always)]
+    pub(crate) fn remove_child_unsafe(&mut self, child_index: Index) {
+        self.children.retain(|&index| index != child_index);
+    }
+
     /// Returns the `parent` of the current node
     #[inline(always)]
     pub(crate) fn get_parent(&self) -> Option<Index> {
@@ -137,6 +153,12 @@ where
         self.parent = Some(index);
     }
 
+    /// Detaches the current node from its parent
+    #[inline(always)]
+    pub(crate) fn clear_parent(&mut self) {
+        self.parent = None;
+    }
+
     /// Overwrites the current minimum bounding region of this node.  This method is unsafe,
     /// as using it incorrectly can lead to corrupt data.
     ///
@@ -165,4 +187,25 @@ where
     pub fn get_data(&self) -> Option<&S> {
         self.data.as_ref()
     }
+
+    /// Returns a mutable reference to the data owned by this node.
+    #[inline(always)]
+    pub(crate) fn get_data_mut(&mut self) -> Option<&mut S> {
+        self.data.as_mut()
+    }
+
+    /// Returns a reference to the minimum bounding region of this node, along with a mutable
+    /// reference to the data owned by it.
+    #[inline(always)]
+    pub(crate) fn get_region_and_data_mut(&mut self) -> Option<(&Rect<T>, &mut S)> {
+        self.data
+            .as_mut()
+            .map(|data| (&self.minimum_bounding_region, data))
+    }
+
+    /// Consumes this node, returning the data owned by it.
+    #[inline(always)]
+    pub(crate) fn into_data(self) -> Option<S> {
+        self.data
+    }
 }
@@ -0,0 +1,1376 @@
+//! An R-tree whose nodes live in fixed-size pages of a file, for datasets larger than memory.
+//!
+//! A [`PagedRTree`] stores each node of the tree in its own page, and reads pages through a
+//! bounded buffer pool which keeps the most recently used pages in memory.  Pages modified by
+//! inserts and removals are written back when they are evicted from the pool, or on
+//! [`PagedRTree::flush`].
+//!
+//! Inserts use the same algorithm as the in-memory [`RTree`](crate::RTree): the same choice of
+//! subtree, the same quadratic split and the same node capacity.  Removals condense the tree in
+//! the same way too, dissolving any node left with too few children and reinserting its
+//! entries.  Each page corresponds to an internal node of an in-memory tree, with the leaves of
+//! the in-memory tree stored inline as the entries of the bottom level of pages.
+//!
+//! All integers and floats are little-endian.  Page 0 holds the metadata of the tree:
+//!
+//! | Offset | Size | Contents                                                   |
+//! |--------|------|------------------------------------------------------------|
+//! | 0      | 8    | The magic bytes `SPCPAGED`                                 |
+//! | 8      | 2    | The format version, currently `1`                          |
+//! | 10     | 2    | Reserved, zero                                             |
+//! | 12     | 4    | The page size in bytes, currently `4096`                   |
+//! | 16     | 8    | The page holding the root node                             |
+//! | 24     | 8    | The number of pages in the file                            |
+//! | 32     | 8    | The number of entries in the tree                          |
+//! | 40     | 8    | The first page of the list of free pages, or `0` if empty  |
+//!
+//! Every other page holds a node: a kind byte (`0` for a node whose children are nodes, `1`
+//! for a node whose children are entries), three reserved bytes and the number of children
+//! (a `u32`), followed by the children themselves.  The children of an internal node are
+//! stored as their minimum bounding region (four `f64`s) followed by the page of the child
+//! (a `u64`).  Entries are stored as their region, followed by the length of their payload
+//! (a `u32`) and the payload itself, as written by its [`Encode`] implementation.
+//!
+//! Pages no longer used by the tree are kept in a list of free pages, and reused before the
+//! file grows.  A free page has kind `2` and no children, and is followed by the next page of
+//! the list (a `u64`), or `0` at the end of the list.
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use geo::contains::Contains;
+use geo::intersects::Intersects;
+use geo_types::{Point, Rect};
+
+use crate::rtree::binary::{put_region, read_exact, Decode, Encode};
+use crate::rtree::metric::Metric;
+use crate::rtree::nearest::Candidate;
+use crate::rtree::{choose_subtree, combine_rects, quadratic_partition, RTreeError};
+
+/// The size of every page in the file.
+pub const PAGE_SIZE: usize = 4096;
+
+/// The magic bytes at the start of the metadata page.
+const MAGIC: &[u8; 8] = b"SPCPAGED";
+
+/// The version of the format written by this module.
+const VERSION: u16 = 1;
+
+/// The minimum number of children a node can have, as for an in-memory tree.
+const MIN_CHILDREN: usize = 2;
+
+/// Nodes are split once they reach this many children, as for an in-memory tree.
+const MAX_CHILDREN: usize = 8;
+
+/// The length of the header at the start of each node page.
+const NODE_HEADER_LENGTH: usize = 8;
+
+/// The length of each entry, not counting its payload.
+const ENTRY_HEADER_LENGTH: usize = 36;
+
+/// The longest payload which can be stored in a tree.  Nodes never have more than
+/// `MAX_CHILDREN - 1` children once split, and every one of them must fit in a page.
+pub const MAX_PAYLOAD_LENGTH: usize =
+    (PAGE_SIZE - NODE_HEADER_LENGTH) / (MAX_CHILDREN - 1) - ENTRY_HEADER_LENGTH;
+
+/// Identifies the kind of each node page.
+const KIND_INTERNAL: u8 = 0;
+const KIND_LEAF: u8 = 1;
+const KIND_FREE: u8 = 2;
+
+/// An entry as stored in a page, as a pair `(region, encoded payload)`.
+type RawEntry = (Rect<f64>, Vec<u8>);
+
+/// A node read from a page.
+#[derive(Clone, Debug)]
+enum PageNode {
+    /// A node whose children are other nodes, given as pairs `(region, page)`.
+    Internal(Vec<(Rect<f64>, u64)>),
+
+    /// A node whose children are entries, given as pairs `(region, encoded payload)`.
+    Leaf(Vec<RawEntry>),
+
+    /// A page which isn't in use, holding the next page of the list of free pages, or `0`.
+    Free(u64),
+}
+
+impl PageNode {
+    /// Returns the number of children of this node.
+    fn child_count(&self) -> usize {
+        match self {
+            PageNode::Internal(children) => children.len(),
+            PageNode::Leaf(entries) => entries.len(),
+            PageNode::Free(_) => 0,
+        }
+    }
+
+    /// Returns the regions of the children of this node.
+    fn regions(&self) -> Vec<Rect<f64>> {
+        match self {
+            PageNode::Internal(children) => children.iter().map(|(region, _)| *region).collect(),
+            PageNode::Leaf(entries) => entries.iter().map(|(region, _)| *region).collect(),
+            PageNode::Free(_) => Vec::new(),
+        }
+    }
+
+    /// Encodes this node as a page.
+    fn encode(&self) -> Vec<u8> {
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+
+        let kind = match self {
+            PageNode::Internal(_) => KIND_INTERNAL,
+            PageNode::Leaf(_) => KIND_LEAF,
+            PageNode::Free(_) => KIND_FREE,
+        };
+        page.extend_from_slice(&[kind, 0, 0, 0]);
+        page.extend_from_slice(&(self.child_count() as u32).to_le_bytes());
+
+        match self {
+            PageNode::Internal(children) => {
+                for (region, child) in children {
+                    put_region(&mut page, *region);
+                    page.extend_from_slice(&child.to_le_bytes());
+                }
+            }
+            PageNode::Leaf(entries) => {
+                for (region, payload) in entries {
+                    put_region(&mut page, *region);
+                    page.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+                    page.extend_from_slice(payload);
+                }
+            }
+            PageNode::Free(next) => page.extend_from_slice(&next.to_le_bytes()),
+        }
+
+        debug_assert!(page.len() <= PAGE_SIZE);
+        page.resize(PAGE_SIZE, 0);
+
+        page
+    }
+
+    /// Decodes a node from the page `page`, which is one of the `page_count` pages in the file.
+    fn decode(page: &[u8], page_count: u64) -> Result<Self, RTreeError> {
+        let mut cursor = PageCursor(&page[NODE_HEADER_LENGTH..]);
+        let count = u32::from_le_bytes(page[4..8].try_into().unwrap()) as usize;
+
+        if count >= MAX_CHILDREN {
+            return Err(RTreeError::Corrupt("page has too many children"));
+        }
+
+        match page[0] {
+            KIND_INTERNAL => {
+                let mut children = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let region = cursor.region()?;
+                    let child = cursor.page()?;
+
+                    if child == 0 || child >= page_count {
+                        return Err(RTreeError::Corrupt("child page is out of bounds"));
+                    }
+                    children.push((region, child));
+                }
+
+                Ok(PageNode::Internal(children))
+            }
+            KIND_LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let region = cursor.region()?;
+                    let length = u32::from_le_bytes(cursor.take(4)?.try_into().unwrap());
+                    entries.push((region, cursor.take(length as usize)?.to_vec()));
+                }
+
+                Ok(PageNode::Leaf(entries))
+            }
+            KIND_FREE => {
+                let next = cursor.page()?;
+
+                if next >= page_count {
+                    return Err(RTreeError::Corrupt("free page is out of bounds"));
+                }
+
+                Ok(PageNode::Free(next))
+            }
+            _ => Err(RTreeError::Corrupt("unknown page kind")),
+        }
+    }
+}
+
+/// A page held in the buffer pool.
+#[derive(Debug)]
+struct Frame {
+    node: PageNode,
+
+    /// Whether `node` has changed since it was read from or written to the file.
+    dirty: bool,
+
+    /// When this frame was last used, as a key into `BufferPool::recency`.
+    last_used: u64,
+}
+
+/// A bounded cache of decoded pages, evicting the least recently used page when full.
+#[derive(Debug)]
+struct BufferPool {
+    file: File,
+
+    /// The maximum number of pages held in memory.
+    capacity: usize,
+
+    /// The cached pages, keyed by page number.
+    frames: HashMap<u64, Frame>,
+
+    /// Cached page numbers keyed by when they were last used, so the least recently used page
+    /// is always first.
+    recency: BTreeMap<u64, u64>,
+
+    /// A counter incremented on every access, used to order accesses.
+    clock: u64,
+}
+
+impl BufferPool {
+    /// Returns the node stored in page `page`, reading it from the file if necessary.  This
+    /// never evicts anything, so that reading a page can't fail because another page can't be
+    /// written back; call [`BufferPool::evict`] once the page has been used.
+    fn get(&mut self, page: u64, page_count: u64) -> Result<&mut Frame, RTreeError> {
+        self.clock += 1;
+
+        if let Some(frame) = self.frames.get_mut(&page) {
+            self.recency.remove(&frame.last_used);
+            self.recency.insert(self.clock, page);
+            frame.last_used = self.clock;
+
+            return Ok(self.frames.get_mut(&page).unwrap());
+        }
+
+        let mut bytes = vec![0; PAGE_SIZE];
+        self.file.seek(SeekFrom::Start(page * PAGE_SIZE as u64))?;
+        read_exact(&mut self.file, &mut bytes)?;
+        let node = PageNode::decode(&bytes, page_count)?;
+
+        self.put(page, node, false);
+
+        Ok(self.frames.get_mut(&page).unwrap())
+    }
+
+    /// Places `node` into the pool as the contents of page `page`.  This never evicts anything,
+    /// so the pool may hold more than `capacity` pages until [`BufferPool::evict`] is called.
+    fn put(&mut self, page: u64, node: PageNode, dirty: bool) {
+        self.clock += 1;
+
+        let dirty = match self.frames.remove(&page) {
+            Some(frame) => {
+                self.recency.remove(&frame.last_used);
+                dirty || frame.dirty
+            }
+            None => dirty,
+        };
+
+        self.recency.insert(self.clock, page);
+        self.frames.insert(
+            page,
+            Frame {
+                node,
+                dirty,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Evicts the least recently used pages until the pool is within its capacity, writing
+    /// back those which are dirty.  A page is only dropped once it has been written, so a page
+    /// which fails to be written stays in the pool until a later eviction or flush succeeds.
+    fn evict(&mut self) -> Result<(), RTreeError> {
+        while self.frames.len() > self.capacity {
+            let (&last_used, &page) = self.recency.first_key_value().unwrap();
+
+            let frame = &self.frames[&page];
+            if frame.dirty {
+                write_page(&mut self.file, page, &frame.node)?;
+            }
+
+            self.recency.remove(&last_used);
+            self.frames.remove(&page);
+        }
+
+        Ok(())
+    }
+
+    /// Writes every dirty page back to the file.
+    fn flush(&mut self) -> Result<(), RTreeError> {
+        let mut dirty = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(&page, _)| page)
+            .collect::<Vec<_>>();
+        dirty.sort_unstable();
+
+        for page in dirty {
+            let frame = self.frames.get_mut(&page).unwrap();
+            write_page(&mut self.file, page, &frame.node)?;
+            frame.dirty = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes `node` to page `page` of `file`.
+fn write_page(file: &mut File, page: u64, node: &PageNode) -> Result<(), RTreeError> {
+    file.seek(SeekFrom::Start(page * PAGE_SIZE as u64))?;
+    file.write_all(&node.encode())?;
+
+    Ok(())
+}
+
+/// The metadata of a tree, as stored in page 0.
+#[derive(Clone, Copy, Debug)]
+struct Meta {
+    /// The page holding the root node.
+    root: u64,
+
+    /// The number of pages in the file, including the metadata page.
+    page_count: u64,
+
+    /// The number of entries in the tree.
+    len: u64,
+
+    /// The first page of the list of free pages, or `0` if there are none.
+    free: u64,
+}
+
+/// The changes made by a single insert or removal.
+///
+/// Nodes are copied out of the buffer pool before they are changed, and the changes are only
+/// placed back in the pool once the whole operation has succeeded.  An error part way through
+/// an operation therefore leaves the tree as it was, and the pool never holds a node which is
+/// overfull or only partly split.
+#[derive(Debug)]
+struct Transaction {
+    /// Copies of the nodes used by the operation, keyed by page.
+    nodes: HashMap<u64, PageNode>,
+
+    /// The pages whose nodes have changed.
+    changed: BTreeSet<u64>,
+
+    /// The metadata of the tree, as changed by the operation.
+    meta: Meta,
+
+    /// The entries beneath nodes dissolved by a removal, which need to be reinserted.
+    orphans: Vec<RawEntry>,
+}
+
+impl Transaction {
+    fn new(meta: Meta) -> Self {
+        Self {
+            nodes: HashMap::new(),
+            changed: BTreeSet::new(),
+            meta,
+            orphans: Vec::new(),
+        }
+    }
+
+    /// Returns the node in page `page`, reading it through `pool` if it hasn't been used yet.
+    fn node(&mut self, pool: &mut BufferPool, page: u64) -> Result<&PageNode, RTreeError> {
+        if !self.nodes.contains_key(&page) {
+            let node = pool.get(page, self.meta.page_count)?.node.clone();
+            self.nodes.insert(page, node);
+        }
+
+        Ok(&self.nodes[&page])
+    }
+
+    /// Returns the node in page `page` so that it can be changed.
+    fn node_mut(&mut self, pool: &mut BufferPool, page: u64) -> Result<&mut PageNode, RTreeError> {
+        self.node(pool, page)?;
+        self.changed.insert(page);
+
+        Ok(self.nodes.get_mut(&page).unwrap())
+    }
+
+    /// Replaces the contents of page `page` with `node`.
+    fn set(&mut self, page: u64, node: PageNode) {
+        self.nodes.insert(page, node);
+        self.changed.insert(page);
+    }
+
+    /// Stores `node` in an unused page, returning the page number.  Free pages are reused
+    /// before the file grows.
+    fn allocate(&mut self, pool: &mut BufferPool, node: PageNode) -> Result<u64, RTreeError> {
+        let page = match self.meta.free {
+            0 => {
+                self.meta.page_count += 1;
+                self.meta.page_count - 1
+            }
+            free => match *self.node(pool, free)? {
+                PageNode::Free(next) => {
+                    self.meta.free = next;
+                    free
+                }
+                _ => {
+                    return Err(RTreeError::Corrupt(
+                        "list of free pages holds a page in use",
+                    ))
+                }
+            },
+        };
+
+        self.set(page, node);
+
+        Ok(page)
+    }
+
+    /// Adds page `page` to the list of free pages.
+    fn free(&mut self, page: u64) {
+        self.set(page, PageNode::Free(self.meta.free));
+        self.meta.free = page;
+    }
+
+    /// Inserts an entry with region `region` and encoded data `payload`, splitting any overfull
+    /// nodes.  This doesn't change the number of entries in the tree.
+    fn insert(
+        &mut self,
+        pool: &mut BufferPool,
+        region: Rect<f64>,
+        payload: Vec<u8>,
+    ) -> Result<(), RTreeError> {
+        // Descend to the bottom level, enlarging the regions along the way, and remembering the
+        // path we took so that splits can be propagated back up.
+        let mut path = Vec::new();
+        let mut page = self.meta.root;
+
+        loop {
+            if path.len() as u64 >= self.meta.page_count {
+                return Err(RTreeError::Corrupt("pages contain a cycle"));
+            }
+
+            let (position, combined_region, grows, child) = match self.node(pool, page)? {
+                PageNode::Leaf(_) => break,
+                PageNode::Internal(children) => {
+                    let regions = children
+                        .iter()
+                        .map(|(region, _)| *region)
+                        .collect::<Vec<_>>();
+                    let (position, combined_region) = choose_subtree(&regions, region);
+
+                    let (child_region, child) = children[position];
+                    (
+                        position,
+                        combined_region,
+                        child_region != combined_region,
+                        child,
+                    )
+                }
+                PageNode::Free(_) => return Err(RTreeError::Corrupt("tree refers to a free page")),
+            };
+
+            if grows {
+                if let PageNode::Internal(children) = self.node_mut(pool, page)? {
+                    children[position].0 = combined_region;
+                }
+            }
+
+            path.push((page, position));
+            page = child;
+        }
+
+        if let PageNode::Leaf(entries) = self.node_mut(pool, page)? {
+            entries.push((region, payload));
+        }
+
+        // Split any overfull nodes, working back up the path.
+        while self.node(pool, page)?.child_count() >= MAX_CHILDREN {
+            let (left, right, left_region, right_region) = split(self.nodes.remove(&page).unwrap());
+
+            match path.pop() {
+                None => {
+                    // Splitting the root: the root page keeps the two halves as its children.
+                    let left_page = self.allocate(pool, left)?;
+                    let right_page = self.allocate(pool, right)?;
+
+                    self.set(
+                        page,
+                        PageNode::Internal(vec![
+                            (left_region, left_page),
+                            (right_region, right_page),
+                        ]),
+                    );
+                }
+                Some((parent, position)) => {
+                    // The current page becomes the left half, and the right half is added to
+                    // the parent.
+                    self.set(page, left);
+                    let right_page = self.allocate(pool, right)?;
+
+                    if let PageNode::Internal(children) = self.node_mut(pool, parent)? {
+                        children[position].0 = left_region;
+                        children.push((right_region, right_page));
+                    }
+
+                    page = parent;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the entries beneath page `page`, at depth `depth`, for which `remove` returns
+    /// `true`, only descending into children whose regions satisfy `pred`.  Removed entries are
+    /// added to `removed`, and the entries beneath any node left with fewer than
+    /// `MIN_CHILDREN` children are added to the orphans so that they can be reinserted.  Returns
+    /// `true` if the node in `page` changed.
+    fn remove_beneath<P, R>(
+        &mut self,
+        pool: &mut BufferPool,
+        page: u64,
+        depth: u64,
+        pred: &P,
+        remove: &mut R,
+        removed: &mut Vec<RawEntry>,
+    ) -> Result<bool, RTreeError>
+    where
+        P: Fn(&Rect<f64>) -> bool,
+        R: FnMut(&Rect<f64>, &[u8]) -> bool,
+    {
+        if depth >= self.meta.page_count {
+            return Err(RTreeError::Corrupt("pages contain a cycle"));
+        }
+
+        let children = match self.node(pool, page)? {
+            PageNode::Leaf(entries) => {
+                let doomed = entries
+                    .iter()
+                    .map(|(region, payload)| remove(region, payload))
+                    .collect::<Vec<_>>();
+
+                if !doomed.contains(&true) {
+                    return Ok(false);
+                }
+
+                if let PageNode::Leaf(entries) = self.node_mut(pool, page)? {
+                    let (gone, kept) =
+                        partition(std::mem::take(entries), |position| doomed[position]);
+                    *entries = kept;
+                    removed.extend(gone);
+                }
+
+                return Ok(true);
+            }
+            PageNode::Internal(children) => children.clone(),
+            PageNode::Free(_) => return Err(RTreeError::Corrupt("tree refers to a free page")),
+        };
+
+        let mut changed = false;
+        let mut kept = Vec::with_capacity(children.len());
+
+        for (region, child) in children {
+            if !pred(&region)
+                || !self.remove_beneath(pool, child, depth + 1, pred, remove, removed)?
+            {
+                kept.push((region, child));
+                continue;
+            }
+
+            changed = true;
+
+            let child_node = self.node(pool, child)?;
+            if child_node.child_count() < MIN_CHILDREN {
+                self.dissolve(pool, child)?;
+            } else {
+                let region = child_node
+                    .regions()
+                    .into_iter()
+                    .reduce(combine_rects)
+                    .unwrap();
+                kept.push((region, child));
+            }
+        }
+
+        if changed {
+            self.set(page, PageNode::Internal(kept));
+        }
+
+        Ok(changed)
+    }
+
+    /// Frees page `page` and every page beneath it, adding their entries to the orphans.
+    fn dissolve(&mut self, pool: &mut BufferPool, page: u64) -> Result<(), RTreeError> {
+        self.node(pool, page)?;
+
+        match self.nodes.remove(&page).unwrap() {
+            PageNode::Internal(children) => {
+                for (_, child) in children {
+                    self.dissolve(pool, child)?;
+                }
+            }
+            PageNode::Leaf(entries) => self.orphans.extend(entries),
+            PageNode::Free(_) => return Err(RTreeError::Corrupt("tree refers to a free page")),
+        }
+
+        self.free(page);
+
+        Ok(())
+    }
+
+    /// Removes levels from the top of the tree while the root has a single child.  A root
+    /// left without any children becomes an empty bottom-level node.
+    fn condense_root(&mut self, pool: &mut BufferPool) -> Result<(), RTreeError> {
+        loop {
+            let root = self.meta.root;
+
+            match self.node(pool, root)? {
+                PageNode::Internal(children) if children.is_empty() => {
+                    self.set(root, PageNode::Leaf(Vec::new()));
+                }
+                PageNode::Internal(children) if children.len() == 1 => {
+                    self.meta.root = children[0].1;
+                    self.free(root);
+                    continue;
+                }
+                _ => {}
+            }
+
+            return Ok(());
+        }
+    }
+}
+
+/// An entry or page waiting to be visited by a nearest neighbour search.
+enum Visit {
+    Page(u64),
+    Entry(Rect<f64>, Vec<u8>),
+}
+
+/// An R-tree stored in fixed-size pages of a file, read through a bounded LRU buffer pool.
+///
+/// Regions are stored as `f64`s, and the data of each entry is stored using its [`Encode`]
+/// and [`Decode`] implementations.  As pages are read through the buffer pool, even queries
+/// need mutable access to the tree.
+///
+/// # Errors
+/// An insert or removal which fails to read a page leaves the tree unchanged.  Once an insert
+/// or removal has been made, pages are evicted to bring the buffer pool back within its
+/// capacity, and an error writing one of them is returned too.  The change has still been made
+/// in that case, and the page is kept in memory until a later eviction or flush writes it.
+///
+/// # Example
+/// ```rust
+/// use spaceindex::Rect;
+/// use spaceindex::rtree::paged::PagedRTree;
+///
+/// let path = std::env::temp_dir().join(format!("paged-doc-{}.idx", std::process::id()));
+///
+/// // Keep at most 16 pages in memory.
+/// let mut tree = PagedRTree::create(&path, 16).unwrap();
+/// tree.insert(Rect::new((0.0, 0.0), (2.0, 2.0)), String::from("house")).unwrap();
+/// tree.sync().unwrap();
+/// drop(tree);
+///
+/// let mut tree = PagedRTree::<String>::open(&path, 16).unwrap();
+/// assert_eq!(
+///     tree.point_lookup((1.0, 1.0)).unwrap(),
+///     vec![(Rect::new((0.0, 0.0), (2.0, 2.0)), String::from("house"))]
+/// );
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct PagedRTree<ND>
+where
+    ND: Encode + Decode,
+{
+    pool: BufferPool,
+
+    meta: Meta,
+
+    /// Whether the metadata has changed since it was last written.
+    dirty: bool,
+
+    _data: PhantomData<ND>,
+}
+
+impl<ND> PagedRTree<ND>
+where
+    ND: Encode + Decode,
+{
+    /// Creates a new, empty tree in the file at `path`, replacing anything already there.
+    /// At most `pool_capacity` pages are held in memory at once.
+    ///
+    /// # Panics
+    /// This function will panic if `pool_capacity` is zero.
+    ///
+    /// # Errors
+    /// This function will return an error if the file can't be created.
+    pub fn create<P: AsRef<Path>>(path: P, pool_capacity: usize) -> Result<Self, RTreeError> {
+        assert!(pool_capacity > 0);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        let mut tree = Self::with_file(
+            file,
+            pool_capacity,
+            Meta {
+                root: 1,
+                page_count: 2,
+                len: 0,
+                free: 0,
+            },
+        );
+        tree.pool.put(1, PageNode::Leaf(Vec::new()), true);
+        tree.flush()?;
+
+        Ok(tree)
+    }
+
+    /// Opens the tree in the file at `path`, which should have been created by
+    /// [`PagedRTree::create`].  At most `pool_capacity` pages are held in memory at once.
+    ///
+    /// # Panics
+    /// This function will panic if `pool_capacity` is zero.
+    ///
+    /// # Errors
+    /// This function will return an error if the file can't be read, or doesn't hold a tree.
+    pub fn open<P: AsRef<Path>>(path: P, pool_capacity: usize) -> Result<Self, RTreeError> {
+        assert!(pool_capacity > 0);
+
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let mut meta = vec![0; PAGE_SIZE];
+        read_exact(&mut file, &mut meta[..MAGIC.len()])?;
+        if &meta[..MAGIC.len()] != MAGIC {
+            return Err(RTreeError::BadMagic);
+        }
+        read_exact(&mut file, &mut meta[MAGIC.len()..])?;
+
+        let version = u16::from_le_bytes(meta[8..10].try_into().unwrap());
+        if version != VERSION {
+            return Err(RTreeError::UnsupportedVersion(version));
+        }
+        if u32::from_le_bytes(meta[12..16].try_into().unwrap()) as usize != PAGE_SIZE {
+            return Err(RTreeError::Corrupt("unsupported page size"));
+        }
+
+        let field =
+            |offset: usize| u64::from_le_bytes(meta[offset..offset + 8].try_into().unwrap());
+        let meta = Meta {
+            root: field(16),
+            page_count: field(24),
+            len: field(32),
+            free: field(40),
+        };
+
+        if meta.root == 0 || meta.root >= meta.page_count {
+            return Err(RTreeError::Corrupt("root page is out of bounds"));
+        }
+        if meta.free >= meta.page_count {
+            return Err(RTreeError::Corrupt("free page is out of bounds"));
+        }
+        if file.metadata()?.len() < meta.page_count * PAGE_SIZE as u64 {
+            return Err(RTreeError::Truncated);
+        }
+
+        let mut tree = Self::with_file(file, pool_capacity, meta);
+        tree.dirty = false;
+
+        Ok(tree)
+    }
+
+    /// Creates a tree over `file` with the given metadata.
+    fn with_file(file: File, pool_capacity: usize, meta: Meta) -> Self {
+        Self {
+            pool: BufferPool {
+                file,
+                capacity: pool_capacity,
+                frames: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+            },
+            meta,
+            dirty: true,
+            _data: PhantomData,
+        }
+    }
+
+    /// Returns the number of entries in the tree.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.meta.len as usize
+    }
+
+    /// Returns `true` if the tree contains no entries, `false` otherwise.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.meta.len == 0
+    }
+
+    /// Returns the number of pages currently held in the buffer pool.
+    #[inline(always)]
+    pub fn cached_pages(&self) -> usize {
+        self.pool.frames.len()
+    }
+
+    /// Inserts an entry with region `region` and data `data` into the tree.
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::PayloadTooLarge`] if `data` encodes to more than
+    /// [`MAX_PAYLOAD_LENGTH`] bytes, or an error if reading or writing a page fails (see
+    /// [Errors](PagedRTree#errors)).
+    pub fn insert(&mut self, region: Rect<f64>, data: ND) -> Result<(), RTreeError> {
+        let mut payload = Vec::new();
+        data.encode(&mut payload);
+
+        if payload.len() > MAX_PAYLOAD_LENGTH {
+            return Err(RTreeError::PayloadTooLarge);
+        }
+
+        let mut tx = Transaction::new(self.meta);
+        tx.insert(&mut self.pool, region, payload)?;
+        tx.meta.len += 1;
+
+        self.commit(tx)
+    }
+
+    /// Removes an entry whose region is `region` and whose data encodes to the same bytes as
+    /// `data`, returning `true` if there was one.  Only one entry is removed, even if several
+    /// match.
+    ///
+    /// # Errors
+    /// This function will return an error if reading or writing a page fails (see
+    /// [Errors](PagedRTree#errors)).
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::Rect;
+    /// use spaceindex::rtree::paged::PagedRTree;
+    ///
+    /// let path = std::env::temp_dir().join(format!("paged-remove-doc-{}.idx", std::process::id()));
+    /// let mut tree = PagedRTree::create(&path, 16).unwrap();
+    ///
+    /// let region = Rect::new((0.0, 0.0), (2.0, 2.0));
+    /// tree.insert(region, 7u64).unwrap();
+    ///
+    /// assert!(!tree.remove(region, &8).unwrap());
+    /// assert!(tree.remove(region, &7).unwrap());
+    /// assert!(tree.is_empty());
+    /// # std::fs::remove_file(path).unwrap();
+    /// ```
+    pub fn remove(&mut self, region: Rect<f64>, data: &ND) -> Result<bool, RTreeError> {
+        let mut payload = Vec::new();
+        data.encode(&mut payload);
+
+        let mut found = false;
+        let (tx, removed) = self.remove_where(
+            |child_region| covers(child_region, &region),
+            |entry_region, entry_payload| {
+                let matches = !found && *entry_region == region && entry_payload == payload;
+                found |= matches;
+                matches
+            },
+        )?;
+
+        self.commit(tx)?;
+
+        Ok(!removed.is_empty())
+    }
+
+    /// Removes every entry whose region intersects `region`, returning the removed pairs
+    /// `(region, data)`.
+    ///
+    /// # Errors
+    /// This function will return an error if reading or writing a page, or decoding a removed
+    /// entry, fails (see [Errors](PagedRTree#errors)).  Nothing is removed if an entry can't be
+    /// decoded.
+    pub fn remove_in_region(
+        &mut self,
+        region: Rect<f64>,
+    ) -> Result<Vec<(Rect<f64>, ND)>, RTreeError> {
+        let (tx, removed) = self.remove_where(
+            |child_region| child_region.intersects(&region),
+            |entry_region, _| entry_region.intersects(&region),
+        )?;
+
+        let removed = removed
+            .into_iter()
+            .map(|(region, payload)| {
+                let data =
+                    ND::decode(&payload).map_err(|error| RTreeError::Decode(Box::new(error)))?;
+                Ok((region, data))
+            })
+            .collect::<Result<Vec<_>, RTreeError>>()?;
+
+        self.commit(tx)?;
+
+        Ok(removed)
+    }
+
+    /// Removes the entries for which `remove` returns `true`, only descending into children
+    /// whose regions satisfy `pred`, and condenses the tree.  Returns the changes to be
+    /// committed along with the removed entries.
+    fn remove_where<P, R>(
+        &mut self,
+        pred: P,
+        mut remove: R,
+    ) -> Result<(Transaction, Vec<RawEntry>), RTreeError>
+    where
+        P: Fn(&Rect<f64>) -> bool,
+        R: FnMut(&Rect<f64>, &[u8]) -> bool,
+    {
+        let mut tx = Transaction::new(self.meta);
+        let mut removed = Vec::new();
+
+        tx.remove_beneath(
+            &mut self.pool,
+            self.meta.root,
+            0,
+            &pred,
+            &mut remove,
+            &mut removed,
+        )?;
+
+        if !removed.is_empty() {
+            tx.condense_root(&mut self.pool)?;
+
+            for (region, payload) in std::mem::take(&mut tx.orphans) {
+                tx.insert(&mut self.pool, region, payload)?;
+            }
+
+            tx.meta.len -= removed.len() as u64;
+        }
+
+        Ok((tx, removed))
+    }
+
+    /// Places the changes made by `tx` into the buffer pool, then evicts pages until the pool is
+    /// back within its capacity.
+    fn commit(&mut self, mut tx: Transaction) -> Result<(), RTreeError> {
+        if !tx.changed.is_empty() {
+            for page in tx.changed {
+                let node = tx.nodes.remove(&page).unwrap();
+                self.pool.put(page, node, true);
+            }
+
+            self.meta = tx.meta;
+            self.dirty = true;
+        }
+
+        self.pool.evict()
+    }
+
+    /// Returns the pairs `(region, data)` of those entries intersecting the given point `point`.
+    ///
+    /// # Errors
+    /// This function will return an error if reading a page, or decoding an entry, fails.
+    pub fn point_lookup<P: Into<Point<f64>>>(
+        &mut self,
+        point: P,
+    ) -> Result<Vec<(Rect<f64>, ND)>, RTreeError> {
+        let point = point.into();
+
+        self.lookup(|region| region.intersects(&point))
+    }
+
+    /// Returns the pairs `(region, data)` of those entries whose region intersects the given
+    /// region.
+    ///
+    /// # Errors
+    /// This function will return an error if reading a page, or decoding an entry, fails.
+    pub fn region_intersection_lookup(
+        &mut self,
+        region: Rect<f64>,
+    ) -> Result<Vec<(Rect<f64>, ND)>, RTreeError> {
+        self.lookup(|child_region| child_region.intersects(&region))
+    }
+
+    /// Returns the pairs `(region, data)` of those entries whose region contains the given
+    /// region.
+    ///
+    /// # Errors
+    /// This function will return an error if reading a page, or decoding an entry, fails.
+    pub fn region_lookup(&mut self, region: Rect<f64>) -> Result<Vec<(Rect<f64>, ND)>, RTreeError> {
+        self.lookup(|child_region| child_region.contains(&region))
+    }
+
+    /// Searches the tree, descending into every child whose region satisfies `pred`.
+    fn lookup<F: Fn(&Rect<f64>) -> bool>(
+        &mut self,
+        pred: F,
+    ) -> Result<Vec<(Rect<f64>, ND)>, RTreeError> {
+        let mut hits = Vec::new();
+        let mut work_queue = vec![self.meta.root];
+
+        // Pages form a tree, so each page should be visited at most once.
+        let mut visited = 0;
+
+        while let Some(page) = work_queue.pop() {
+            visited += 1;
+            if visited > self.meta.page_count {
+                return Err(RTreeError::Corrupt("pages contain a cycle"));
+            }
+
+            match &self.pool.get(page, self.meta.page_count)?.node {
+                PageNode::Internal(children) => {
+                    work_queue.extend(
+                        children
+                            .iter()
+                            .filter(|(region, _)| pred(region))
+                            .map(|(_, child)| *child),
+                    );
+                }
+                PageNode::Leaf(entries) => {
+                    for (region, payload) in entries.iter().filter(|(region, _)| pred(region)) {
+                        let data = ND::decode(payload)
+                            .map_err(|error| RTreeError::Decode(Box::new(error)))?;
+                        hits.push((*region, data));
+                    }
+                }
+                PageNode::Free(_) => return Err(RTreeError::Corrupt("tree refers to a free page")),
+            }
+
+            self.pool.evict()?;
+        }
+
+        Ok(hits)
+    }
+
+    /// Returns up to `k` triples `(region, data, distance)` of the entries closest to `point`,
+    /// ordered by increasing distance, with distances measured as by
+    /// [`RTree::nearest_neighbors`](crate::RTree::nearest_neighbors).
+    ///
+    /// # Errors
+    /// This function will return an error if reading a page, or decoding an entry, fails.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::Rect;
+    /// use spaceindex::rtree::metric::Euclidean;
+    /// use spaceindex::rtree::paged::PagedRTree;
+    ///
+    /// let path = std::env::temp_dir().join(format!("paged-nearest-doc-{}.idx", std::process::id()));
+    /// let mut tree = PagedRTree::create(&path, 16).unwrap();
+    ///
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a' as u32).unwrap();
+    /// tree.insert(Rect::new((4.0, 3.0), (5.0, 5.0)), 'b' as u32).unwrap();
+    ///
+    /// let nearest = tree.nearest_neighbors((2.0, 2.0), 1, Euclidean).unwrap();
+    /// assert_eq!(nearest, vec![(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a' as u32, 2.0f64.sqrt())]);
+    /// # std::fs::remove_file(path).unwrap();
+    /// ```
+    pub fn nearest_neighbors<P: Into<Point<f64>>, M: Metric<f64>>(
+        &mut self,
+        point: P,
+        k: usize,
+        metric: M,
+    ) -> Result<Vec<(Rect<f64>, ND, f64)>, RTreeError> {
+        let point = point.into();
+
+        self.nearest(
+            |region| metric.distance_to_rect(point, region),
+            Some(k),
+            None,
+        )
+    }
+
+    /// Returns all triples `(region, data, distance)` of entries within `distance` of `point`
+    /// as measured by `metric`, ordered by increasing distance.
+    ///
+    /// # Errors
+    /// This function will return an error if reading a page, or decoding an entry, fails.
+    pub fn within_distance<P: Into<Point<f64>>, M: Metric<f64>>(
+        &mut self,
+        point: P,
+        distance: f64,
+        metric: M,
+    ) -> Result<Vec<(Rect<f64>, ND, f64)>, RTreeError> {
+        let point = point.into();
+
+        self.nearest(
+            |region| metric.distance_to_rect(point, region),
+            None,
+            Some(distance),
+        )
+    }
+
+    /// Performs a best-first search of the tree, as for an in-memory tree, returning entries in
+    /// order of increasing distance.
+    fn nearest<F: Fn(Rect<f64>) -> f64>(
+        &mut self,
+        distance: F,
+        k: Option<usize>,
+        max_distance: Option<f64>,
+    ) -> Result<Vec<(Rect<f64>, ND, f64)>, RTreeError> {
+        let mut hits = Vec::new();
+
+        if k == Some(0) {
+            return Ok(hits);
+        }
+
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: 0.0,
+            index: Visit::Page(self.meta.root),
+        });
+
+        // Pages form a tree, so each page should be visited at most once.
+        let mut visited = 0;
+
+        while let Some(Candidate {
+            distance: candidate_distance,
+            index,
+        }) = queue.pop()
+        {
+            // Every remaining candidate is at least this far away, so we're done.
+            if matches!(max_distance, Some(max_distance) if candidate_distance > max_distance) {
+                break;
+            }
+
+            let page = match index {
+                Visit::Entry(region, payload) => {
+                    let data = ND::decode(&payload)
+                        .map_err(|error| RTreeError::Decode(Box::new(error)))?;
+                    hits.push((region, data, candidate_distance));
+
+                    if Some(hits.len()) == k {
+                        break;
+                    }
+
+                    continue;
+                }
+                Visit::Page(page) => page,
+            };
+
+            visited += 1;
+            if visited > self.meta.page_count {
+                return Err(RTreeError::Corrupt("pages contain a cycle"));
+            }
+
+            match &self.pool.get(page, self.meta.page_count)?.node {
+                PageNode::Internal(children) => {
+                    queue.extend(children.iter().map(|(region, child)| Candidate {
+                        distance: distance(*region),
+                        index: Visit::Page(*child),
+                    }));
+                }
+                PageNode::Leaf(entries) => {
+                    queue.extend(entries.iter().map(|(region, payload)| Candidate {
+                        distance: distance(*region),
+                        index: Visit::Entry(*region, payload.clone()),
+                    }));
+                }
+                PageNode::Free(_) => return Err(RTreeError::Corrupt("tree refers to a free page")),
+            }
+
+            self.pool.evict()?;
+        }
+
+        Ok(hits)
+    }
+
+    /// Checks the consistency of the tree, as for
+    /// [`RTree::check_consistency`](crate::RTree::check_consistency).  That is,
+    /// - The region of each child of a node contains the regions of its own children.
+    /// - Every page is either reachable from the root exactly once, or on the list of free pages.
+    /// - The number of entries beneath the root is the length of the tree.
+    ///
+    /// # Errors
+    /// This function will return [`RTreeError::Corrupt`] describing the first problem found
+    /// if the tree is inconsistent, or an error if reading a page fails.
+    pub fn check_consistency(&mut self) -> Result<(), RTreeError> {
+        let mut seen = BTreeSet::new();
+        let mut entries = 0;
+        let mut work_queue = vec![(self.meta.root, None)];
+
+        while let Some((page, region)) = work_queue.pop() {
+            if !seen.insert(page) {
+                return Err(RTreeError::Corrupt("pages contain a cycle"));
+            }
+
+            let node = &self.pool.get(page, self.meta.page_count)?.node;
+            if let PageNode::Free(_) = node {
+                return Err(RTreeError::Corrupt("tree refers to a free page"));
+            }
+
+            if let Some(region) = region {
+                if !node.regions().iter().all(|child| covers(&region, child)) {
+                    return Err(RTreeError::Corrupt("child region exceeds its parent"));
+                }
+            }
+
+            match node {
+                PageNode::Internal(children) => work_queue.extend(
+                    children
+                        .iter()
+                        .map(|(region, child)| (*child, Some(*region))),
+                ),
+                PageNode::Leaf(leaf_entries) => entries += leaf_entries.len() as u64,
+                PageNode::Free(_) => unreachable!(),
+            }
+
+            self.pool.evict()?;
+        }
+
+        let mut free = self.meta.free;
+        while free != 0 {
+            if !seen.insert(free) {
+                return Err(RTreeError::Corrupt("free page is also in use"));
+            }
+
+            free = match self.pool.get(free, self.meta.page_count)?.node {
+                PageNode::Free(next) => next,
+                _ => {
+                    return Err(RTreeError::Corrupt(
+                        "list of free pages holds a page in use",
+                    ))
+                }
+            };
+
+            self.pool.evict()?;
+        }
+
+        if seen.len() as u64 != self.meta.page_count - 1 {
+            return Err(RTreeError::Corrupt("file contains unreachable pages"));
+        }
+        if entries != self.meta.len {
+            return Err(RTreeError::Corrupt("tree length doesn't match its entries"));
+        }
+
+        Ok(())
+    }
+
+    /// Writes every modified page, and the metadata of the tree, back to the file.
+    ///
+    /// # Errors
+    /// This function will return an error if writing to the file fails.
+    pub fn flush(&mut self) -> Result<(), RTreeError> {
+        self.pool.flush()?;
+
+        if self.dirty {
+            let mut meta = vec![0; PAGE_SIZE];
+            meta[..8].copy_from_slice(MAGIC);
+            meta[8..10].copy_from_slice(&VERSION.to_le_bytes());
+            meta[12..16].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+            meta[16..24].copy_from_slice(&self.meta.root.to_le_bytes());
+            meta[24..32].copy_from_slice(&self.meta.page_count.to_le_bytes());
+            meta[32..40].copy_from_slice(&self.meta.len.to_le_bytes());
+            meta[40..48].copy_from_slice(&self.meta.free.to_le_bytes());
+
+            self.pool.file.seek(SeekFrom::Start(0))?;
+            self.pool.file.write_all(&meta)?;
+            self.dirty = false;
+        }
+
+        self.pool.file.flush()?;
+
+        Ok(())
+    }
+
+    /// Flushes the tree, then waits until the file has reached durable storage.
+    ///
+    /// # Errors
+    /// This function will return an error if writing to or syncing the file fails.
+    pub fn sync(&mut self) -> Result<(), RTreeError> {
+        self.flush()?;
+        self.pool.file.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Replaces the file pages are read from and written to, so that tests can make I/O fail.
+    #[cfg(test)]
+    pub(crate) fn replace_file(&mut self, file: File) {
+        self.pool.file = file;
+    }
+}
+
+impl<ND> Drop for PagedRTree<ND>
+where
+    ND: Encode + Decode,
+{
+    /// Flushes the tree on a best-effort basis.  Call [`PagedRTree::flush`] or
+    /// [`PagedRTree::sync`] to find out whether this succeeds.
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Splits the children of `node` into two nodes using the quadratic split, returning the new
+/// nodes along with their minimum bounding regions.
+fn split(node: PageNode) -> (PageNode, PageNode, Rect<f64>, Rect<f64>) {
+    let (group1, left_region, right_region) =
+        quadratic_partition(&node.regions(), MIN_CHILDREN, MAX_CHILDREN);
+
+    let (left, right) = match node {
+        PageNode::Internal(children) => {
+            let (left, right) = partition(children, |position| group1.contains(&position));
+            (PageNode::Internal(left), PageNode::Internal(right))
+        }
+        PageNode::Leaf(entries) => {
+            let (left, right) = partition(entries, |position| group1.contains(&position));
+            (PageNode::Leaf(left), PageNode::Leaf(right))
+        }
+        PageNode::Free(_) => unreachable!("free pages have no children to split"),
+    };
+
+    (left, right, left_region, right_region)
+}
+
+/// Splits `items` into those whose positions satisfy `left` and the rest.
+fn partition<S, F: Fn(usize) -> bool>(items: Vec<S>, left: F) -> (Vec<S>, Vec<S>) {
+    let mut left_items = Vec::new();
+    let mut right_items = Vec::new();
+
+    for (position, item) in items.into_iter().enumerate() {
+        if left(position) {
+            left_items.push(item);
+        } else {
+            right_items.push(item);
+        }
+    }
+
+    (left_items, right_items)
+}
+
+/// Returns `true` if every point of `inner`, including its boundary, lies in `outer`.
+fn covers(outer: &Rect<f64>, inner: &Rect<f64>) -> bool {
+    let (outer_min, outer_max) = (outer.min(), outer.max());
+    let (inner_min, inner_max) = (inner.min(), inner.max());
+
+    outer_min.x <= inner_min.x
+        && outer_min.y <= inner_min.y
+        && outer_max.x >= inner_max.x
+        && outer_max.y >= inner_max.y
+}
+
+/// A cursor over the children of a node page.
+struct PageCursor<'a>(&'a [u8]);
+
+impl<'a> PageCursor<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], RTreeError> {
+        if self.0.len() < length {
+            return Err(RTreeError::Corrupt("node extends past the end of its page"));
+        }
+
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn page(&mut self) -> Result<u64, RTreeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn region(&mut self) -> Result<Rect<f64>, RTreeError> {
+        let bytes = self.take(32)?;
+        let value =
+            |offset: usize| f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+        Ok(Rect::new((value(0), value(8)), (value(16), value(24))))
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Coordinate, Point, Rect};
+
+use crate::rtree::{ItemId, RTree, RTreeError};
+
+/// The periodic images of an (already wrapped) query which can intersect a wrapped entry.
+const INTERSECTION_SHIFTS: [i8; 3] = [-1, 0, 1];
+
+/// The periodic images of an (already wrapped) query which can be contained in a wrapped entry.
+/// Entries only ever overhang the upper edges of the domain, so only positive shifts are needed.
+const CONTAINMENT_SHIFTS: [i8; 2] = [0, 1];
+
+impl<ND, T> RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Creates a new [`RTree`] whose entries live on the periodic (toroidal) domain `domain`.
+    ///
+    /// Entries are wrapped into the domain on insertion, and region, point and proximity
+    /// queries find entries across the periodic boundaries.  A query straddling an edge of the
+    /// domain is split into its wrapped pieces.
+    ///
+    /// # Panics
+    /// This function will panic if `domain` has zero width or height.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::with_periodic_domain(Rect::new((0.0, 0.0), (10.0, 10.0)));
+    ///
+    /// // This region overhangs the right edge of the domain.
+    /// tree.insert(Rect::new((9.0, 4.0), (11.0, 6.0)), ()).unwrap();
+    ///
+    /// // This region lies outside the domain, and is wrapped back into it.
+    /// tree.insert(Rect::new((-5.0, 4.0), (-4.0, 6.0)), ()).unwrap();
+    ///
+    /// // The first region wraps around to cover the point (0.5, 5.0)
+    /// assert_eq!(tree.point_lookup((0.5, 5.0)).len(), 1);
+    ///
+    /// // The second region is found at its wrapped position
+    /// assert_eq!(tree.point_lookup((5.5, 5.0)).len(), 1);
+    ///
+    /// // This query straddles the left edge of the domain, so intersects both regions.
+    /// assert_eq!(tree.region_intersection_lookup(Rect::new((-4.5, 4.5), (0.5, 5.5))).len(), 2);
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn with_periodic_domain(domain: Rect<T>) -> Self {
+        assert!(domain.width() > T::zero() && domain.height() > T::zero());
+
+        Self {
+            domain: Some(domain),
+            ..Self::new()
+        }
+    }
+
+    /// Returns the periodic domain of this tree, if it has one.
+    #[inline(always)]
+    pub fn periodic_domain(&self) -> Option<Rect<T>> {
+        self.domain
+    }
+
+    /// Wraps `region` into the periodic domain of this tree (if any), so that its lower-left
+    /// corner lies in the domain.
+    ///
+    /// # Errors
+    /// This function will return an error if `region` is wider or taller than the domain.
+    pub(crate) fn wrap_region(&self, region: Rect<T>) -> Result<Rect<T>, RTreeError> {
+        match self.domain {
+            None => Ok(region),
+            Some(domain) => {
+                if region.width() > domain.width() || region.height() > domain.height() {
+                    return Err(RTreeError::RegionLargerThanDomain);
+                }
+
+                let min = wrap_coordinate(domain, region.min());
+                Ok(Rect::new(
+                    min,
+                    Coordinate {
+                        x: min.x + region.width(),
+                        y: min.y + region.height(),
+                    },
+                ))
+            }
+        }
+    }
+
+    /// Runs `lookup` against each periodic image of `region` in turn, returning every hit once.
+    /// Trees without a periodic domain just run `lookup` against `region`.
+    pub(crate) fn periodic_lookup<F: Fn(Rect<T>) -> Vec<ItemId>>(
+        &self,
+        region: Rect<T>,
+        containment: bool,
+        lookup: F,
+    ) -> Vec<ItemId> {
+        let domain = match self.domain {
+            None => return lookup(region),
+            Some(domain) => domain,
+        };
+
+        let shifts: &[i8] = if containment {
+            &CONTAINMENT_SHIFTS
+        } else {
+            &INTERSECTION_SHIFTS
+        };
+
+        // Queries larger than the domain can't be wrapped sensibly, so clamp them to its size.
+        let region = Rect::new(
+            region.min(),
+            Coordinate {
+                x: region.min().x + region.width().min(domain.width()),
+                y: region.min().y + region.height().min(domain.height()),
+            },
+        );
+        let min = wrap_coordinate(domain, region.min());
+
+        let mut seen = HashSet::new();
+        let mut hits = Vec::new();
+        for &dx in shifts {
+            for &dy in shifts {
+                let offset = Coordinate {
+                    x: T::from(dx).unwrap() * domain.width(),
+                    y: T::from(dy).unwrap() * domain.height(),
+                };
+                let image = Rect::new(min + offset, min + offset + (region.max() - region.min()));
+
+                hits.extend(lookup(image).into_iter().filter(|hit| seen.insert(*hit)));
+            }
+        }
+
+        hits
+    }
+
+    /// Returns the smallest distance under `distance` between `point` and any periodic image
+    /// of `region`.  Trees without a periodic domain just measure the distance to `region`.
+    pub(crate) fn periodic_distance<F: Fn(Point<T>, Rect<T>) -> T>(
+        &self,
+        point: Point<T>,
+        region: Rect<T>,
+        distance: F,
+    ) -> T {
+        let domain = match self.domain {
+            None => return distance(point, region),
+            Some(domain) => domain,
+        };
+
+        let point = wrap_coordinate(domain, point.into());
+
+        let mut best = T::infinity();
+        for &dx in INTERSECTION_SHIFTS.iter() {
+            for &dy in INTERSECTION_SHIFTS.iter() {
+                let image = Point::new(
+                    point.x + T::from(dx).unwrap() * domain.width(),
+                    point.y + T::from(dy).unwrap() * domain.height(),
+                );
+                best = best.min(distance(image, region));
+            }
+        }
+
+        best
+    }
+}
+
+/// Wraps `coordinate` into the half-open periodic domain `domain`.
+fn wrap_coordinate<T: CoordFloat>(domain: Rect<T>, coordinate: Coordinate<T>) -> Coordinate<T> {
+    Coordinate {
+        x: wrap_value(coordinate.x, domain.min().x, domain.width()),
+        y: wrap_value(coordinate.y, domain.min().y, domain.height()),
+    }
+}
+
+/// Wraps `value` into the interval `[start, start + length)`.
+fn wrap_value<T: CoordFloat>(value: T, start: T, length: T) -> T {
+    let mut offset = (value - start) % length;
+
+    if offset < T::zero() {
+        offset = offset + length;
+    }
+
+    // Guard against rounding pushing us onto the upper edge.
+    if offset >= length {
+        offset = T::zero();
+    }
+
+    start + offset
+}
@@ -0,0 +1,277 @@
+use std::collections::{HashMap, HashSet};
+
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Coordinate, Rect};
+
+use crate::rtree::{combine_rects, Index, ItemId, RTree, RTreeError};
+
+impl<ND, T> RTree<ND, T>
+where
+    T: CoordFloat + HasKernel,
+{
+    /// Removes the element `id` from the tree, returning its region and data.
+    /// Returns `None` if `id` isn't in this tree.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// let id = tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a').unwrap();
+    ///
+    /// assert_eq!(tree.remove(id), Some((Rect::new((0.0, 0.0), (1.0, 1.0)), 'a')));
+    /// assert_eq!(tree.remove(id), None);
+    /// assert!(tree.point_lookup((0.5, 0.5)).is_empty());
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn remove(&mut self, id: ItemId) -> Option<(Rect<T>, ND)> {
+        self.remove_leaves(vec![id.0]).pop()
+    }
+
+    /// Moves the element `id` to the region `region`.  The element keeps its [`ItemId`].
+    ///
+    /// # Errors
+    /// This function will return an error if `id` isn't in this tree,
+    /// or if this tree has a periodic domain and `region` is larger than it.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    /// let id = tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a').unwrap();
+    ///
+    /// tree.update_region(id, Rect::new((5.0, 5.0), (6.0, 6.0))).unwrap();
+    ///
+    /// assert!(tree.point_lookup((0.5, 0.5)).is_empty());
+    /// assert_eq!(tree.point_lookup((5.5, 5.5)), vec![id]);
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn update_region(&mut self, id: ItemId, region: Rect<T>) -> Result<(), RTreeError> {
+        let index = id.0;
+
+        if !self.nodes.get(index).is_some_and(|node| node.is_leaf()) {
+            return Err(RTreeError::ItemNotFound);
+        }
+
+        let region = self.wrap_region(region)?;
+
+        // Detach the leaf from the tree, condensing the tree around where it used to be.
+        let mut dirty = HashSet::new();
+        if let Some(parent) = self.get_node(index).get_parent() {
+            self.get_node_mut(parent).remove_child_unsafe(index);
+            dirty.insert(parent);
+        }
+        self.condense(dirty);
+
+        // This is safe as the leaf has no children, and is no longer attached to the tree.
+        let leaf_node = self.get_node_mut(index);
+        leaf_node.clear_parent();
+        leaf_node.set_minimum_bounding_region_unsafe(region);
+
+        self.insert_leaf(index)
+    }
+
+    /// Removes every element in the tree whose minimum bounding box intersects the given region,
+    /// returning the removed pairs `(region, data)`.
+    ///
+    /// The tree is only condensed once, after all of the elements have been removed.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    ///
+    /// // insert a few regions
+    /// tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 'a');
+    /// tree.insert(Rect::new((2.0, 2.0), (3.0, 3.0)), 'b');
+    /// tree.insert(Rect::new((2.5, 0.0), (3.5, 1.0)), 'c');
+    ///
+    /// // Clear out everything touching the box ((1.5, 1.5), (4.0, 4.0))
+    /// let removed = tree.remove_in_region(Rect::new((1.5, 1.5), (4.0, 4.0)));
+    /// assert_eq!(removed, vec![(Rect::new((2.0, 2.0), (3.0, 3.0)), 'b')]);
+    ///
+    /// // The other regions are still there
+    /// assert_eq!(tree.region_intersection_lookup(Rect::new((0.0, 0.0), (4.0, 4.0))).len(), 2);
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn remove_in_region(&mut self, region: Rect<T>) -> Vec<(Rect<T>, ND)> {
+        let leaves = self
+            .region_intersection_lookup(region)
+            .into_iter()
+            .map(|id| id.0)
+            .collect();
+
+        self.remove_leaves(leaves)
+    }
+
+    /// Retains only the elements for which `keep` returns `true`, removing everything else.
+    ///
+    /// The tree is only condensed once, after all of the elements have been removed.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let mut tree = RTree::new();
+    ///
+    /// for i in 0..100 {
+    ///     let x = i as f64;
+    ///     tree.insert(Rect::new((x, 0.0), (x + 1.0, 1.0)), i);
+    /// }
+    ///
+    /// // Only keep the even elements
+    /// tree.retain(|_, data| data % 2 == 0);
+    ///
+    /// // The point (10.5, 0.5) is only contained in the region for element 10
+    /// let hits = tree.point_lookup((10.5, 0.5));
+    /// assert_eq!(hits.len(), 1);
+    /// assert_eq!(tree.get(hits[0]).unwrap().1, &10);
+    /// # tree.validate_consistency();
+    /// ```
+    pub fn retain<F: FnMut(&Rect<T>, &ND) -> bool>(&mut self, mut keep: F) {
+        let leaves = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| match node.get_data() {
+                Some(data) => !keep(&node.get_region(), data),
+                None => false,
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        self.remove_leaves(leaves);
+    }
+
+    /// Removes the given leaves from the tree and condenses it, returning the removed pairs
+    /// `(region, data)`.  Any index which doesn't refer to a leaf of this tree is ignored.
+    pub(crate) fn remove_leaves(&mut self, leaves: Vec<Index>) -> Vec<(Rect<T>, ND)> {
+        let mut removed = Vec::with_capacity(leaves.len());
+        let mut dirty = HashSet::new();
+
+        for leaf_index in leaves {
+            if !self
+                .nodes
+                .get(leaf_index)
+                .is_some_and(|node| node.is_leaf())
+            {
+                continue;
+            }
+
+            let node = self.nodes.remove(leaf_index).unwrap();
+
+            if let Some(parent) = node.get_parent() {
+                // The parent is condensed below, so it's fine to leave its MBR alone for now.
+                self.get_node_mut(parent).remove_child_unsafe(leaf_index);
+                dirty.insert(parent);
+            }
+
+            let region = node.get_region();
+            removed.push((region, node.into_data().unwrap()));
+        }
+
+        self.len -= removed.len();
+
+        self.condense(dirty);
+
+        removed
+    }
+
+    /// Condenses the tree after children have been removed from the nodes in `dirty`.
+    ///
+    /// Working upwards from the deepest affected node, any non-root node left with fewer
+    /// than `min_children` children is dissolved and its leaves are reinserted, while every
+    /// other affected node has its minimum bounding region shrunk to fit its remaining children.
+    pub(crate) fn condense(&mut self, dirty: HashSet<Index>) {
+        // Find every node whose MBR might be affected, along with its depth in the tree.
+        let mut depths: HashMap<Index, usize> = HashMap::new();
+
+        for index in dirty {
+            let mut path = Vec::new();
+            let mut current = Some(index);
+
+            while let Some(index) = current {
+                if depths.contains_key(&index) {
+                    break;
+                }
+
+                path.push(index);
+                current = self.get_node(index).get_parent();
+            }
+
+            // `current` is either the first ancestor we've already seen, or we reached the root.
+            let base_depth = current.map_or(0, |index| depths[&index] + 1);
+
+            for (offset, index) in path.into_iter().rev().enumerate() {
+                depths.insert(index, base_depth + offset);
+            }
+        }
+
+        let mut affected = depths.into_iter().collect::<Vec<_>>();
+        affected.sort_by(|(_, left), (_, right)| right.cmp(left));
+
+        let mut orphans = Vec::new();
+
+        for (index, _) in affected {
+            let node = self.get_node(index);
+
+            if index != self.root && node.child_count() < self.min_children {
+                let parent = node.get_parent().unwrap();
+
+                // The parent is shallower than this node, so it has yet to be condensed.
+                self.get_node_mut(parent).remove_child_unsafe(index);
+                self.dissolve(index, &mut orphans);
+            } else {
+                self.shrink_region(index);
+            }
+        }
+
+        // If the root has a single internal child, we can remove a level from the tree.
+        while self.root_node().child_count() == 1 {
+            let child_index = self.root_node().child_index_iter().next().unwrap();
+
+            if self.get_node(child_index).is_leaf() {
+                break;
+            }
+
+            self.nodes.remove(self.root);
+            self.get_node_mut(child_index).clear_parent();
+            self.root = child_index;
+        }
+
+        for leaf_index in orphans {
+            // Reattaching a leaf can't fail.
+            self.insert_leaf(leaf_index).unwrap();
+        }
+    }
+
+    /// Removes the node with index `index` and every internal node beneath it from the tree,
+    /// detaching all of its leaves and adding them to `orphans`.
+    fn dissolve(&mut self, index: Index, orphans: &mut Vec<Index>) {
+        let node = self.nodes.remove(index).unwrap();
+
+        for child_index in node.child_index_iter() {
+            if self.get_node(child_index).is_leaf() {
+                self.get_node_mut(child_index).clear_parent();
+                orphans.push(child_index);
+            } else {
+                self.dissolve(child_index, orphans);
+            }
+        }
+    }
+
+    /// Shrinks the minimum bounding region of the node with index `index` to fit its children.
+    fn shrink_region(&mut self, index: Index) {
+        let region = self
+            .child_iter(index)
+            .map(|(_, child_node)| child_node.get_region())
+            .reduce(combine_rects)
+            .unwrap_or_else(|| Rect::new(Coordinate::zero(), Coordinate::zero()));
+
+        // This is safe as `region` contains every child of this node, and is contained in the
+        // old region of this node (and so the region of its parent).
+        self.get_node_mut(index)
+            .set_minimum_bounding_region_unsafe(region);
+    }
+}
@@ -11,6 +11,7 @@ pub struct TreeRenderOptions {
     width: u32,
     height: u32,
     threshold: Option<usize>,
+    origin: (f64, f64),
 }
 
 impl TreeRenderOptions {
@@ -19,6 +20,7 @@ impl TreeRenderOptions {
             width,
             height,
             threshold: None,
+            origin: (0.0, 0.0),
         }
     }
 
@@ -34,6 +36,13 @@ impl TreeRenderOptions {
         self
     }
 
+    /// Draws the point `(x, y)` at the top left corner of the image, rather than `(0, 0)`.
+    pub fn with_origin(&mut self, x: f64, y: f64) -> &mut Self {
+        self.origin = (x, y);
+
+        self
+    }
+
     pub fn draw_tree<P: AsRef<Path>, ND, T: CoordFloat + HasKernel + Into<f64>>(
         &self,
         filename: P,
@@ -55,7 +64,7 @@ pub fn draw_tree<P: AsRef<Path>, ND, T>(
     let mut img = RgbImage::new(options.width, options.height);
     let mut dirty = false;
 
-    render_node(&mut img, &mut dirty, tree, index, 0, options.threshold);
+    render_node(&mut img, &mut dirty, tree, index, 0, options);
 
     // only render an image if theres actually something to render
     if dirty {
@@ -71,10 +80,12 @@ fn render_node<ND, T>(
     tree: &RTree<ND, T>,
     index: Index,
     level: usize,
-    threshold: Option<usize>,
+    options: &TreeRenderOptions,
 ) where
     T: CoordFloat + HasKernel + Into<f64>,
 {
+    let threshold = options.threshold;
+
     // If a threshold is set and we exceed it, stop rendering.
     if let Some(threshold) = threshold {
         if level > threshold {
@@ -84,14 +95,19 @@ fn render_node<ND, T>(
 
     // Render all children of this node
     for child_index in tree.get_node(index).child_index_iter() {
-        render_node(canvas, dirty, tree, child_index, level + 1, threshold);
+        render_node(canvas, dirty, tree, child_index, level + 1, options);
     }
 
     // If we don't have a threshold our we are at the given threshold, render
     // the MBR for this ode.
     if threshold.is_none() || threshold == Some(level) {
         *dirty = true;
-        draw_mbr(canvas, tree.get_node(index).get_region(), level);
+        draw_mbr(
+            canvas,
+            tree.get_node(index).get_region(),
+            level,
+            options.origin,
+        );
     }
 }
 
@@ -124,11 +140,12 @@ fn draw_mbr<C: Canvas<Pixel = Rgb<u8>>, T: CoordFloat + Into<f64>>(
     canvas: &mut C,
     mbr: Rect<T>,
     level: usize,
+    origin: (f64, f64),
 ) {
     let (x0, x1) = mbr.min().x_y();
-    let (x0, x1) = (x0.into(), x1.into());
+    let (x0, x1) = (x0.into() - origin.0, x1.into() - origin.1);
     let (y0, y1) = mbr.max().x_y();
-    let (y0, y1) = (y0.into(), y1.into());
+    let (y0, y1) = (y0.into() - origin.0, y1.into() - origin.1);
 
     draw_line(
         canvas,
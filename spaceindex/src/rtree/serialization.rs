@@ -0,0 +1,129 @@
+use geo::kernels::HasKernel;
+use geo_types::{CoordFloat, Rect};
+use serde::de::Error;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::rtree::{valid_node_capacity, RTree};
+
+/// The serialized form of an [`RTree`].  Only the configuration and entries of the tree are
+/// stored; the tree itself is rebuilt (using [`RTree::bulk_load`]'s packing) on deserialization,
+/// so its invariants never depend on the contents of the input.
+#[derive(Deserialize)]
+#[serde(rename = "RTree")]
+struct SerializedRTree<ND, T>
+where
+    T: CoordFloat,
+{
+    min_children: usize,
+    max_children: usize,
+    domain: Option<Rect<T>>,
+    entries: Vec<(Rect<T>, ND)>,
+}
+
+/// Serializes the entries of a tree as a sequence of pairs `(region, data)`.
+struct Entries<'a, ND, T>(&'a RTree<ND, T>)
+where
+    T: CoordFloat + HasKernel;
+
+impl<'a, ND, T> Serialize for Entries<'a, ND, T>
+where
+    ND: Serialize,
+    T: CoordFloat + HasKernel + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|(_, region, data)| (region, data)))
+    }
+}
+
+impl<ND, T> Serialize for RTree<ND, T>
+where
+    ND: Serialize,
+    T: CoordFloat + HasKernel + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RTree", 4)?;
+        state.serialize_field("min_children", &self.min_children)?;
+        state.serialize_field("max_children", &self.max_children)?;
+        state.serialize_field("domain", &self.domain)?;
+        state.serialize_field("entries", &Entries(self))?;
+        state.end()
+    }
+}
+
+impl<'de, ND, T> Deserialize<'de> for RTree<ND, T>
+where
+    ND: Deserialize<'de>,
+    T: CoordFloat + HasKernel + Deserialize<'de>,
+{
+    /// Deserializes and rebuilds a tree, rejecting any input which doesn't describe a valid tree.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::{Rect, RTree};
+    ///
+    /// let tree = (0..10)
+    ///     .map(|i| (Rect::new((i as f64, 0.0), (i as f64 + 1.0, 1.0)), i))
+    ///     .collect::<RTree<_, _>>();
+    ///
+    /// let json = serde_json::to_string(&tree).unwrap();
+    /// let restored: RTree<i32, f64> = serde_json::from_str(&json).unwrap();
+    ///
+    /// assert!(tree == restored);
+    /// # restored.validate_consistency();
+    /// ```
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = SerializedRTree::<ND, T>::deserialize(deserializer)?;
+
+        if !valid_node_capacity(serialized.min_children, serialized.max_children) {
+            return Err(D::Error::custom(format!(
+                "invalid node capacity: min_children = {}, max_children = {}",
+                serialized.min_children, serialized.max_children
+            )));
+        }
+
+        let domain = match serialized.domain {
+            Some(domain) => {
+                let domain = validate_region::<T, D::Error>(domain)?;
+
+                if domain.width() <= T::zero() || domain.height() <= T::zero() {
+                    return Err(D::Error::custom("periodic domain has zero width or height"));
+                }
+
+                Some(domain)
+            }
+            None => None,
+        };
+
+        let entries = serialized
+            .entries
+            .into_iter()
+            .map(|(region, data)| Ok((validate_region::<T, D::Error>(region)?, data)))
+            .collect::<Result<Vec<_>, D::Error>>()?;
+
+        let mut tree = Self {
+            min_children: serialized.min_children,
+            max_children: serialized.max_children,
+            domain,
+            ..Self::new()
+        };
+        tree.pack(entries).map_err(D::Error::custom)?;
+
+        Ok(tree)
+    }
+}
+
+/// Checks that every coordinate of `region` is finite, returning the region with its corners
+/// normalized so that `min` really is the minimum corner.
+fn validate_region<T: CoordFloat, E: Error>(region: Rect<T>) -> Result<Rect<T>, E> {
+    let (min, max) = (region.min(), region.max());
+
+    if [min.x, min.y, max.x, max.y]
+        .iter()
+        .any(|value| !value.is_finite())
+    {
+        return Err(E::custom("region has a non-finite coordinate"));
+    }
+
+    Ok(Rect::new(min, max))
+}
@@ -1,8 +1,15 @@
+use std::collections::HashMap;
 use test::Bencher;
 
+use geo::intersects::Intersects;
 use rand::Rng;
 
-use crate::rtree::RTree;
+use crate::rtree::mapped::MappedRTree;
+use crate::rtree::metric::{Chebyshev, Euclidean, Manhattan, Metric, SquaredEuclidean};
+use crate::rtree::moving::MovingRTree;
+use crate::rtree::paged::{self, PagedRTree};
+use crate::rtree::wal::WalRTreeMap;
+use crate::rtree::{Index, ItemId, RTree, RTreeError, RTreeMap};
 use crate::{point, Rect};
 
 #[bench]
@@ -39,3 +46,1812 @@ fn bench_large_tree_lookups(b: &mut Bencher) {
             .collect::<Vec<_>>()
     });
 }
+
+/// Generates a tree containing `count` small random regions.
+fn random_tree(count: usize) -> RTree<usize, f64> {
+    let mut rng = rand::thread_rng();
+    let mut tree = RTree::new();
+
+    for i in 0..count {
+        let xmin = rng.gen_range(0.0..=1_000.0);
+        let width = rng.gen_range(0.0..=20.0);
+        let ymin = rng.gen_range(0.0..=1_000.0);
+        let height = rng.gen_range(0.0..=20.0);
+
+        let rect = Rect::new((xmin, ymin), (xmin + width, ymin + height));
+        tree.insert(rect, i).unwrap();
+    }
+
+    tree
+}
+
+/// Checks that `nearest_neighbors` agrees with a brute force search using `metric`.
+fn check_nearest_neighbors<M: Metric<f64>>(tree: &RTree<usize, f64>, metric: M) {
+    let mut rng = rand::thread_rng();
+
+    for _ in 0..50 {
+        let query =
+            point! { x: rng.gen_range(-100.0..=1_100.0), y: rng.gen_range(-100.0..=1_100.0) };
+
+        let mut expected = tree
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.is_leaf())
+            .map(|(_, node)| metric.distance_to_rect(query, node.get_region()))
+            .collect::<Vec<_>>();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.truncate(10);
+
+        let actual = tree
+            .nearest_neighbors(query, 10, &metric)
+            .into_iter()
+            .map(|(_, distance)| distance)
+            .collect::<Vec<_>>();
+
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn test_nearest_neighbors_matches_brute_force() {
+    let tree = random_tree(2_000);
+    tree.validate_consistency();
+
+    check_nearest_neighbors(&tree, Euclidean);
+    check_nearest_neighbors(&tree, SquaredEuclidean);
+    check_nearest_neighbors(&tree, Manhattan);
+    check_nearest_neighbors(&tree, Chebyshev);
+}
+
+#[test]
+fn test_periodic_domain_matches_copied_entries() {
+    let mut rng = rand::thread_rng();
+    let domain = Rect::new((0.0, 0.0), (100.0, 50.0));
+
+    // A periodic tree, and a plain tree containing every periodic image of each entry.
+    let mut periodic_tree = RTree::with_periodic_domain(domain);
+    let mut copied_tree = RTree::new();
+
+    for i in 0..500 {
+        let xmin = rng.gen_range(0.0..100.0);
+        let ymin = rng.gen_range(0.0..50.0);
+        let width = rng.gen_range(0.0..=10.0);
+        let height = rng.gen_range(0.0..=10.0);
+
+        let rect = Rect::new((xmin, ymin), (xmin + width, ymin + height));
+        periodic_tree.insert(rect, i).unwrap();
+
+        for dx in [-100.0, 0.0, 100.0] {
+            for dy in [-50.0, 0.0, 50.0] {
+                let image = Rect::new(
+                    (xmin + dx, ymin + dy),
+                    (xmin + width + dx, ymin + height + dy),
+                );
+                copied_tree.insert(image, i).unwrap();
+            }
+        }
+    }
+
+    periodic_tree.validate_consistency();
+
+    let data = |tree: &RTree<usize, f64>, hits: Vec<ItemId>| {
+        let mut data = hits
+            .into_iter()
+            .map(|hit| *tree.get(hit).unwrap().1)
+            .collect::<Vec<_>>();
+        data.sort_unstable();
+        data.dedup();
+        data
+    };
+
+    for _ in 0..200 {
+        let x = rng.gen_range(0.0..100.0);
+        let y = rng.gen_range(0.0..50.0);
+        let query = Rect::new(
+            (x, y),
+            (x + rng.gen_range(0.0..=20.0), y + rng.gen_range(0.0..=20.0)),
+        );
+
+        assert_eq!(
+            data(&periodic_tree, periodic_tree.point_lookup((x, y))),
+            data(&copied_tree, copied_tree.point_lookup((x, y)))
+        );
+        assert_eq!(
+            data(
+                &periodic_tree,
+                periodic_tree.region_intersection_lookup(query)
+            ),
+            data(&copied_tree, copied_tree.region_intersection_lookup(query))
+        );
+
+        let nearest = periodic_tree.nearest_neighbors((x, y), 1, Euclidean);
+        let expected = copied_tree.nearest_neighbors((x, y), 1, Euclidean);
+        assert!((nearest[0].1 - expected[0].1).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_moving_tree_matches_brute_force() {
+    let mut rng = rand::thread_rng();
+    let mut tree = MovingRTree::new(0.0, 10.0);
+    let mut objects = Vec::new();
+    let mut ids = Vec::new();
+
+    for i in 0..500 {
+        let x = rng.gen_range(0.0..=100.0);
+        let y = rng.gen_range(0.0..=100.0);
+        let region = Rect::new(
+            (x, y),
+            (x + rng.gen_range(0.0..=2.0), y + rng.gen_range(0.0..=2.0)),
+        );
+        let velocity = (rng.gen_range(-5.0..=5.0), rng.gen_range(-5.0..=5.0));
+        let time = rng.gen_range(0.0..=10.0);
+
+        ids.push(tree.insert(region, velocity, time, i).unwrap());
+        objects.push((region, velocity, time));
+    }
+    tree.validate_consistency();
+
+    let expected = |query: Rect<f64>, time: f64| {
+        let mut hits = objects
+            .iter()
+            .enumerate()
+            .filter(|(_, (region, (vx, vy), t))| {
+                let (dx, dy) = (vx * (time - t), vy * (time - t));
+                let moved = Rect::new(
+                    (region.min().x + dx, region.min().y + dy),
+                    (region.max().x + dx, region.max().y + dy),
+                );
+                moved.intersects(&query)
+            })
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        hits.sort_unstable();
+        hits
+    };
+
+    for rebase in [None, Some(5.0)] {
+        if let Some(reference_time) = rebase {
+            tree.rebase(reference_time);
+            tree.validate_consistency();
+            assert!(tree
+                .intersecting_at(Rect::new((0.0, 0.0), (1.0, 1.0)), 1.0)
+                .is_err());
+
+            // Rebasing leaves the tree's structure alone, so ids stay valid.
+            for (i, &id) in ids.iter().enumerate() {
+                assert_eq!(tree.get(id).map(|object| *object.get_data()), Some(i));
+            }
+        }
+
+        // Query well past the horizon too, where node bounds have grown but must still
+        // contain every object beneath them.
+        for _ in 0..200 {
+            let x = rng.gen_range(-200.0..=300.0);
+            let y = rng.gen_range(-200.0..=300.0);
+            let query = Rect::new((x, y), (x + 10.0, y + 10.0));
+            let time = tree.reference_time() + rng.gen_range(0.0..=50.0);
+
+            let mut actual = tree
+                .intersecting_at(query, time)
+                .unwrap()
+                .into_iter()
+                .map(|index| *tree.get(index).unwrap().get_data())
+                .collect::<Vec<_>>();
+            actual.sort_unstable();
+
+            assert_eq!(actual, expected(query, time));
+        }
+    }
+}
+
+#[test]
+fn test_moving_tree_remove_and_update() {
+    let mut rng = rand::thread_rng();
+    let mut tree = MovingRTree::new(0.0, 10.0);
+
+    let mut random_motion = || {
+        let x = rng.gen_range(0.0..=100.0);
+        let y = rng.gen_range(0.0..=100.0);
+        let region = Rect::new((x, y), (x + 1.0, y + 1.0));
+        let velocity = (rng.gen_range(-5.0..=5.0), rng.gen_range(-5.0..=5.0));
+
+        (region, velocity, rng.gen_range(0.0..=10.0))
+    };
+
+    let mut objects = Vec::new();
+    for i in 0..300 {
+        let (region, velocity, time) = random_motion();
+        let id = tree.insert(region, velocity, time, i).unwrap();
+        objects.push(Some((id, region, velocity, time)));
+    }
+
+    // Remove every third object, and change the motion of every other object
+    for (i, object) in objects.iter_mut().enumerate() {
+        let (id, ..) = object.unwrap();
+
+        if i % 3 == 0 {
+            assert_eq!(tree.remove(id).map(|object| *object.get_data()), Some(i));
+            assert!(tree.remove(id).is_none());
+            *object = None;
+        } else if i % 2 == 0 {
+            let (region, velocity, time) = random_motion();
+            tree.update(id, region, velocity, time).unwrap();
+            *object = Some((id, region, velocity, time));
+        }
+    }
+
+    assert_eq!(tree.len(), 200);
+    tree.validate_consistency();
+
+    for _ in 0..100 {
+        let (query, _, time) = random_motion();
+        let (x, y) = query.min().x_y();
+        let query = Rect::new((x, y), (x + 10.0, y + 10.0));
+
+        let mut expected = objects
+            .iter()
+            .flatten()
+            .filter(|(_, region, (vx, vy), t)| {
+                let (dx, dy) = (vx * (time - t), vy * (time - t));
+                let moved = Rect::new(
+                    (region.min().x + dx, region.min().y + dy),
+                    (region.max().x + dx, region.max().y + dy),
+                );
+                moved.intersects(&query)
+            })
+            .map(|(id, ..)| *id)
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+
+        let mut actual = tree.intersecting_at(query, time).unwrap();
+        actual.sort_unstable();
+
+        assert_eq!(actual, expected);
+    }
+}
+
+/// Checks that every node other than the root has at least `min_children` children, and that
+/// every leaf is at the same depth.
+fn check_fill_and_balance<ND>(tree: &RTree<ND, f64>) {
+    for (index, node) in tree.nodes.iter() {
+        if index != tree.root && !node.is_leaf() {
+            assert!(node.child_count() >= tree.min_children);
+        }
+    }
+
+    let depth = |mut index: Index| {
+        let mut depth = 0;
+        while let Some(parent) = tree.get_node(index).get_parent() {
+            index = parent;
+            depth += 1;
+        }
+        depth
+    };
+
+    let mut leaf_depths = tree
+        .nodes
+        .iter()
+        .filter(|(_, node)| node.is_leaf())
+        .map(|(index, _)| depth(index));
+
+    if let Some(first) = leaf_depths.next() {
+        assert!(leaf_depths.all(|depth| depth == first));
+    }
+}
+
+#[test]
+fn test_remove_in_region_and_retain() {
+    let mut tree = random_tree(3_000);
+
+    let regions = tree
+        .nodes
+        .iter()
+        .filter_map(|(_, node)| node.get_data().map(|data| (node.get_region(), *data)))
+        .collect::<Vec<_>>();
+
+    // Clear out a tile
+    let tile = Rect::new((250.0, 250.0), (500.0, 500.0));
+    let mut removed = tree
+        .remove_in_region(tile)
+        .into_iter()
+        .map(|(_, data)| data)
+        .collect::<Vec<_>>();
+    removed.sort_unstable();
+
+    let mut expected = regions
+        .iter()
+        .filter(|(region, _)| region.intersects(&tile))
+        .map(|(_, data)| *data)
+        .collect::<Vec<_>>();
+    expected.sort_unstable();
+
+    assert_eq!(removed, expected);
+    assert!(tree.region_intersection_lookup(tile).is_empty());
+    tree.validate_consistency();
+    check_fill_and_balance(&tree);
+
+    // Now only keep every third element
+    tree.retain(|_, data| data % 3 == 0);
+
+    let mut remaining = tree
+        .nodes
+        .iter()
+        .filter_map(|(_, node)| node.get_data().copied())
+        .collect::<Vec<_>>();
+    remaining.sort_unstable();
+
+    let mut expected = regions
+        .iter()
+        .filter(|(region, data)| !region.intersects(&tile) && data % 3 == 0)
+        .map(|(_, data)| *data)
+        .collect::<Vec<_>>();
+    expected.sort_unstable();
+
+    assert_eq!(remaining, expected);
+    tree.validate_consistency();
+    check_fill_and_balance(&tree);
+
+    // Removing everything leaves us with an empty tree we can keep using
+    tree.retain(|_, _| false);
+    assert_eq!(tree.nodes.len(), 1);
+    tree.validate_consistency();
+
+    tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 0).unwrap();
+    assert_eq!(tree.point_lookup((0.5, 0.5)).len(), 1);
+}
+
+#[test]
+fn test_iterate_clear_and_drain() {
+    let mut tree = random_tree(1_000);
+    assert_eq!(tree.len(), 1_000);
+
+    let mut data = tree.iter().map(|(_, _, data)| *data).collect::<Vec<_>>();
+    data.sort_unstable();
+    assert_eq!(data, (0..1_000).collect::<Vec<_>>());
+
+    // Every id yielded by `iter` refers to its own element
+    for (id, region, data) in tree.iter() {
+        assert_eq!(tree.get(id), Some((region, data)));
+    }
+
+    // Changing data in place leaves the regions where they were
+    let regions = tree
+        .iter()
+        .map(|(id, region, _)| (id, *region))
+        .collect::<HashMap<_, _>>();
+    for (_, _, data) in tree.iter_mut() {
+        *data *= 2;
+    }
+    for (id, region, data) in tree.iter() {
+        assert_eq!(data % 2, 0);
+        assert_eq!(regions[&id], *region);
+        assert!(tree.point_lookup(region.center()).contains(&id));
+    }
+    tree.validate_consistency();
+
+    // `len` follows inserts and removes
+    let removed = tree
+        .iter()
+        .map(|(id, _, _)| id)
+        .take(100)
+        .collect::<Vec<_>>();
+    for id in removed {
+        tree.remove(id).unwrap();
+    }
+    assert_eq!(tree.len(), 900);
+    tree.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 1).unwrap();
+    assert_eq!(tree.len(), 901);
+    assert_eq!(tree.iter().count(), 901);
+
+    let mut drained = tree.drain().map(|(_, data)| data).collect::<Vec<_>>();
+    assert_eq!(drained.len(), 901);
+    drained.sort_unstable();
+    drained.dedup();
+    assert_eq!(drained.len(), 901);
+
+    // A drained tree is empty and consistent, and can be used again
+    assert_eq!(tree.len(), 0);
+    assert!(tree.is_empty());
+    assert_eq!(tree.iter().count(), 0);
+    assert_eq!(tree.nodes.len(), 1);
+    tree.validate_consistency();
+
+    for i in 0..100 {
+        let x = i as f64;
+        tree.insert(Rect::new((x, 0.0), (x + 1.0, 1.0)), i).unwrap();
+    }
+    assert_eq!(tree.len(), 100);
+    assert_eq!(tree.point_lookup((10.5, 0.5)).len(), 1);
+    tree.validate_consistency();
+    check_fill_and_balance(&tree);
+
+    tree.clear();
+    assert_eq!(tree.len(), 0);
+    assert!(tree.point_lookup((10.5, 0.5)).is_empty());
+    tree.validate_consistency();
+}
+
+#[test]
+fn test_rtree_map_tracks_keys() {
+    let mut rng = rand::thread_rng();
+    let mut map = RTreeMap::new();
+    let mut expected = HashMap::new();
+
+    let random_rect = |rng: &mut rand::rngs::ThreadRng| {
+        let x = rng.gen_range(0.0..=1_000.0);
+        let y = rng.gen_range(0.0..=1_000.0);
+        Rect::new((x, y), (x + 5.0, y + 5.0))
+    };
+
+    for key in 0..1_000 {
+        let rect = random_rect(&mut rng);
+        map.insert(key.to_string(), rect, key).unwrap();
+        expected.insert(key.to_string(), (rect, key));
+    }
+
+    // Replace some elements, move some others and remove the rest.
+    for key in 0..1_000 {
+        let rect = random_rect(&mut rng);
+        let key = key.to_string();
+
+        match rng.gen_range(0..4) {
+            0 => {
+                assert_eq!(
+                    map.insert(key.clone(), rect, 0).unwrap(),
+                    expected.insert(key, (rect, 0))
+                );
+            }
+            1 => {
+                map.update_region(key.as_str(), rect).unwrap();
+                expected.get_mut(&key).unwrap().0 = rect;
+            }
+            2 => {
+                assert_eq!(map.remove(key.as_str()), expected.remove(&key));
+                assert!(map.update_region(key.as_str(), rect).is_err());
+            }
+            _ => {}
+        }
+    }
+
+    map.tree().validate_consistency();
+    check_fill_and_balance(map.tree());
+    assert_eq!(map.len(), expected.len());
+
+    for (key, (rect, data)) in expected.iter() {
+        assert_eq!(map.get(key.as_str()), Some((rect, data)));
+        assert!(map.point_lookup(rect.center()).contains(&key));
+    }
+}
+
+#[test]
+fn test_rtree_map_bulk_load() {
+    let entries = (0..1_000).map(|key| {
+        let x = (key % 100) as f64;
+        let y = (key / 100) as f64;
+        (key % 800, Rect::new((x, y), (x + 0.5, y + 0.5)), key)
+    });
+
+    let mut map = RTreeMap::bulk_load(entries);
+    map.tree().validate_consistency();
+
+    // Only the last element with each key is kept
+    assert_eq!(map.len(), 800);
+    assert_eq!(map.tree().len(), 800);
+    assert_eq!(map.get(&5).unwrap().1, &805);
+    assert_eq!(map.get(&205).unwrap().1, &205);
+    assert!(map.point_lookup((5.25, 0.25)).is_empty());
+    assert_eq!(map.point_lookup((5.25, 8.25)), vec![&5]);
+
+    // Keys can be removed and moved as with a map built by insertion
+    assert!(map.remove(&5).is_some());
+    map.update_region(&6, Rect::new((-5.0, -5.0), (-4.0, -4.0)))
+        .unwrap();
+    assert_eq!(map.point_lookup((-4.5, -4.5)), vec![&6]);
+    map.tree().validate_consistency();
+}
+
+#[test]
+fn test_bulk_load_matches_repeated_insertion() {
+    let inserted = random_tree(5_000);
+
+    let mut entries = inserted
+        .iter()
+        .map(|(_, region, data)| (*region, *data))
+        .collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|(_, data)| *data);
+
+    let packed = entries.iter().copied().collect::<RTree<_, _>>();
+    packed.validate_consistency();
+    check_fill_and_balance(&packed);
+    assert_eq!(packed.len(), entries.len());
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..200 {
+        let x = rng.gen_range(0.0..=1_000.0);
+        let y = rng.gen_range(0.0..=1_000.0);
+        let query = Rect::new((x, y), (x + 50.0, y + 50.0));
+
+        let hits = |tree: &RTree<usize, f64>| {
+            let mut hits = tree
+                .region_intersection_lookup(query)
+                .into_iter()
+                .map(|id| *tree.get(id).unwrap().1)
+                .collect::<Vec<_>>();
+            hits.sort_unstable();
+            hits
+        };
+
+        assert_eq!(hits(&packed), hits(&inserted));
+    }
+
+    // Extending a packed tree keeps it balanced, and consuming it gives back every entry.
+    let mut tree = packed;
+    tree.extend(
+        entries
+            .iter()
+            .map(|(region, data)| (*region, data + entries.len())),
+    );
+    tree.validate_consistency();
+    check_fill_and_balance(&tree);
+
+    let mut drained = tree.into_iter().collect::<Vec<_>>();
+    drained.sort_unstable_by_key(|(_, data)| *data);
+    entries.extend(
+        entries
+            .clone()
+            .into_iter()
+            .map(|(region, data)| (region, data + 5_000)),
+    );
+
+    assert_eq!(drained, entries);
+}
+
+#[test]
+fn test_bulk_load_small_trees() {
+    for count in 0..100 {
+        let tree = (0..count)
+            .map(|i| {
+                let x = i as f64;
+                (Rect::new((x, x), (x + 1.0, x + 1.0)), i)
+            })
+            .collect::<RTree<_, _>>();
+
+        tree.validate_consistency();
+        check_fill_and_balance(&tree);
+        assert_eq!(tree.len(), count);
+        assert_eq!(tree.point_lookup((0.5, 0.5)).len(), usize::from(count > 0));
+    }
+}
+
+#[test]
+fn test_content_and_structural_equality() {
+    let tree = random_tree(2_000);
+
+    let fork = tree.clone();
+    fork.validate_consistency();
+    assert!(tree.structurally_eq(&fork));
+    assert!(tree == fork);
+
+    // A packed tree holds the same entries laid out differently.
+    let packed = tree
+        .iter()
+        .map(|(_, region, data)| (*region, *data))
+        .collect::<RTree<_, _>>();
+    assert!(tree == packed);
+    assert!(!tree.structurally_eq(&packed));
+
+    // Changing the data of a single entry breaks both kinds of equality.
+    let mut fork = tree.clone();
+    let (id, _, _) = fork.iter().next().unwrap();
+    *fork.get_mut(id).unwrap() += 1;
+    assert!(tree != fork);
+    assert!(!tree.structurally_eq(&fork));
+
+    // Entries sharing a region are matched up regardless of order.
+    let region = Rect::new((0.0, 0.0), (1.0, 1.0));
+    let left = vec![(region, 1), (region, 2), (region, 2)]
+        .into_iter()
+        .collect::<RTree<_, f64>>();
+    let right = vec![(region, 2), (region, 1), (region, 2)]
+        .into_iter()
+        .collect::<RTree<_, f64>>();
+    let other = vec![(region, 1), (region, 1), (region, 2)]
+        .into_iter()
+        .collect::<RTree<_, f64>>();
+    assert!(left == right);
+    assert!(left != other);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip_and_validation() {
+    let tree = random_tree(2_000);
+    let json = serde_json::to_string(&tree).unwrap();
+    let restored: RTree<usize, f64> = serde_json::from_str(&json).unwrap();
+
+    restored.validate_consistency();
+    check_fill_and_balance(&restored);
+    assert!(tree == restored);
+
+    // The periodic domain survives the round trip.
+    let mut tree = RTree::with_periodic_domain(Rect::new((0.0, 0.0), (10.0, 10.0)));
+    tree.insert(Rect::new((9.0, 4.0), (11.0, 6.0)), 'a')
+        .unwrap();
+    let json = serde_json::to_string(&tree).unwrap();
+    let restored: RTree<char, f64> = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.periodic_domain(), tree.periodic_domain());
+    assert_eq!(restored.point_lookup((0.5, 5.0)).len(), 1);
+
+    // Hand-edited input describing an invalid tree is rejected.
+    let parse = |domain: &str, entries: &str| {
+        let json = format!(
+            r#"{{"min_children":2,"max_children":8,"domain":{},"entries":{}}}"#,
+            domain, entries
+        );
+        serde_json::from_str::<RTree<u8, f64>>(&json)
+    };
+    let rect = |min_x: &str, max_x: &str| {
+        format!(
+            r#"{{"min":{{"x":{},"y":0.0}},"max":{{"x":{},"y":1.0}}}}"#,
+            min_x, max_x
+        )
+    };
+
+    assert!(parse("null", &format!("[[{}, 1]]", rect("0.0", "1.0"))).is_ok());
+    assert!(parse("null", &format!("[[{}, 1]]", rect("0.0", "1e999"))).is_err());
+    assert!(parse(&rect("0.0", "0.0"), "[]").is_err());
+    assert!(parse(
+        &rect("0.0", "1.0"),
+        &format!("[[{}, 1]]", rect("0.0", "5.0"))
+    )
+    .is_err());
+    assert!(serde_json::from_str::<RTree<u8, f64>>(
+        r#"{"min_children":5,"max_children":8,"domain":null,"entries":[]}"#
+    )
+    .is_err());
+    assert!(serde_json::from_str::<RTree<u8, f64>>(
+        r#"{"min_children":1,"max_children":8,"domain":null,"entries":[]}"#
+    )
+    .is_err());
+
+    // Swapped corners are normalized rather than producing a corrupt tree.
+    let tree = parse("null", &format!("[[{}, 1]]", rect("1.0", "0.0"))).unwrap();
+    assert_eq!(tree.point_lookup((0.5, 0.5)).len(), 1);
+    tree.validate_consistency();
+}
+
+#[test]
+fn test_binary_round_trip() {
+    let tree = random_tree(3_000)
+        .into_iter()
+        .map(|(region, data)| (region, data as u64))
+        .collect::<RTree<_, _>>();
+
+    let mut bytes = Vec::new();
+    tree.write_to(&mut bytes).unwrap();
+
+    let restored = RTree::<u64, f64>::read_from(bytes.as_slice()).unwrap();
+    restored.validate_consistency();
+    assert!(restored.structurally_eq(&tree));
+
+    // The periodic domain survives the round trip, and so do empty trees.
+    let mut tree = RTree::with_periodic_domain(Rect::new((0.0, 0.0), (10.0, 10.0)));
+    let mut bytes = Vec::new();
+    tree.write_to(&mut bytes).unwrap();
+    assert!(RTree::<String, f64>::read_from(bytes.as_slice())
+        .unwrap()
+        .structurally_eq(&tree));
+
+    tree.insert(Rect::new((9.0, 4.0), (11.0, 6.0)), String::from("wrapped"))
+        .unwrap();
+    let mut bytes = Vec::new();
+    tree.write_to(&mut bytes).unwrap();
+
+    let restored = RTree::<String, f64>::read_from(bytes.as_slice()).unwrap();
+    assert!(restored.structurally_eq(&tree));
+    assert_eq!(restored.point_lookup((0.5, 5.0)).len(), 1);
+}
+
+#[test]
+fn test_binary_rejects_damaged_input() {
+    let tree = (0..50)
+        .map(|i| {
+            let x = i as f64;
+            (Rect::new((x, x), (x + 1.0, x + 1.0)), i as u32)
+        })
+        .collect::<RTree<_, f64>>();
+
+    let mut bytes = Vec::new();
+    tree.write_to(&mut bytes).unwrap();
+
+    // Every proper prefix of the file is truncated.
+    for length in 0..bytes.len() {
+        assert!(matches!(
+            RTree::<u32, f64>::read_from(&bytes[..length]),
+            Err(RTreeError::Truncated)
+        ));
+    }
+
+    // Flipping any bit is detected.
+    for offset in 0..bytes.len() {
+        let mut damaged = bytes.clone();
+        damaged[offset] ^= 0x10;
+
+        match RTree::<u32, f64>::read_from(damaged.as_slice()) {
+            Err(RTreeError::BadMagic) => assert!(offset < 8),
+            Err(RTreeError::ChecksumMismatch(_)) | Err(RTreeError::Truncated) => {}
+            other => panic!("damage at offset {} went undetected: {:?}", offset, other),
+        }
+    }
+
+    // Damaged version bytes are reported as damage, rather than as a different version.
+    let mut damaged = bytes.clone();
+    damaged[8..10].copy_from_slice(&7u16.to_le_bytes());
+    assert!(matches!(
+        RTree::<u32, f64>::read_from(damaged.as_slice()),
+        Err(RTreeError::ChecksumMismatch("header"))
+    ));
+
+    // Only an intact header from another version is unsupported.
+    let checksum = crc32fast::hash(&damaged[..60]);
+    damaged[60..64].copy_from_slice(&checksum.to_le_bytes());
+    assert!(matches!(
+        RTree::<u32, f64>::read_from(damaged.as_slice()),
+        Err(RTreeError::UnsupportedVersion(7))
+    ));
+
+    // A node escaping its parent is caught even when the checksums are intact.
+    let mut damaged = bytes.clone();
+    let section_length = u64::from_le_bytes(damaged[64..72].try_into().unwrap()) as usize;
+    let section = 72..72 + section_length;
+    let child = section.start + 1 + 32 + 4;
+    damaged[child + 1..child + 9].copy_from_slice(&(-100.0f64).to_le_bytes());
+    let checksum = crc32fast::hash(&damaged[section.clone()]);
+    damaged[section.end..section.end + 4].copy_from_slice(&checksum.to_le_bytes());
+    assert!(matches!(
+        RTree::<u32, f64>::read_from(damaged.as_slice()),
+        Err(RTreeError::Corrupt(_))
+    ));
+
+    // Payloads which fail to decode are reported as such.
+    assert!(matches!(
+        RTree::<u64, f64>::read_from(bytes.as_slice()),
+        Err(RTreeError::Decode(_))
+    ));
+}
+
+#[test]
+fn test_mapped_tree_matches_tree() {
+    let tree = random_tree(5_000)
+        .into_iter()
+        .map(|(region, data)| (region, data as u64))
+        .collect::<RTree<_, _>>();
+
+    let mut bytes = Vec::new();
+    tree.write_mapped(&mut bytes).unwrap();
+    let mapped = MappedRTree::new(bytes.as_slice()).unwrap();
+    assert_eq!(mapped.len(), tree.len());
+
+    // Maps each mapped hit to the data of its entry, checking its region on the way.
+    let data = |ordinals: Vec<usize>| {
+        let mut data = ordinals
+            .into_iter()
+            .map(|ordinal| {
+                let (region, data) = mapped.get::<u64>(ordinal).unwrap();
+                assert_eq!(mapped.region(ordinal), Some(region));
+                data
+            })
+            .collect::<Vec<_>>();
+        data.sort_unstable();
+        data
+    };
+    let tree_data = |ids: Vec<ItemId>| {
+        let mut data = ids
+            .into_iter()
+            .map(|id| *tree.get(id).unwrap().1)
+            .collect::<Vec<_>>();
+        data.sort_unstable();
+        data
+    };
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..200 {
+        let x = rng.gen_range(0.0..=1_000.0);
+        let y = rng.gen_range(0.0..=1_000.0);
+        let query = Rect::new((x, y), (x + 30.0, y + 30.0));
+
+        assert_eq!(
+            data(mapped.point_lookup((x, y))),
+            tree_data(tree.point_lookup((x, y)))
+        );
+        assert_eq!(
+            data(mapped.region_intersection_lookup(query)),
+            tree_data(tree.region_intersection_lookup(query))
+        );
+
+        let distances =
+            |hits: Vec<(usize, f64)>| hits.into_iter().map(|(_, d)| d).collect::<Vec<_>>();
+        let expected = tree
+            .nearest_neighbors((x, y), 10, Euclidean)
+            .into_iter()
+            .map(|(_, d)| d)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            distances(mapped.nearest_neighbors((x, y), 10, Euclidean)),
+            expected
+        );
+    }
+
+    assert!(mapped.get::<u64>(mapped.len()).is_err());
+    assert!(mapped.payload(mapped.len()).is_none());
+
+    // Empty trees work too.
+    let mut bytes = Vec::new();
+    RTree::<u64, f64>::new().write_mapped(&mut bytes).unwrap();
+    let mapped = MappedRTree::new(bytes).unwrap();
+    assert!(mapped.is_empty());
+    assert!(mapped.point_lookup((0.0, 0.0)).is_empty());
+}
+
+#[test]
+fn test_mapped_tree_rejects_damaged_input() {
+    let tree = (0..200)
+        .map(|i| {
+            let x = i as f64;
+            (Rect::new((x, x), (x + 1.0, x + 1.0)), i as u32)
+        })
+        .collect::<RTree<_, f64>>();
+
+    let mut bytes = Vec::new();
+    tree.write_mapped(&mut bytes).unwrap();
+
+    for length in 0..bytes.len() {
+        assert!(MappedRTree::new(&bytes[..length]).is_err());
+    }
+
+    // Damage anywhere must either be rejected up front, or leave queries safe to run.
+    for offset in 0..bytes.len() {
+        let mut damaged = bytes.clone();
+        damaged[offset] ^= 0x01;
+
+        if let Ok(mapped) = MappedRTree::new(damaged.as_slice()) {
+            let _ = mapped.validate();
+            mapped.region_intersection_lookup(Rect::new((0.0, 0.0), (500.0, 500.0)));
+            mapped.nearest_neighbors((100.0, 100.0), 20, Euclidean);
+            for ordinal in 0..mapped.len() {
+                let _ = mapped.get::<u32>(ordinal);
+            }
+        }
+    }
+
+    // Damage to node records is only found by `validate`, while queries stay safe.  Here the
+    // root claims to be its own first child, and the first leaf's payload runs off the end.
+    let node_count = u64::from_le_bytes(bytes[16..24].try_into().unwrap()) as usize;
+    let offsets_start = 64 + node_count * 48;
+    let mut damaged = bytes.clone();
+    damaged[64 + 32..64 + 40].copy_from_slice(&0u64.to_le_bytes());
+    damaged[offsets_start + 8..offsets_start + 16].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    let mapped = MappedRTree::new(damaged.as_slice()).unwrap();
+    assert!(matches!(mapped.validate(), Err(RTreeError::Corrupt(_))));
+    assert!(mapped
+        .region_intersection_lookup(Rect::new((0.0, 0.0), (500.0, 500.0)))
+        .is_empty());
+    assert!(mapped
+        .nearest_neighbors((100.0, 100.0), 20, Euclidean)
+        .is_empty());
+    assert!(mapped.payload(0).is_none());
+    assert!(matches!(mapped.get::<u32>(0), Err(RTreeError::Corrupt(_))));
+    assert!(MappedRTree::new(bytes.as_slice())
+        .unwrap()
+        .validate()
+        .is_ok());
+
+    // Trees with a periodic domain can't be mapped.
+    let tree = RTree::<u32, f64>::with_periodic_domain(Rect::new((0.0, 0.0), (1.0, 1.0)));
+    assert!(tree.write_mapped(Vec::new()).is_err());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_mapped_tree_open() {
+    let tree = random_tree(1_000)
+        .into_iter()
+        .map(|(region, data)| (region, data as u64))
+        .collect::<RTree<_, _>>();
+
+    let path = std::env::temp_dir().join(format!("spaceindex-mapped-{}.idx", std::process::id()));
+    tree.write_mapped(std::fs::File::create(&path).unwrap())
+        .unwrap();
+
+    let mapped = MappedRTree::open(&path).unwrap();
+    assert_eq!(mapped.len(), tree.len());
+    assert_eq!(
+        mapped.nearest_neighbors((500.0, 500.0), 1, Euclidean)[0].1,
+        tree.nearest_neighbors((500.0, 500.0), 1, Euclidean)[0].1
+    );
+
+    drop(mapped);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_paged_tree_matches_tree() {
+    let path = std::env::temp_dir().join(format!("spaceindex-paged-{}.idx", std::process::id()));
+
+    let mut tree = RTree::new();
+    // A tiny pool forces pages to be evicted and read back throughout.
+    let mut paged = PagedRTree::create(&path, 4).unwrap();
+
+    let mut rng = rand::thread_rng();
+    for i in 0..3_000u64 {
+        let x = rng.gen_range(0.0..=1_000.0);
+        let y = rng.gen_range(0.0..=1_000.0);
+        let region = Rect::new((x, y), (x + rng.gen_range(0.0..=10.0), y + 5.0));
+
+        tree.insert(region, i).unwrap();
+        paged.insert(region, i).unwrap();
+        assert!(paged.cached_pages() <= 4);
+    }
+    assert_eq!(paged.len(), 3_000);
+
+    let sorted = |mut hits: Vec<u64>| {
+        hits.sort_unstable();
+        hits
+    };
+    let paged_data =
+        |hits: Vec<(Rect<f64>, u64)>| sorted(hits.into_iter().map(|(_, d)| d).collect());
+    let tree_data = |tree: &RTree<u64, f64>, ids: Vec<ItemId>| {
+        sorted(ids.into_iter().map(|id| *tree.get(id).unwrap().1).collect())
+    };
+
+    let queries = (0..100)
+        .map(|_| {
+            let x = rng.gen_range(0.0..=1_000.0);
+            let y = rng.gen_range(0.0..=1_000.0);
+            (x, y)
+        })
+        .collect::<Vec<_>>();
+
+    let check = |paged: &mut PagedRTree<u64>| {
+        for &(x, y) in &queries {
+            let query = Rect::new((x, y), (x + 30.0, y + 30.0));
+            let small = Rect::new((x, y), (x + 0.5, y + 0.5));
+
+            assert_eq!(
+                paged_data(paged.point_lookup((x, y)).unwrap()),
+                tree_data(&tree, tree.point_lookup((x, y)))
+            );
+            assert_eq!(
+                paged_data(paged.region_intersection_lookup(query).unwrap()),
+                tree_data(&tree, tree.region_intersection_lookup(query))
+            );
+            assert_eq!(
+                paged_data(paged.region_lookup(small).unwrap()),
+                tree_data(&tree, tree.region_lookup(small))
+            );
+        }
+    };
+    check(&mut paged);
+
+    // Everything survives being written out and read back with a different pool size.
+    paged.sync().unwrap();
+    drop(paged);
+    let mut paged = PagedRTree::<u64>::open(&path, 32).unwrap();
+    assert_eq!(paged.len(), 3_000);
+    check(&mut paged);
+
+    // Dropping the tree flushes it too.
+    paged
+        .insert(Rect::new((-5.0, -5.0), (-4.0, -4.0)), 3_000)
+        .unwrap();
+    drop(paged);
+    let mut paged = PagedRTree::<u64>::open(&path, 1).unwrap();
+    assert_eq!(paged.len(), 3_001);
+    assert_eq!(
+        paged.point_lookup((-4.5, -4.5)).unwrap(),
+        vec![(Rect::new((-5.0, -5.0), (-4.0, -4.0)), 3_000)]
+    );
+
+    drop(paged);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_paged_tree_rejects_bad_input() {
+    let path =
+        std::env::temp_dir().join(format!("spaceindex-paged-bad-{}.idx", std::process::id()));
+
+    let mut paged = PagedRTree::<Vec<u8>>::create(&path, 2).unwrap();
+    let region = Rect::new((0.0, 0.0), (1.0, 1.0));
+    assert!(paged
+        .insert(region, vec![0; paged::MAX_PAYLOAD_LENGTH])
+        .is_ok());
+    assert!(matches!(
+        paged.insert(region, vec![0; paged::MAX_PAYLOAD_LENGTH + 1]),
+        Err(RTreeError::PayloadTooLarge)
+    ));
+    assert_eq!(paged.len(), 1);
+
+    // Pages full of the largest payloads still fit once split.
+    for _ in 0..20 {
+        paged
+            .insert(region, vec![1; paged::MAX_PAYLOAD_LENGTH])
+            .unwrap();
+    }
+    assert_eq!(paged.point_lookup((0.5, 0.5)).unwrap().len(), 21);
+    drop(paged);
+
+    // Files which aren't paged trees are rejected.
+    std::fs::write(&path, b"not a tree").unwrap();
+    assert!(matches!(
+        PagedRTree::<Vec<u8>>::open(&path, 2),
+        Err(RTreeError::BadMagic)
+    ));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_paged_tree_remove_and_nearest_with_small_pool() {
+    let path =
+        std::env::temp_dir().join(format!("spaceindex-paged-small-{}.idx", std::process::id()));
+
+    let mut tree = RTree::new();
+    let mut ids = HashMap::new();
+    // With two pages in memory, splits of the root evict the pages being split.
+    let mut paged = PagedRTree::create(&path, 2).unwrap();
+
+    let mut rng = rand::thread_rng();
+    for i in 0..2_000u64 {
+        let x = rng.gen_range(0.0..=1_000.0);
+        let y = rng.gen_range(0.0..=1_000.0);
+        let region = Rect::new((x, y), (x + rng.gen_range(0.0..=10.0), y + 5.0));
+
+        ids.insert(i, tree.insert(region, i).unwrap());
+        paged.insert(region, i).unwrap();
+        assert!(paged.cached_pages() <= 2);
+    }
+    paged.check_consistency().unwrap();
+
+    // Remove a few entries one at a time, and a few regions at a time.
+    for i in (0..2_000u64).step_by(7) {
+        let (region, _) = tree.remove(ids[&i]).unwrap();
+        assert!(paged.remove(region, &i).unwrap());
+        assert!(!paged.remove(region, &i).unwrap());
+    }
+    for _ in 0..20 {
+        let x = rng.gen_range(0.0..=1_000.0);
+        let y = rng.gen_range(0.0..=1_000.0);
+        let query = Rect::new((x, y), (x + 50.0, y + 50.0));
+
+        let mut expected = tree
+            .remove_in_region(query)
+            .into_iter()
+            .map(|(_, i)| i)
+            .collect::<Vec<_>>();
+        let mut removed = paged
+            .remove_in_region(query)
+            .unwrap()
+            .into_iter()
+            .map(|(_, i)| i)
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+        removed.sort_unstable();
+        assert_eq!(removed, expected);
+    }
+    assert_eq!(paged.len(), tree.len());
+    paged.check_consistency().unwrap();
+
+    let queries = (0..50)
+        .map(|_| {
+            let x = rng.gen_range(-100.0..=1_100.0);
+            let y = rng.gen_range(-100.0..=1_100.0);
+            (x, y)
+        })
+        .collect::<Vec<_>>();
+
+    let check = |paged: &mut PagedRTree<u64>| {
+        for &(x, y) in &queries {
+            let query = Rect::new((x, y), (x + 30.0, y + 30.0));
+
+            let mut hits = paged
+                .region_intersection_lookup(query)
+                .unwrap()
+                .into_iter()
+                .map(|(_, i)| i)
+                .collect::<Vec<_>>();
+            let mut expected = tree
+                .region_intersection_lookup(query)
+                .into_iter()
+                .map(|id| *tree.get(id).unwrap().1)
+                .collect::<Vec<_>>();
+            hits.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(hits, expected);
+
+            // Ties may be broken differently, so only compare distances.
+            let distances = paged
+                .nearest_neighbors((x, y), 10, Euclidean)
+                .unwrap()
+                .into_iter()
+                .map(|(_, _, distance)| distance)
+                .collect::<Vec<_>>();
+            let expected = tree
+                .nearest_neighbors((x, y), 10, Euclidean)
+                .into_iter()
+                .map(|(_, distance)| distance)
+                .collect::<Vec<_>>();
+            assert_eq!(distances, expected);
+
+            assert_eq!(
+                paged
+                    .within_distance((x, y), 25.0, Chebyshev)
+                    .unwrap()
+                    .len(),
+                tree.within_distance((x, y), 25.0, Chebyshev).len()
+            );
+        }
+    };
+    check(&mut paged);
+
+    // Removed pages are reused rather than growing the file.
+    paged.sync().unwrap();
+    let file_length = std::fs::metadata(&path).unwrap().len();
+    for i in 0..200u64 {
+        let region = Rect::new((i as f64, 0.0), (i as f64 + 1.0, 1.0));
+        paged.insert(region, 10_000 + i).unwrap();
+        assert!(paged.remove(region, &(10_000 + i)).unwrap());
+    }
+    paged.sync().unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), file_length);
+
+    drop(paged);
+    let mut paged = PagedRTree::<u64>::open(&path, 3).unwrap();
+    assert_eq!(paged.len(), tree.len());
+    paged.check_consistency().unwrap();
+    check(&mut paged);
+
+    // Removing everything leaves an empty tree which can be used again.
+    assert_eq!(
+        paged
+            .remove_in_region(Rect::new((-1.0, -1.0), (2_000.0, 2_000.0)))
+            .unwrap()
+            .len(),
+        tree.len()
+    );
+    assert!(paged.is_empty());
+    paged.check_consistency().unwrap();
+    paged.insert(Rect::new((0.0, 0.0), (1.0, 1.0)), 1).unwrap();
+    assert_eq!(paged.point_lookup((0.5, 0.5)).unwrap().len(), 1);
+
+    drop(paged);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_paged_tree_survives_write_errors() {
+    let path = std::env::temp_dir().join(format!(
+        "spaceindex-paged-errors-{}.idx",
+        std::process::id()
+    ));
+
+    let mut paged = PagedRTree::create(&path, 2).unwrap();
+    let region = |i: u64| {
+        let x = (i % 40) as f64 * 10.0;
+        let y = (i / 40) as f64 * 10.0;
+        Rect::new((x, y), (x + 5.0, y + 5.0))
+    };
+
+    for i in 0..100 {
+        paged.insert(region(i), i).unwrap();
+    }
+    paged.sync().unwrap();
+
+    // Pages can still be read through a read-only handle, but writing them back fails.  Keep
+    // inserting and removing while every eviction fails, splitting the root along the way.
+    paged.replace_file(std::fs::File::open(&path).unwrap());
+
+    let mut failures = 0;
+    for i in 100..600 {
+        failures += paged.insert(region(i), i).is_err() as usize;
+    }
+    for i in (0..600).step_by(3) {
+        match paged.remove(region(i), &i) {
+            Ok(removed) => assert!(removed),
+            Err(_) => failures += 1,
+        }
+    }
+    assert!(failures > 0);
+    assert!(paged.flush().is_err());
+
+    // Once writes succeed again, nothing has been lost.
+    paged.replace_file(
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap(),
+    );
+    paged.sync().unwrap();
+    drop(paged);
+
+    let mut paged = PagedRTree::<u64>::open(&path, 2).unwrap();
+    paged.check_consistency().unwrap();
+    assert_eq!(paged.len(), 400);
+    for i in 0..600 {
+        let expected = if i % 3 == 0 {
+            vec![]
+        } else {
+            vec![(region(i), i)]
+        };
+        assert_eq!(paged.region_lookup(region(i)).unwrap(), expected);
+    }
+
+    drop(paged);
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Returns the contents of a map as a sorted list of `(key, corners, data)`.
+fn wal_contents(map: &RTreeMap<u64, u32, f64>) -> Vec<(u64, [f64; 4], u32)> {
+    let mut contents = map
+        .iter()
+        .map(|(&key, region, &data)| {
+            let corners = [
+                region.min().x,
+                region.min().y,
+                region.max().x,
+                region.max().y,
+            ];
+            (key, corners, data)
+        })
+        .collect::<Vec<_>>();
+    contents.sort_by_key(|&(key, _, _)| key);
+    contents
+}
+
+/// Makes a random change to `map`.
+fn random_wal_change(map: &mut WalRTreeMap<u32>, rng: &mut impl Rng) {
+    let key = rng.gen_range(0..50);
+    let x = rng.gen_range(0.0..=100.0);
+    let y = rng.gen_range(0.0..=100.0);
+    let region = Rect::new((x, y), (x + 1.0, y + 1.0));
+
+    match rng.gen_range(0..3) {
+        0 => {
+            map.insert(key, region, rng.gen()).unwrap();
+        }
+        1 => {
+            map.remove(key).unwrap();
+        }
+        _ => {
+            if map.map().contains_key(&key) {
+                map.update_region(key, region).unwrap();
+            } else {
+                assert!(matches!(
+                    map.update_region(key, region),
+                    Err(RTreeError::ItemNotFound)
+                ));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_wal_replays_and_checkpoints() {
+    let directory =
+        std::env::temp_dir().join(format!("spaceindex-wal-replay-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+
+    let mut rng = rand::thread_rng();
+    let mut map = WalRTreeMap::open(&directory, 25).unwrap();
+
+    for round in 0..10 {
+        for _ in 0..40 {
+            random_wal_change(&mut map, &mut rng);
+        }
+        let expected = wal_contents(map.map());
+
+        // Checkpoints keep the log short.
+        let log_length = std::fs::metadata(directory.join("wal")).unwrap().len();
+        assert!(log_length < 25 * 64);
+
+        drop(map);
+        map = WalRTreeMap::open(&directory, 25).unwrap();
+        assert_eq!(wal_contents(map.map()), expected, "round {}", round);
+    }
+
+    // A checkpoint which crashes after renaming the snapshot leaves a stale log behind, which
+    // must not be replayed again.
+    map.checkpoint().unwrap();
+    random_wal_change(&mut map, &mut rng);
+    let stale_log = std::fs::read(directory.join("wal")).unwrap();
+    map.checkpoint().unwrap();
+    let expected = wal_contents(map.map());
+    drop(map);
+
+    std::fs::write(directory.join("wal"), stale_log).unwrap();
+    std::fs::write(directory.join("snapshot.tmp"), b"half a snapshot").unwrap();
+    let map = WalRTreeMap::<u32>::open(&directory, 25).unwrap();
+    assert_eq!(wal_contents(map.map()), expected);
+    assert!(!directory.join("snapshot.tmp").exists());
+    drop(map);
+
+    // A damaged snapshot is an error rather than silently losing data.
+    let mut snapshot = std::fs::read(directory.join("snapshot")).unwrap();
+    let last = snapshot.len() - 1;
+    snapshot[last] ^= 0x01;
+    std::fs::write(directory.join("snapshot"), snapshot).unwrap();
+    assert!(WalRTreeMap::<u32>::open(&directory, 25).is_err());
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_wal_recovers_from_torn_writes() {
+    let directory =
+        std::env::temp_dir().join(format!("spaceindex-wal-torn-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+
+    // Record the contents of the map after every change, along with the length of the log
+    // once that change was logged.  The interval is large enough that no checkpoint is taken.
+    let mut rng = rand::thread_rng();
+    let mut map = WalRTreeMap::open(&directory, 1_000).unwrap();
+    let mut history = vec![(16, Vec::new())];
+    for _ in 0..12 {
+        random_wal_change(&mut map, &mut rng);
+        let log_length = std::fs::metadata(directory.join("wal")).unwrap().len();
+        history.push((log_length, wal_contents(map.map())));
+    }
+    drop(map);
+    let log = std::fs::read(directory.join("wal")).unwrap();
+
+    for length in 0..=log.len() {
+        std::fs::write(directory.join("wal"), &log[..length]).unwrap();
+
+        // The map holds exactly the changes whose records were completely written.
+        let (_, expected) = history
+            .iter()
+            .rev()
+            .find(|(log_length, _)| *log_length <= length.max(16) as u64)
+            .unwrap();
+        let mut map = WalRTreeMap::<u32>::open(&directory, 1_000).unwrap();
+        assert_eq!(&wal_contents(map.map()), expected, "length {}", length);
+
+        // New changes are appended after the last complete record, and survive reopening.
+        // Checking every length is slow, as each change is synced to disk.
+        if length % 8 != 0 {
+            continue;
+        }
+        map.insert(1_000, Rect::new((0.0, 0.0), (1.0, 1.0)), 7)
+            .unwrap();
+        let expected = wal_contents(map.map());
+        drop(map);
+        let map = WalRTreeMap::<u32>::open(&directory, 1_000).unwrap();
+        assert_eq!(wal_contents(map.map()), expected, "length {}", length);
+    }
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_wal_recovers_from_failed_writes() {
+    use std::io::Write;
+
+    let directory =
+        std::env::temp_dir().join(format!("spaceindex-wal-failed-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let log_path = directory.join("wal");
+    let writable = || {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&log_path)
+            .unwrap()
+    };
+    let region = |key: u64| Rect::new((key as f64, 0.0), (key as f64 + 1.0, 1.0));
+
+    let mut map = WalRTreeMap::open(&directory, 1_000).unwrap();
+    for key in 0..5 {
+        map.insert(key, region(key), key as u32).unwrap();
+    }
+
+    // A change which can't be logged isn't made.
+    map.replace_log(std::fs::File::open(&log_path).unwrap());
+    assert!(map.insert(5, region(5), 5).is_err());
+    assert!(!map.map().contains_key(&5));
+
+    // Even if the failed write left part of a record behind, later changes are replayed.
+    let mut log = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&log_path)
+        .unwrap();
+    log.write_all(&[41, 0, 0, 0, 7, 7]).unwrap();
+    drop(log);
+
+    map.replace_log(writable());
+    map.insert(6, region(6), 6).unwrap();
+    map.remove(0).unwrap();
+    let expected = wal_contents(map.map());
+    drop(map);
+
+    let mut map = WalRTreeMap::<u32>::open(&directory, 1_000).unwrap();
+    assert_eq!(wal_contents(map.map()), expected);
+
+    // A checkpoint which writes its snapshot but fails to empty the log leaves a stale log,
+    // which is emptied before later changes are logged.
+    map.replace_log(std::fs::File::open(&log_path).unwrap());
+    assert!(map.checkpoint().is_err());
+    assert!(map.insert(7, region(7), 7).is_err());
+
+    map.replace_log(writable());
+    map.insert(8, region(8), 8).unwrap();
+    map.remove(1).unwrap();
+    let expected = wal_contents(map.map());
+    drop(map);
+
+    let map = WalRTreeMap::<u32>::open(&directory, 1_000).unwrap();
+    assert_eq!(wal_contents(map.map()), expected);
+    assert!(map.map().contains_key(&8));
+    assert!(!map.map().contains_key(&7));
+    drop(map);
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_wal_retries_failed_checkpoints() {
+    let directory =
+        std::env::temp_dir().join(format!("spaceindex-wal-checkpoint-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let region = |key: u64| Rect::new((key as f64, 0.0), (key as f64 + 1.0, 1.0));
+
+    let mut map = WalRTreeMap::open(&directory, 4).unwrap();
+    for key in 0..3 {
+        map.insert(key, region(key), key as u32).unwrap();
+    }
+
+    // A directory in the way of the temporary snapshot makes every checkpoint fail.
+    let blocker = directory.join("snapshot.tmp");
+    std::fs::create_dir(&blocker).unwrap();
+
+    // The changes which trigger a checkpoint still succeed, as they are already in the log.
+    assert_eq!(map.insert(0, region(10), 10).unwrap(), Some((region(0), 0)));
+    assert!(map.pending_checkpoint_error().is_some());
+    assert_eq!(map.remove(1).unwrap(), Some((region(1), 1)));
+    map.update_region(2, region(12)).unwrap();
+    assert!(map.pending_checkpoint_error().is_some());
+    assert!(map.checkpoint().is_err());
+
+    // Once the checkpoint can succeed, the next change retries it.
+    std::fs::remove_dir(&blocker).unwrap();
+    map.insert(3, region(3), 3).unwrap();
+    assert!(map.pending_checkpoint_error().is_none());
+    assert_eq!(std::fs::metadata(directory.join("wal")).unwrap().len(), 16);
+
+    let expected = wal_contents(map.map());
+    drop(map);
+
+    let map = WalRTreeMap::<u32>::open(&directory, 4).unwrap();
+    assert_eq!(wal_contents(map.map()), expected);
+    assert_eq!(map.len(), 3);
+    drop(map);
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(feature = "geojson")]
+#[test]
+fn test_geojson_round_trip() {
+    let document = r#"{
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [3.0, 4.0] },
+                "properties": { "name": "well" }
+            },
+            {
+                "type": "Feature",
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[0.0, 0.0], [5.0, 1.0], [2.0, 6.0], [0.0, 0.0]]]
+                },
+                "properties": null
+            }
+        ]
+    }"#;
+
+    let tree = RTree::from_geojson(document.as_bytes()).unwrap();
+    tree.validate_consistency();
+    assert_eq!(tree.len(), 2);
+    assert_eq!(tree.point_lookup((3.0, 4.0)).len(), 2);
+    assert_eq!(
+        tree.region_lookup(Rect::new((0.0, 0.0), (5.0, 6.0))).len(),
+        1
+    );
+
+    // Exporting and reimporting gives back the same regions and properties.
+    let exported = tree.to_geojson(false).unwrap().to_string();
+    let restored = RTree::from_geojson(exported.as_bytes()).unwrap();
+    assert!(tree == restored);
+
+    // Node regions are tagged with their depth.
+    let tree = random_tree(500);
+    let collection = tree.to_geojson(true).unwrap();
+    let depths = collection
+        .features
+        .iter()
+        .filter_map(|feature| feature.property("depth")?.as_u64())
+        .collect::<Vec<_>>();
+    assert_eq!(depths.len() + tree.len(), collection.features.len());
+    assert_eq!(depths.iter().filter(|&&depth| depth == 0).count(), 1);
+
+    // Data which isn't an object is stored under `data`.
+    assert!(collection
+        .features
+        .iter()
+        .any(|feature| feature.property("data") == Some(&serde_json::json!(0))));
+
+    // Features must have a non-empty geometry.
+    let unlocated = r#"{ "type": "Feature", "geometry": null, "properties": {} }"#;
+    assert!(matches!(
+        RTree::from_geojson(unlocated.as_bytes()),
+        Err(RTreeError::GeoJson(_))
+    ));
+    let empty = r#"{ "type": "GeometryCollection", "geometries": [] }"#;
+    assert!(RTree::from_geojson(empty.as_bytes()).is_err());
+    assert!(RTree::from_geojson("{ not json".as_bytes()).is_err());
+}
+
+#[cfg(feature = "wkt")]
+#[test]
+fn test_wkt_and_wkb_round_trip() {
+    use crate::io::wkt::{read_wkb, write_wkb};
+
+    let wkts = [
+        "POINT(1 2)",
+        "LINESTRING(0 0,4 3,5 -1)",
+        "POLYGON((0 0,10 0,10 10,0 0),(1 1,2 1,2 2,1 1))",
+        "MULTIPOINT((3 3),(7 8))",
+        "MULTILINESTRING((0 0,1 1),(2 2,3 5))",
+        "MULTIPOLYGON(((0 0,1 0,1 1,0 0)),((5 5,6 5,6 6,5 5)))",
+        "GEOMETRYCOLLECTION(POINT(9 9),LINESTRING(8 8,9 7))",
+    ];
+
+    let tree = RTree::from_wkt(wkts).unwrap();
+    tree.validate_consistency();
+    assert_eq!(tree.len(), wkts.len());
+    assert_eq!(tree.point_lookup((5.5, 5.5)).len(), 3);
+
+    // Dumping as WKT gives back the original strings.
+    let mut dumped = tree.wkt_iter().map(|(_, wkt)| wkt).collect::<Vec<_>>();
+    dumped.sort();
+    let mut expected = wkts.map(String::from).to_vec();
+    expected.sort();
+    assert_eq!(dumped, expected);
+
+    // Dumping as WKB and reading back gives the same tree.
+    let wkbs = tree.wkb_iter().map(|(_, wkb)| wkb).collect::<Vec<_>>();
+    let restored = RTree::from_wkb(&wkbs).unwrap();
+    assert!(tree == restored);
+
+    // Every truncation of a WKB geometry is rejected.
+    for wkb in &wkbs {
+        for length in 0..wkb.len() {
+            assert!(read_wkb(&wkb[..length]).is_err());
+        }
+        let mut extended = wkb.clone();
+        extended.push(0);
+        assert!(read_wkb(&extended).is_err());
+    }
+
+    // Big-endian and PostGIS extended WKB are read too.
+    let big_endian = [
+        &[0, 0, 0, 0, 1][..],
+        &1.5f64.to_be_bytes(),
+        &(-2.0f64).to_be_bytes(),
+    ]
+    .concat();
+    let extended = [
+        &[1, 1, 0, 0, 0x20][..],
+        &4326u32.to_le_bytes(),
+        &1.5f64.to_le_bytes(),
+        &(-2.0f64).to_le_bytes(),
+    ]
+    .concat();
+    let point = geo_types::Geometry::Point(point!(x: 1.5, y: -2.0));
+    assert_eq!(read_wkb(&big_endian).unwrap(), point);
+    assert_eq!(read_wkb(&extended).unwrap(), point);
+
+    let mut tree = RTree::new();
+    tree.insert_wkb(&big_endian).unwrap();
+    tree.insert_wkt("LINESTRING(0 0,1 1)").unwrap();
+    assert_eq!(tree.point_lookup((1.5, -2.0)).len(), 1);
+
+    // Unsupported, malformed and empty geometries are rejected.
+    assert!(matches!(
+        read_wkb(&[1, 0xe9, 3, 0, 0]),
+        Err(RTreeError::Wkb(_))
+    ));
+    assert!(read_wkb(&[2, 1, 0, 0, 0]).is_err());
+    assert!(read_wkb(&[1, 4, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]).is_err());
+    assert!(matches!(
+        tree.insert_wkt("POINT(1"),
+        Err(RTreeError::Wkt(_))
+    ));
+    assert!(tree.insert_wkt("GEOMETRYCOLLECTION EMPTY").is_err());
+
+    let mut empty = Vec::new();
+    write_wkb(
+        &geo_types::Geometry::LineString(geo_types::LineString(Vec::new())),
+        &mut empty,
+    );
+    assert!(tree.insert_wkb(&empty).is_err());
+
+    // Deeply nested collections are rejected rather than overflowing the stack.
+    let nested = [1, 7, 0, 0, 0, 1, 0, 0, 0].repeat(10_000);
+    assert!(read_wkb(&nested).is_err());
+    assert_eq!(tree.len(), 2);
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_csv_load() {
+    use crate::io::csv::{load, Columns};
+
+    // Random boxes load into the same tree as a direct bulk load.
+    let tree = random_tree(1_000);
+    let mut data = String::from("id,minx,miny,maxx,maxy\n");
+    for (_, region, id) in tree.iter() {
+        let (min, max) = (region.min(), region.max());
+        data += &format!("{},{:?},{:?},{:?},{:?}\n", id, min.x, min.y, max.x, max.y);
+    }
+    let loaded = load(data.as_bytes(), &Columns::bounds()).unwrap();
+    loaded.validate_consistency();
+    let expected = tree
+        .iter()
+        .map(|(_, region, id)| (*region, vec![id.to_string()]))
+        .collect::<RTree<_, _>>();
+    assert!(loaded == expected);
+
+    // Points, with custom column names and payload columns either side.
+    let data = "name,lon,lat,kind\n\"well, old\",3.5,4,water\npump,-1,2e1,water\n";
+    let columns = Columns::Point {
+        x: String::from("lon"),
+        y: String::from("lat"),
+    };
+    let tree = load(data.as_bytes(), &columns).unwrap();
+    assert_eq!(tree.len(), 2);
+    let (region, data) = tree.get(tree.point_lookup((-1.0, 20.0))[0]).unwrap();
+    assert_eq!(*region, Rect::new((-1.0, 20.0), (-1.0, 20.0)));
+    assert_eq!(data, &vec![String::from("pump"), String::from("water")]);
+    assert_eq!(
+        tree.get(tree.point_lookup((3.5, 4.0))[0]).unwrap().1[0],
+        "well, old"
+    );
+
+    // Malformed input is reported with its line.
+    let line = |data: &str, columns: &Columns| match load(data.as_bytes(), columns) {
+        Err(RTreeError::Csv { line, .. }) => line,
+        result => panic!(
+            "expected a CSV error, got {:?}",
+            result.map(|tree| tree.len())
+        ),
+    };
+    assert_eq!(line("minx,miny,maxx\n0,0,1\n", &Columns::bounds()), 1);
+    assert_eq!(line("x,y\n0,0\n1,one\n", &Columns::point()), 3);
+    assert_eq!(
+        line(
+            "x,y,note\n0,0,ok\n1,1,\"a\nb\"\n2,NaN,bad\n",
+            &Columns::point()
+        ),
+        5
+    );
+    assert_eq!(line("x,y\n0,0\n1,2,3\n", &Columns::point()), 3);
+    assert_eq!(line("x,y\n0,inf\n", &Columns::point()), 2);
+
+    // An empty file with just a header gives an empty tree.
+    assert!(load("x,y\n".as_bytes(), &Columns::point())
+        .unwrap()
+        .is_empty());
+}
+
+#[cfg(feature = "flatgeobuf")]
+#[test]
+fn test_flatgeobuf_index() {
+    use crate::io::flatgeobuf::{hilbert, index_length, PackedHilbertIndex, DEFAULT_NODE_SIZE};
+
+    // The first 16 positions along the curve fill the 4x4 square at its start, one step at a time.
+    let mut square = (0..4)
+        .flat_map(|x| (0..4).map(move |y| (hilbert(x, y), (x, y))))
+        .collect::<Vec<_>>();
+    square.sort_unstable();
+    assert_eq!(
+        square.iter().map(|(d, _)| *d).collect::<Vec<_>>(),
+        (0..16).collect::<Vec<_>>()
+    );
+    for pair in square.windows(2) {
+        let ((_, (x0, y0)), (_, (x1, y1))) = (pair[0], pair[1]);
+        assert_eq!(x0.abs_diff(x1) + y0.abs_diff(y1), 1);
+    }
+
+    // Sizes match those FlatGeobuf computes.
+    assert_eq!(index_length(0, 16), 0);
+    assert_eq!(index_length(1, 16), 2 * 40);
+    assert_eq!(index_length(20, 16), 23 * 40);
+    assert_eq!(index_length(256, 16), 273 * 40);
+    assert_eq!(index_length(257, 16), (257 + 17 + 2 + 1) * 40);
+
+    for node_size in [2, DEFAULT_NODE_SIZE] {
+        let tree = random_tree(2_000);
+        let mut bytes = Vec::new();
+        let order = tree
+            .write_flatgeobuf_index(&mut bytes, node_size, |_, &data| data as u64 + 1)
+            .unwrap();
+        assert_eq!(bytes.len(), index_length(tree.len(), node_size));
+        assert_eq!(order.len(), tree.len());
+
+        let index = PackedHilbertIndex::new(bytes.as_slice(), tree.len(), node_size).unwrap();
+        assert_eq!(index.extent(), Some(tree.root_node().get_region()));
+
+        // Offsets are the running total of the feature lengths, in Hilbert order.
+        let offsets = order
+            .iter()
+            .scan(0, |offset, &id| {
+                let current = *offset;
+                *offset += *tree.get(id).unwrap().1 as u64 + 1;
+                Some(current)
+            })
+            .collect::<Vec<_>>();
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let x = rng.gen_range(0.0..=1_000.0);
+            let y = rng.gen_range(0.0..=1_000.0);
+            let query = Rect::new((x, y), (x + 30.0, y + 30.0));
+
+            let hits = index.region_intersection_lookup(query);
+            for hit in &hits {
+                assert_eq!(hit.offset, offsets[hit.index]);
+            }
+
+            let mut found = hits.iter().map(|hit| order[hit.index]).collect::<Vec<_>>();
+            let mut expected = tree.region_intersection_lookup(query);
+            found.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(found, expected);
+
+            assert_eq!(
+                index.point_lookup(x, y).len(),
+                tree.point_lookup((x, y)).len()
+            );
+        }
+
+        // Damaged indexes give wrong answers, but never panic.
+        let mut damaged = bytes.clone();
+        for (i, byte) in damaged.iter_mut().enumerate() {
+            *byte ^= (i % 251) as u8;
+        }
+        let index = PackedHilbertIndex::new(damaged, tree.len(), node_size).unwrap();
+        index.region_intersection_lookup(Rect::new((0.0, 0.0), (1_000.0, 1_000.0)));
+
+        assert!(PackedHilbertIndex::new(&bytes[1..], tree.len(), node_size).is_err());
+        assert!(PackedHilbertIndex::new(&bytes, tree.len() + 1, node_size).is_err());
+        assert!(PackedHilbertIndex::new(&bytes, usize::MAX, node_size).is_err());
+        assert!(PackedHilbertIndex::new(&bytes, tree.len(), 1).is_err());
+    }
+
+    // Empty trees have an empty index.
+    let tree = RTree::<u32, f64>::new();
+    let mut bytes = Vec::new();
+    assert!(tree
+        .write_flatgeobuf_index(&mut bytes, DEFAULT_NODE_SIZE, |_, _| 0)
+        .unwrap()
+        .is_empty());
+    let index = PackedHilbertIndex::new(bytes, 0, DEFAULT_NODE_SIZE).unwrap();
+    assert!(index.is_empty());
+    assert_eq!(index.extent(), None);
+    assert!(index.point_lookup(0.0, 0.0).is_empty());
+}
@@ -0,0 +1,594 @@
+//! A write-ahead log giving durability to incremental changes of an [`RTreeMap`].
+//!
+//! A [`WalRTreeMap`] keeps its state in a directory holding two files: a snapshot of every
+//! element of the map, and a log of the changes made since that snapshot was taken.  Every
+//! change is appended to the log, and synced to disk, before it is applied to the map.
+//! Opening the directory loads the snapshot and replays the log over it.
+//!
+//! Once enough changes have been logged, a checkpoint writes a new snapshot to a temporary
+//! file, syncs it, renames it over the old snapshot and then empties the log.  A crash at any
+//! point leaves either the old snapshot with its log, or the new snapshot with a log which is
+//! recognizably stale, so no change is lost or applied twice.  A checkpoint which fails
+//! doesn't fail the change which triggered it, as that change is already durable in the log.
+//! Instead the checkpoint is retried after each later change until it succeeds, and the error
+//! is kept for [`WalRTreeMap::pending_checkpoint_error`].
+//!
+//! All integers and floats are little-endian.  Both files start with a 16 byte header: eight
+//! magic bytes (`SPCSNAPS` for the snapshot, `SPCWALOG` for the log) followed by the
+//! generation of the snapshot (a `u64`), which is incremented by every checkpoint.  The rest of
+//! each file is a sequence of records, each stored as the length of its body (a `u32`), the
+//! CRC-32 of its body (a `u32`), then the body itself:
+//!
+//! - An operation byte: `0` to insert, `1` to remove, or `2` to update the region of an element,
+//! - The key of the element (a `u64`),
+//! - For inserts and updates, the region of the element as `min x, min y, max x, max y`, and
+//! - For inserts, the data of the element as written by its [`Encode`] implementation.
+//!
+//! The snapshot holds one insert record for every element of the map.
+//!
+//! A crash while appending to the log can leave an incomplete record at its end.  Replay stops
+//! at the first record which is incomplete or fails its checksum, and the log is truncated
+//! there so that new records are appended after the last complete one.  A write to the log
+//! which fails without a crash is handled in the same way: the log is truncated back to its
+//! last complete record, and if that fails too, nothing more is appended until it succeeds.
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use geo_types::Rect;
+
+use crate::rtree::binary::{put_region, Decode, Encode};
+use crate::rtree::{RTreeError, RTreeMap};
+
+/// The magic bytes at the start of the snapshot.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"SPCSNAPS";
+
+/// The magic bytes at the start of the log.
+const LOG_MAGIC: &[u8; 8] = b"SPCWALOG";
+
+/// The length of the header of both files.
+const HEADER_LENGTH: usize = 16;
+
+/// The length of the length and checksum stored before the body of each record.
+const RECORD_HEADER_LENGTH: usize = 8;
+
+/// The names of the files within the directory.
+const SNAPSHOT_NAME: &str = "snapshot";
+const SNAPSHOT_TEMP_NAME: &str = "snapshot.tmp";
+const LOG_NAME: &str = "wal";
+
+/// Operation bytes identifying each kind of record.
+const OP_INSERT: u8 = 0;
+const OP_REMOVE: u8 = 1;
+const OP_UPDATE_REGION: u8 = 2;
+
+/// A change to a map, as stored in a record.
+enum Record<ND> {
+    Insert(u64, Rect<f64>, ND),
+    Remove(u64),
+    UpdateRegion(u64, Rect<f64>),
+}
+
+/// An [`RTreeMap`] with `u64` keys whose changes are made durable by a write-ahead log.
+///
+/// Queries go through [`WalRTreeMap::map`]; changes go through the methods of this type, which
+/// log each change before applying it.
+///
+/// # Example
+/// ```rust
+/// use spaceindex::Rect;
+/// use spaceindex::rtree::wal::WalRTreeMap;
+///
+/// let directory = std::env::temp_dir().join(format!("wal-doc-{}", std::process::id()));
+///
+/// // Checkpoint after every 100 changes.
+/// let mut map = WalRTreeMap::open(&directory, 100).unwrap();
+/// map.insert(1, Rect::new((0.0, 0.0), (2.0, 2.0)), 3u32).unwrap();
+/// map.insert(2, Rect::new((5.0, 0.0), (6.0, 1.0)), 1u32).unwrap();
+/// map.remove(1).unwrap();
+/// drop(map);
+///
+/// let map = WalRTreeMap::<u32>::open(&directory, 100).unwrap();
+/// assert_eq!(map.map().point_lookup((5.5, 0.5)), vec![&2]);
+/// assert!(map.map().point_lookup((1.0, 1.0)).is_empty());
+/// # std::fs::remove_dir_all(directory).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct WalRTreeMap<ND>
+where
+    ND: Encode + Decode,
+{
+    map: RTreeMap<u64, ND, f64>,
+
+    /// The directory holding the snapshot and the log.
+    directory: PathBuf,
+
+    /// The log, positioned at its end unless `log_damaged` is set.
+    log: File,
+
+    /// The length of the log up to the end of its last complete record, or zero if the log
+    /// doesn't yet have a header for the current generation.
+    log_length: u64,
+
+    /// Whether a failed write may have left the log longer than `log_length`, or positioned
+    /// elsewhere.  The log is repaired before anything more is appended to it.
+    log_damaged: bool,
+
+    /// The generation of the current snapshot.
+    generation: u64,
+
+    /// The number of records in the log.
+    records: usize,
+
+    /// A checkpoint is taken once the log holds this many records.
+    checkpoint_interval: usize,
+
+    /// The error from the last checkpoint, if it failed.
+    checkpoint_error: Option<RTreeError>,
+}
+
+impl<ND> WalRTreeMap<ND>
+where
+    ND: Encode + Decode,
+{
+    /// Opens the map stored in `directory`, creating an empty map if the directory doesn't
+    /// hold one yet.  A checkpoint is taken after every `checkpoint_interval` changes.
+    ///
+    /// # Panics
+    /// This function will panic if `checkpoint_interval` is zero.
+    ///
+    /// # Errors
+    /// This function will return an error if the snapshot or log can't be read, if the
+    /// snapshot is damaged, or if the log describes changes which can't be applied.  An
+    /// incomplete or damaged record at the end of the log is not an error: the log is
+    /// truncated to end at the last complete record instead.
+    pub fn open<P: AsRef<Path>>(
+        directory: P,
+        checkpoint_interval: usize,
+    ) -> Result<Self, RTreeError> {
+        assert!(checkpoint_interval > 0);
+
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        // A leftover temporary snapshot is from a checkpoint which never completed.
+        match fs::remove_file(directory.join(SNAPSHOT_TEMP_NAME)) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+
+        let mut map = RTreeMap::new();
+        let generation = match fs::read(directory.join(SNAPSHOT_NAME)) {
+            Ok(bytes) => load_snapshot(&bytes, &mut map)?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(directory.join(LOG_NAME))?;
+
+        let mut bytes = Vec::new();
+        log.read_to_end(&mut bytes)?;
+
+        let mut wal = Self {
+            map,
+            directory,
+            log,
+            log_length: 0,
+            log_damaged: false,
+            generation,
+            records: 0,
+            checkpoint_interval,
+            checkpoint_error: None,
+        };
+
+        if bytes.len() < HEADER_LENGTH {
+            // The log was never completely created.
+            wal.reset_log()?;
+            return Ok(wal);
+        }
+        if &bytes[..LOG_MAGIC.len()] != LOG_MAGIC {
+            return Err(RTreeError::BadMagic);
+        }
+
+        let log_generation = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        if log_generation < generation {
+            // The log was already folded into the snapshot by a checkpoint which crashed
+            // before it could empty the log.
+            wal.reset_log()?;
+            return Ok(wal);
+        }
+        if log_generation > generation {
+            return Err(RTreeError::Corrupt("log is newer than its snapshot"));
+        }
+
+        let mut offset = HEADER_LENGTH;
+        while let Some((body, next_offset)) = next_record(&bytes, offset) {
+            apply(&mut wal.map, decode_record(body)?)?;
+            wal.records += 1;
+            offset = next_offset;
+        }
+
+        // Drop any torn record at the end, so new records follow the last complete one.
+        if offset < bytes.len() {
+            wal.log.set_len(offset as u64)?;
+            wal.log.sync_data()?;
+        }
+        wal.log.seek(SeekFrom::Start(offset as u64))?;
+        wal.log_length = offset as u64;
+
+        Ok(wal)
+    }
+
+    /// Returns a reference to the underlying map, for running queries.
+    #[inline(always)]
+    pub fn map(&self) -> &RTreeMap<u64, ND, f64> {
+        &self.map
+    }
+
+    /// Returns the number of elements in the map.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements, `false` otherwise.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Inserts an element with key `key` into the map, replacing any existing element with the
+    /// same key.  The region and data of the replaced element are returned.
+    ///
+    /// # Errors
+    /// This function will return an error if the change can't be logged, or if the underlying
+    /// map fails to insert `region`.  A failed checkpoint is not an error; see
+    /// [`WalRTreeMap::pending_checkpoint_error`].
+    pub fn insert(
+        &mut self,
+        key: u64,
+        region: Rect<f64>,
+        data: ND,
+    ) -> Result<Option<(Rect<f64>, ND)>, RTreeError> {
+        let mut body = vec![OP_INSERT];
+        body.extend_from_slice(&key.to_le_bytes());
+        put_region(&mut body, region);
+        data.encode(&mut body);
+        self.append(&body)?;
+
+        let replaced = self.map.insert(key, region, data)?;
+        self.checkpoint_if_due();
+
+        Ok(replaced)
+    }
+
+    /// Removes the element with key `key` from the map, returning its region and data.
+    /// Nothing is logged if there is no such element.
+    ///
+    /// # Errors
+    /// This function will return an error if the change can't be logged.  A failed checkpoint
+    /// is not an error; see [`WalRTreeMap::pending_checkpoint_error`].
+    pub fn remove(&mut self, key: u64) -> Result<Option<(Rect<f64>, ND)>, RTreeError> {
+        if !self.map.contains_key(&key) {
+            return Ok(None);
+        }
+
+        let mut body = vec![OP_REMOVE];
+        body.extend_from_slice(&key.to_le_bytes());
+        self.append(&body)?;
+
+        let removed = self.map.remove(&key);
+        self.checkpoint_if_due();
+
+        Ok(removed)
+    }
+
+    /// Moves the element with key `key` to the region `region`.
+    ///
+    /// # Errors
+    /// This function will return an error if there is no element with key `key`, if the change
+    /// can't be logged, or if the underlying map fails to insert `region`.  A failed checkpoint
+    /// is not an error; see [`WalRTreeMap::pending_checkpoint_error`].
+    pub fn update_region(&mut self, key: u64, region: Rect<f64>) -> Result<(), RTreeError> {
+        if !self.map.contains_key(&key) {
+            return Err(RTreeError::ItemNotFound);
+        }
+
+        let mut body = vec![OP_UPDATE_REGION];
+        body.extend_from_slice(&key.to_le_bytes());
+        put_region(&mut body, region);
+        self.append(&body)?;
+
+        self.map.update_region(&key, region)?;
+        self.checkpoint_if_due();
+
+        Ok(())
+    }
+
+    /// Writes every element of the map to a new snapshot, and empties the log.
+    ///
+    /// # Errors
+    /// This function will return an error if writing the snapshot or the log fails.  The
+    /// directory still holds a consistent state of the map in that case.
+    pub fn checkpoint(&mut self) -> Result<(), RTreeError> {
+        self.write_checkpoint()?;
+        self.checkpoint_error = None;
+
+        Ok(())
+    }
+
+    /// Returns the error from the last checkpoint, if it failed.  Checkpoints triggered by a
+    /// change are retried after every later change, and the error is cleared once one
+    /// succeeds.  Until then the log keeps growing, but no change is lost.
+    ///
+    /// # Example
+    /// ```rust
+    /// use spaceindex::Rect;
+    /// use spaceindex::rtree::wal::WalRTreeMap;
+    ///
+    /// let directory = std::env::temp_dir().join(format!("wal-pending-{}", std::process::id()));
+    /// let mut map = WalRTreeMap::open(&directory, 1).unwrap();
+    ///
+    /// map.insert(1, Rect::new((0.0, 0.0), (1.0, 1.0)), 7u32).unwrap();
+    /// if let Some(error) = map.pending_checkpoint_error() {
+    ///     eprintln!("the log can't be compacted yet: {}", error);
+    /// }
+    /// # std::fs::remove_dir_all(directory).unwrap();
+    /// ```
+    #[inline(always)]
+    pub fn pending_checkpoint_error(&self) -> Option<&RTreeError> {
+        self.checkpoint_error.as_ref()
+    }
+
+    /// Writes a new snapshot and empties the log, as for [`WalRTreeMap::checkpoint`].
+    fn write_checkpoint(&mut self) -> Result<(), RTreeError> {
+        let generation = self.generation + 1;
+        let temp_path = self.directory.join(SNAPSHOT_TEMP_NAME);
+
+        {
+            let file = File::create(&temp_path)?;
+            let mut writer = BufWriter::new(file);
+            writer.write_all(SNAPSHOT_MAGIC)?;
+            writer.write_all(&generation.to_le_bytes())?;
+
+            let mut body = Vec::new();
+            for (key, region, data) in self.map.iter() {
+                body.clear();
+                body.push(OP_INSERT);
+                body.extend_from_slice(&key.to_le_bytes());
+                put_region(&mut body, *region);
+                data.encode(&mut body);
+                writer.write_all(&frame(&body)?)?;
+            }
+
+            let file = writer.into_inner().map_err(|error| error.into_error())?;
+            file.sync_all()?;
+        }
+
+        fs::rename(&temp_path, self.directory.join(SNAPSHOT_NAME))?;
+        sync_directory(&self.directory)?;
+
+        // The new snapshot is in place, so the old log is stale.  If emptying it fails, opening
+        // the directory still discards it as it has an older generation, and it is emptied
+        // before anything more is appended to it.
+        self.generation = generation;
+        self.reset_log()
+    }
+
+    /// Takes a checkpoint if the log has grown long enough, keeping any error for
+    /// [`WalRTreeMap::pending_checkpoint_error`].  The change which triggered the checkpoint is
+    /// already in the log, so it is durable either way.
+    fn checkpoint_if_due(&mut self) {
+        if self.records >= self.checkpoint_interval {
+            self.checkpoint_error = self.write_checkpoint().err();
+        }
+    }
+
+    /// Appends a record with body `body` to the log, and syncs it to disk.
+    ///
+    /// If this fails, the log is truncated back to its last complete record, so that a partly
+    /// written record can't hide the records appended after it from replay.
+    fn append(&mut self, body: &[u8]) -> Result<(), RTreeError> {
+        let record = frame(body)?;
+
+        if self.log_damaged {
+            self.repair_log()?;
+        }
+
+        self.log_damaged = true;
+        if let Err(error) = self.write_record(&record) {
+            // If this fails too, the log is repaired before the next append instead.
+            let _ = self.repair_log();
+            return Err(error);
+        }
+        self.log_damaged = false;
+
+        self.log_length += record.len() as u64;
+        self.records += 1;
+
+        Ok(())
+    }
+
+    /// Writes `record` at the current position of the log, and syncs it to disk.
+    fn write_record(&mut self, record: &[u8]) -> Result<(), RTreeError> {
+        self.log.write_all(record)?;
+        self.log.sync_data()?;
+
+        Ok(())
+    }
+
+    /// Truncates the log back to its last complete record, or empties it if it doesn't have a
+    /// header for the current generation.
+    fn repair_log(&mut self) -> Result<(), RTreeError> {
+        if self.log_length == 0 {
+            return self.reset_log();
+        }
+
+        self.log.set_len(self.log_length)?;
+        self.log.seek(SeekFrom::Start(self.log_length))?;
+        self.log.sync_data()?;
+        self.log_damaged = false;
+
+        Ok(())
+    }
+
+    /// Replaces the log with an empty log for the current generation.
+    fn reset_log(&mut self) -> Result<(), RTreeError> {
+        self.records = 0;
+        self.log_length = 0;
+        self.log_damaged = true;
+
+        self.log.set_len(0)?;
+        self.log.seek(SeekFrom::Start(0))?;
+        self.log.write_all(LOG_MAGIC)?;
+        self.log.write_all(&self.generation.to_le_bytes())?;
+        self.log.sync_all()?;
+
+        self.log_length = HEADER_LENGTH as u64;
+        self.log_damaged = false;
+
+        Ok(())
+    }
+
+    /// Replaces the handle used to write the log, so that tests can make writes fail.  The
+    /// position of the new handle is unknown, so the log is repaired before the next append.
+    #[cfg(test)]
+    pub(crate) fn replace_log(&mut self, log: File) {
+        self.log = log;
+        self.log_damaged = true;
+    }
+}
+
+/// Loads the snapshot `bytes` into the empty map `map`, returning the generation of the
+/// snapshot.  Snapshots are only ever renamed into place once complete, so any damage is an
+/// error.
+fn load_snapshot<ND: Decode>(
+    bytes: &[u8],
+    map: &mut RTreeMap<u64, ND, f64>,
+) -> Result<u64, RTreeError> {
+    if bytes.len() < SNAPSHOT_MAGIC.len() {
+        return Err(RTreeError::Truncated);
+    }
+    if &bytes[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+        return Err(RTreeError::BadMagic);
+    }
+    if bytes.len() < HEADER_LENGTH {
+        return Err(RTreeError::Truncated);
+    }
+
+    let generation = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+
+    let mut offset = HEADER_LENGTH;
+    while let Some((body, next_offset)) = next_record(bytes, offset) {
+        match decode_record(body)? {
+            record @ Record::Insert(..) => apply(map, record)?,
+            _ => return Err(RTreeError::Corrupt("snapshot contains a non-insert record")),
+        }
+        offset = next_offset;
+    }
+
+    if offset < bytes.len() {
+        return Err(RTreeError::ChecksumMismatch("snapshot"));
+    }
+
+    Ok(generation)
+}
+
+/// Frames `body` as a record, prefixing it with its length and checksum.
+fn frame(body: &[u8]) -> Result<Vec<u8>, RTreeError> {
+    let length = u32::try_from(body.len()).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "record is too large")
+    })?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_LENGTH + body.len());
+    record.extend_from_slice(&length.to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(body).to_le_bytes());
+    record.extend_from_slice(body);
+
+    Ok(record)
+}
+
+/// Returns the body of the record starting at `offset` in `bytes` along with the offset of the
+/// next record, or `None` if there is no complete, undamaged record at `offset`.
+fn next_record(bytes: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let header = bytes.get(offset..offset.checked_add(RECORD_HEADER_LENGTH)?)?;
+    let length = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    let start = offset + RECORD_HEADER_LENGTH;
+    let end = start.checked_add(length)?;
+    let body = bytes.get(start..end)?;
+
+    (crc32fast::hash(body) == checksum).then_some((body, end))
+}
+
+/// Decodes the body of a record whose checksum has already been verified.
+fn decode_record<ND: Decode>(body: &[u8]) -> Result<Record<ND>, RTreeError> {
+    let corrupt = || RTreeError::Corrupt("record is malformed");
+
+    let (&op, rest) = body.split_first().ok_or_else(corrupt)?;
+    let key = u64::from_le_bytes(rest.get(..8).ok_or_else(corrupt)?.try_into().unwrap());
+    let rest = &rest[8..];
+
+    let region = || -> Result<Rect<f64>, RTreeError> {
+        let bytes = rest.get(..32).ok_or_else(corrupt)?;
+        let value =
+            |offset: usize| f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+        Ok(Rect::new((value(0), value(8)), (value(16), value(24))))
+    };
+
+    match op {
+        OP_INSERT => {
+            let region = region()?;
+            let data =
+                ND::decode(&rest[32..]).map_err(|error| RTreeError::Decode(Box::new(error)))?;
+
+            Ok(Record::Insert(key, region, data))
+        }
+        OP_REMOVE if rest.is_empty() => Ok(Record::Remove(key)),
+        OP_UPDATE_REGION if rest.len() == 32 => Ok(Record::UpdateRegion(key, region()?)),
+        _ => Err(corrupt()),
+    }
+}
+
+/// Applies the change `record` to `map`.  Changes are only logged if they apply cleanly, so a
+/// record which doesn't means the log is corrupt.
+fn apply<ND>(map: &mut RTreeMap<u64, ND, f64>, record: Record<ND>) -> Result<(), RTreeError> {
+    match record {
+        Record::Insert(key, region, data) => {
+            map.insert(key, region, data)?;
+        }
+        Record::Remove(key) => {
+            map.remove(&key)
+                .ok_or(RTreeError::Corrupt("log removes a missing element"))?;
+        }
+        Record::UpdateRegion(key, region) => {
+            if !map.contains_key(&key) {
+                return Err(RTreeError::Corrupt("log moves a missing element"));
+            }
+            map.update_region(&key, region)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Syncs the directory `directory`, so that a rename within it is durable.
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> Result<(), RTreeError> {
+    File::open(directory)?.sync_all()?;
+
+    Ok(())
+}
+
+/// Directories can't be synced on this platform; renames are durable once they return.
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> Result<(), RTreeError> {
+    Ok(())
+}